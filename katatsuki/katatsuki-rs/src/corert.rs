@@ -0,0 +1,74 @@
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::ffi::{CStr, CString};
+use libc::c_char;
+use sys::katatsuki_Track;
+use sys::katatsuki_get_track_data;
+
+use chrono::Local;
+
+use track::{Track, TrackFileType};
+use FromPrimitive;
+
+const TICKS_PER_MS: i64 = 10000;
+
+fn ticks_to_ms(ticks: i64) -> i32 {
+    (ticks / TICKS_PER_MS) as i32
+}
+
+fn c_str_to_str(c_str: *const c_char) -> Option<String> {
+    if c_str.is_null() {
+        return None;
+    }
+
+    let bytes = unsafe { CStr::from_ptr(c_str).to_bytes() };
+    if bytes.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(bytes).to_string())
+    }
+}
+
+/// Reads track data through libkatatsuki-sys.
+pub fn read_track(path: &Path, source: Option<&str>) -> Result<Track> {
+    if let Ok(path_ptr) = CString::new(path.as_os_str().to_string_lossy().as_ref()) {
+        let track: katatsuki_Track = unsafe { katatsuki_get_track_data(path_ptr.into_raw()) };
+        if track.FileType == 0 {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("File {:?} is unsupported", path),
+            ))
+        } else {
+            Ok(Track {
+                file_path: path.to_owned(),
+                file_type: TrackFileType::from_u32(track.FileType).unwrap(),
+                title: c_str_to_str(track.Title).unwrap_or("".to_owned()),
+                artist: c_str_to_str(track.Artist).unwrap_or("".to_owned()),
+                album: c_str_to_str(track.Album).unwrap_or("".to_owned()),
+                album_artists: c_str_to_str(track.AlbumArtists)
+                    .unwrap_or("".to_owned())
+                    .split(';')
+                    .map(|c| c.to_owned())
+                    .collect::<Vec<String>>(),
+                year: track.Year as i32,
+                track_number: track.TrackNumber as i32,
+                musicbrainz_track_id: c_str_to_str(track.MusicBrainzTrackId),
+                has_front_cover: track.HasFrontCover,
+                front_cover_width: track.FrontCoverWidth,
+                front_cover_height: track.FrontCoverHeight,
+                bitrate: track.Bitrate,
+                sample_rate: track.SampleRate,
+                source: source.unwrap_or("None").to_owned(),
+                disc_number: track.DiscNumber as i32,
+                disc_count: track.DiscCount as i32,
+                duration: ticks_to_ms(track.Duration),
+                updated: Local::now().format("%Y-%m-%d").to_string(),
+            })
+        }
+    } else {
+        Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Path was invalid."),
+        ))
+    }
+}
@@ -2,43 +2,39 @@
 extern crate enum_primitive_derive;
 
 extern crate chrono;
+#[cfg(feature = "corert")]
 extern crate libc;
 extern crate num_traits;
 
+#[cfg(feature = "corert")]
 extern crate libkatatsuki_sys as sys;
 
 use std::io::{Error, ErrorKind, Result};
 use std::path::Path;
-use std::ffi::{CStr, CString};
-use libc::c_char;
-use sys::katatsuki_Track;
-use sys::katatsuki_get_track_data;
-
-use chrono::Local;
 
 pub use num_traits::{FromPrimitive, ToPrimitive};
 pub use track::Track;
 pub use track::TrackFileType;
 
 mod track;
-const TICKS_PER_MS: i64 = 10000;
 
-fn ticks_to_ms(ticks: i64) -> i32 {
-    (ticks / TICKS_PER_MS) as i32
-}
+// The native backend takes precedence when both backends are enabled.
+// corert still builds libkatatsuki-sys then, so building without .NET
+// needs `--no-default-features --features native`.
+#[cfg(all(feature = "corert", not(feature = "native")))]
+mod corert;
+#[cfg(all(feature = "corert", not(feature = "native")))]
+use corert as backend;
 
-fn c_str_to_str(c_str: *const c_char) -> Option<String> {
-    if c_str.is_null() {
-        return None;
-    }
+// The native module is always built, since it also locates the audio
+// data for `audio_ranges`.
+#[cfg_attr(not(feature = "native"), allow(dead_code))]
+mod native;
+#[cfg(feature = "native")]
+use native as backend;
 
-    let bytes = unsafe { CStr::from_ptr(c_str).to_bytes() };
-    if bytes.is_empty() {
-        None
-    } else {
-        Some(String::from_utf8_lossy(bytes).to_string())
-    }
-}
+#[cfg(not(any(feature = "corert", feature = "native")))]
+compile_error!("katatsuki requires either the `corert` or the `native` feature to be enabled.");
 
 impl Track {
     pub fn from_path(path: &Path, source: Option<&str>) -> Result<Track> {
@@ -48,45 +44,21 @@ impl Track {
                 format!("File {:?} not found.", path),
             ))
         } else {
-            if let Ok(path_ptr) = CString::new(path.as_os_str().to_string_lossy().as_ref()) {
-                let track: katatsuki_Track = unsafe { katatsuki_get_track_data(path_ptr.into_raw()) };
-                if track.FileType == 0 {
-                    Err(Error::new(
-                        ErrorKind::InvalidData,
-                        format!("File {:?} is unsupported", path),
-                    ))
-                } else {
-                    Ok(Track {
-                        file_path: path.to_owned(),
-                        file_type: TrackFileType::from_u32(track.FileType).unwrap(),
-                        title: c_str_to_str(track.Title).unwrap_or("".to_owned()),
-                        artist: c_str_to_str(track.Artist).unwrap_or("".to_owned()),
-                        album: c_str_to_str(track.Album).unwrap_or("".to_owned()),
-                        album_artists: c_str_to_str(track.AlbumArtists)
-                            .unwrap_or("".to_owned())
-                            .split(';')
-                            .map(|c| c.to_owned())
-                            .collect::<Vec<String>>(),
-                        year: track.Year as i32,
-                        track_number: track.TrackNumber as i32,
-                        musicbrainz_track_id: c_str_to_str(track.MusicBrainzTrackId),
-                        has_front_cover: track.HasFrontCover,
-                        front_cover_width: track.FrontCoverWidth,
-                        front_cover_height: track.FrontCoverHeight,
-                        bitrate: track.Bitrate,
-                        sample_rate: track.SampleRate,
-                        source: source.unwrap_or("None").to_owned(),
-                        disc_number: track.DiscNumber as i32,
-                        duration: ticks_to_ms(track.Duration),
-                        updated: Local::now().format("%Y-%m-%d").to_string(),
-                    })
-                }
-            } else {
-                Err(Error::new(
-                    ErrorKind::UnexpectedEof,
-                    format!("Path was invalid."),
-                ))
-            }
+            backend::read_track(path, source)
         }
     }
 }
+
+/// Gets the byte ranges of the audio data in the file at the given path,
+/// as offsets and lengths. Tags and other metadata are left out, so that
+/// the ranges hold the same data after the file is retagged.
+pub fn audio_ranges(path: &Path) -> Result<Vec<(u64, u64)>> {
+    if !path.exists() {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("File {:?} not found.", path),
+        ))
+    } else {
+        native::audio_ranges(path)
+    }
+}
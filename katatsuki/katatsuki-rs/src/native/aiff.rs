@@ -0,0 +1,106 @@
+//! AIFF and AIFF-C files, with tags in an ID3 chunk.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use track::TrackFileType;
+
+use super::id3;
+use super::{be_u16, be_u32, be_u64, invalid, read_vec, stream_len, Properties, Tags};
+
+/// The highest sample rate that an AIFF file is taken to have, in Hz.
+/// Anything higher comes from a corrupt COMM chunk.
+const MAX_SAMPLE_RATE: u64 = 768_000;
+
+/// Converts an 80-bit IEEE 754 extended precision float, which AIFF
+/// uses for the sample rate.
+fn extended_to_f64(b: &[u8]) -> f64 {
+    let exponent = (be_u16(&b[0..2]) & 0x7FFF) as i32;
+    let mantissa = be_u64(&b[2..10]);
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    let value = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    if b[0] & 0x80 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Gets the range of the sound data chunk.
+pub fn audio_ranges<R: Read + Seek>(file: &mut R) -> Result<Vec<(u64, u64)>> {
+    let len = stream_len(file)?;
+    let mut position = 12;
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        let header = read_vec(file, 8)?;
+        let chunk_len = be_u32(&header[4..8]) as u64;
+        if &header[0..4] == b"SSND" {
+            return Ok(vec![(position + 8, chunk_len.min(len - position - 8))]);
+        }
+        position += 8 + chunk_len + (chunk_len & 1);
+    }
+    Err(invalid("AIFF file has no SSND chunk."))
+}
+
+pub fn read<R: Read + Seek>(file: &mut R) -> Result<(Tags, Properties)> {
+    let len = stream_len(file)?;
+    let mut position = 12;
+    let mut tags = Tags::default();
+    let mut common = None;
+
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        let header = read_vec(file, 8)?;
+        let chunk_len = be_u32(&header[4..8]) as u64;
+        match &header[0..4] {
+            b"COMM" => common = Some(read_vec(file, chunk_len)?),
+            b"ID3 " | b"id3 " => if let Some(id3) = id3::parse_tag(&read_vec(file, chunk_len)?) {
+                tags = id3;
+            },
+            _ => (),
+        }
+        // Chunks are padded to an even length.
+        position += 8 + chunk_len + (chunk_len & 1);
+    }
+
+    let common = match common {
+        Some(ref common) if common.len() >= 18 => common,
+        _ => return Err(invalid("AIFF file has no COMM chunk.")),
+    };
+    let channels = be_u16(&common[0..2]) as u64;
+    let sample_frames = be_u32(&common[2..6]) as u64;
+    let bits_per_sample = be_u16(&common[6..8]);
+    let sample_rate = extended_to_f64(&common[8..18]);
+    // Checked as a float first, since a corrupt rate may not fit in an integer.
+    if !(sample_rate >= 1.0 && sample_rate <= MAX_SAMPLE_RATE as f64) {
+        return Err(invalid("AIFF file has an invalid sample rate."));
+    }
+    let sample_rate = sample_rate as u64;
+
+    let duration = (sample_frames * 1000 / sample_rate) as i64;
+    let bitrate = sample_rate
+        .checked_mul(channels)
+        .and_then(|rate| rate.checked_mul(bits_per_sample as u64))
+        .map(|rate| rate / 1000)
+        .unwrap_or(u64::max_value());
+
+    let file_type = match bits_per_sample {
+        4 => TrackFileType::AIFF4,
+        8 => TrackFileType::AIFF8,
+        16 => TrackFileType::AIFF16,
+        24 => TrackFileType::AIFF24,
+        32 => TrackFileType::AIFF32,
+        _ => TrackFileType::AIFF,
+    };
+
+    Ok((
+        tags,
+        Properties {
+            file_type,
+            duration,
+            bitrate: bitrate.min(i32::max_value() as u64) as i32,
+            sample_rate: sample_rate.min(i32::max_value() as u64) as i32,
+        },
+    ))
+}
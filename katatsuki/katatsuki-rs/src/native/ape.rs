@@ -0,0 +1,209 @@
+//! Monkey's Audio files and APEv2 tags.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use track::TrackFileType;
+
+use super::id3;
+use super::picture;
+use super::{average_bitrate, invalid, le_u16, le_u32, parse_number, parse_total, parse_year,
+            read_vec, stream_len, FrontCover, Properties, Tags};
+
+const FOOTER_LEN: u64 = 32;
+const ID3V1_LEN: u64 = 128;
+
+/// Monkey's Audio format flags for files older than 3.98.
+const FLAG_8_BIT: u16 = 1;
+const FLAG_24_BIT: u16 = 8;
+
+/// Gets the position and full length of the APEv2 tag at the end of the
+/// file, which may be followed by an ID3v1 tag.
+pub fn find_tag<R: Read + Seek>(file: &mut R) -> Result<Option<(u64, u64)>> {
+    let len = stream_len(file)?;
+    for &trailing in &[0, ID3V1_LEN] {
+        if len < FOOTER_LEN + trailing {
+            continue;
+        }
+        let footer_position = len - FOOTER_LEN - trailing;
+        file.seek(SeekFrom::Start(footer_position))?;
+        let footer = read_vec(file, FOOTER_LEN)?;
+        if &footer[0..8] != b"APETAGEX" {
+            continue;
+        }
+        // The size includes the footer, but not the optional header.
+        let size = le_u32(&footer[12..16]) as u64;
+        let has_header = le_u32(&footer[20..24]) & 0x8000_0000 != 0;
+        let tag_end = footer_position + FOOTER_LEN;
+        if size < FOOTER_LEN || size > tag_end {
+            return Ok(None);
+        }
+        let header_len = if has_header && tag_end - size >= FOOTER_LEN {
+            FOOTER_LEN
+        } else {
+            0
+        };
+        let start = tag_end - size - header_len;
+        return Ok(Some((start, size + header_len)));
+    }
+    Ok(None)
+}
+
+/// Reads the APEv2 tag at the end of the file, if there is one.
+pub fn read_tag<R: Read + Seek>(file: &mut R) -> Result<Option<Tags>> {
+    let (start, tag_len) = match find_tag(file)? {
+        Some(tag) => tag,
+        None => return Ok(None),
+    };
+    // The footer is always the last 32 bytes of the tag.
+    file.seek(SeekFrom::Start(start + tag_len - FOOTER_LEN))?;
+    let footer = read_vec(file, FOOTER_LEN)?;
+    let items_len = le_u32(&footer[12..16]) as u64 - FOOTER_LEN;
+    let count = le_u32(&footer[16..20]);
+    file.seek(SeekFrom::Start(start + tag_len - FOOTER_LEN - items_len))?;
+    let items = read_vec(file, items_len)?;
+
+    let mut tags = Tags::default();
+    let mut position = 0;
+    for _ in 0..count {
+        if position + 8 > items.len() {
+            break;
+        }
+        let value_len = le_u32(&items[position..position + 4]) as usize;
+        let flags = le_u32(&items[position + 4..position + 8]);
+        position += 8;
+        let key_end = match items[position..].iter().position(|&b| b == 0) {
+            Some(end) => position + end,
+            None => break,
+        };
+        let key = String::from_utf8_lossy(&items[position..key_end]).to_lowercase();
+        position = key_end + 1;
+        if position + value_len > items.len() {
+            break;
+        }
+        let value = &items[position..position + value_len];
+        position += value_len;
+
+        // Bits 1 and 2 give the item type, where 0 is UTF-8 text.
+        if (flags >> 1) & 3 == 0 {
+            let text = String::from_utf8_lossy(value);
+            // Multiple values are separated by null characters.
+            let first = text.split('\0').next().unwrap_or("");
+            match key.as_ref() {
+                "title" => Tags::set(&mut tags.title, first),
+                "artist" => Tags::set(&mut tags.artist, first),
+                "album" => Tags::set(&mut tags.album, first),
+                "album artist" | "albumartist" => {
+                    tags.album_artists = text.split('\0')
+                        .filter(|value| !value.trim().is_empty())
+                        .map(|value| value.to_owned())
+                        .collect()
+                }
+                "year" => tags.year = parse_year(first),
+                "track" => tags.track_number = parse_number(first),
+                "disc" => {
+                    tags.disc_number = parse_number(first);
+                    tags.disc_count = parse_total(first);
+                }
+                "musicbrainz_trackid" => Tags::set(&mut tags.musicbrainz_track_id, first),
+                _ => (),
+            }
+        } else if key == "cover art (front)" {
+            // Binary cover art is prefixed with a null terminated file name.
+            let data = match value.iter().position(|&b| b == 0) {
+                Some(end) => &value[end + 1..],
+                None => value,
+            };
+            let (width, height) = picture::dimensions(data).unwrap_or((0, 0));
+            tags.front_cover = Some(FrontCover { width, height });
+        }
+    }
+    Ok(Some(tags))
+}
+
+/// Reads a Monkey's Audio file whose "MAC " marker is at the given offset.
+pub fn read<R: Read + Seek>(file: &mut R, offset: u64) -> Result<(Tags, Properties)> {
+    let len = stream_len(file)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let descriptor = read_vec(file, 6)?;
+    let version = le_u16(&descriptor[4..6]);
+
+    let (bits_per_sample, sample_rate, total_frames, blocks_per_frame, final_frame_blocks) =
+        if version >= 3980 {
+            // The descriptor gives the length of itself, which is
+            // followed by the header.
+            let descriptor = read_vec(file, 46)?;
+            let descriptor_len = le_u32(&descriptor[2..6]) as u64;
+            file.seek(SeekFrom::Start(offset + descriptor_len))?;
+            let header = read_vec(file, 24)?;
+            (
+                le_u16(&header[16..18]),
+                le_u32(&header[20..24]) as u64,
+                le_u32(&header[12..16]) as u64,
+                le_u32(&header[4..8]) as u64,
+                le_u32(&header[8..12]) as u64,
+            )
+        } else {
+            let header = read_vec(file, 26)?;
+            let compression = le_u16(&header[0..2]);
+            let flags = le_u16(&header[2..4]);
+            let bits_per_sample = if flags & FLAG_8_BIT != 0 {
+                8
+            } else if flags & FLAG_24_BIT != 0 {
+                24
+            } else {
+                16
+            };
+            let blocks_per_frame = if version >= 3950 {
+                73728 * 4
+            } else if version >= 3900 || (version >= 3800 && compression == 4000) {
+                73728
+            } else {
+                9216
+            };
+            (
+                bits_per_sample,
+                le_u32(&header[6..10]) as u64,
+                le_u32(&header[18..22]) as u64,
+                blocks_per_frame,
+                le_u32(&header[22..26]) as u64,
+            )
+        };
+
+    if sample_rate == 0 {
+        return Err(invalid("Monkey's Audio file has no sample rate."));
+    }
+    let total_blocks = if total_frames == 0 {
+        0
+    } else {
+        (total_frames - 1) * blocks_per_frame + final_frame_blocks
+    };
+    let duration = (total_blocks * 1000 / sample_rate) as i64;
+
+    let mut tags = match id3::read(file, 0)? {
+        Some(tags) => tags,
+        None => Tags::default(),
+    };
+    if let Some(ape) = read_tag(file)? {
+        tags.merge(ape);
+    }
+    if let Some(id3v1) = id3::read_v1(file)? {
+        tags.merge(id3v1);
+    }
+
+    let file_type = match bits_per_sample {
+        8 => TrackFileType::MonkeysAudio8,
+        16 => TrackFileType::MonkeysAudio16,
+        24 => TrackFileType::MonkeysAudio24,
+        _ => TrackFileType::MonkeysAudio,
+    };
+
+    Ok((
+        tags,
+        Properties {
+            file_type,
+            duration,
+            bitrate: average_bitrate(len - offset, duration),
+            sample_rate: sample_rate as i32,
+        },
+    ))
+}
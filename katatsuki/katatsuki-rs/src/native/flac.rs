@@ -0,0 +1,96 @@
+//! Native FLAC streams.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use track::TrackFileType;
+
+use super::xiph;
+use super::{average_bitrate, be_u24, be_u32, invalid, read_vec, stream_len, trailing_tags_start,
+            Properties, Tags};
+
+const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+/// Gets the range of the audio frames, which follow the last metadata block.
+pub fn audio_ranges<R: Read + Seek>(file: &mut R, offset: u64) -> Result<Vec<(u64, u64)>> {
+    let len = stream_len(file)?;
+    let mut position = offset + 4;
+    loop {
+        file.seek(SeekFrom::Start(position))?;
+        let header = read_vec(file, 4)?;
+        position += 4 + be_u24(&header[1..4]) as u64;
+        if header[0] & 0x80 != 0 || position >= len {
+            break;
+        }
+    }
+    let end = trailing_tags_start(file)?;
+    Ok(vec![(position, end.saturating_sub(position))])
+}
+
+/// Reads a FLAC stream whose "fLaC" marker is at the given offset.
+pub fn read<R: Read + Seek>(file: &mut R, offset: u64) -> Result<(Tags, Properties)> {
+    let len = stream_len(file)?;
+    let mut position = offset + 4;
+    let mut tags = Tags::default();
+    let mut stream_info = None;
+
+    loop {
+        file.seek(SeekFrom::Start(position))?;
+        let header = read_vec(file, 4)?;
+        let last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let block_len = be_u24(&header[1..4]) as u64;
+        position += 4 + block_len;
+
+        match block_type {
+            STREAMINFO => stream_info = Some(read_vec(file, block_len)?),
+            VORBIS_COMMENT => xiph::parse_comments(&read_vec(file, block_len)?, &mut tags),
+            PICTURE if tags.front_cover.is_none() => {
+                tags.front_cover = xiph::parse_picture(&read_vec(file, block_len)?)
+            }
+            _ => (),
+        }
+
+        if last || position >= len {
+            break;
+        }
+    }
+
+    let stream_info = match stream_info {
+        Some(ref info) if info.len() >= 18 => info,
+        _ => return Err(invalid("FLAC stream is missing STREAMINFO.")),
+    };
+
+    // The sample rate, channels, bits per sample and total samples
+    // are packed into bytes 10 to 17 as 20, 3, 5 and 36 bits.
+    let packed = be_u32(&stream_info[10..14]);
+    let sample_rate = packed >> 12;
+    let bits_per_sample = ((packed >> 4) & 0x1F) + 1;
+    let total_samples = ((packed & 0x0F) as u64) << 32 | be_u32(&stream_info[14..18]) as u64;
+
+    let duration = if sample_rate > 0 {
+        (total_samples * 1000 / sample_rate as u64) as i64
+    } else {
+        0
+    };
+
+    let file_type = match bits_per_sample {
+        4 => TrackFileType::FLAC4,
+        8 => TrackFileType::FLAC8,
+        16 => TrackFileType::FLAC16,
+        24 => TrackFileType::FLAC24,
+        32 => TrackFileType::FLAC32,
+        _ => TrackFileType::FLAC,
+    };
+
+    Ok((
+        tags,
+        Properties {
+            file_type,
+            duration,
+            bitrate: average_bitrate(len.saturating_sub(position), duration),
+            sample_rate: sample_rate as i32,
+        },
+    ))
+}
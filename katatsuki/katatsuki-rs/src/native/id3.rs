@@ -0,0 +1,315 @@
+//! ID3v2.2, ID3v2.3, ID3v2.4 and ID3v1 tags.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use super::picture;
+use super::{be_u24, be_u32, parse_number, parse_total, parse_year, read_up_to, read_vec,
+            FrontCover, Tags};
+
+/// The APIC picture type of the front cover.
+const FRONT_COVER: u8 = 3;
+
+fn syncsafe(b: &[u8]) -> u32 {
+    (b[0] as u32 & 0x7F) << 21 | (b[1] as u32 & 0x7F) << 14 | (b[2] as u32 & 0x7F) << 7
+        | b[3] as u32 & 0x7F
+}
+
+/// Gets the full size of the ID3v2 tag with the given header,
+/// including the header and footer.
+fn tag_size(header: &[u8]) -> Option<u64> {
+    if header.len() < 10 || &header[0..3] != b"ID3" {
+        return None;
+    }
+    let footer = if header[3] == 4 && header[5] & 0x10 != 0 {
+        10
+    } else {
+        0
+    };
+    Some(10 + syncsafe(&header[6..10]) as u64 + footer)
+}
+
+/// Skips over any ID3v2 tags at the start of the file,
+/// returning the offset of the first byte after them.
+pub fn skip<R: Read + Seek>(file: &mut R) -> Result<u64> {
+    let mut offset = 0;
+    loop {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 10];
+        if read_up_to(file, &mut header)? < header.len() {
+            return Ok(offset);
+        }
+        match tag_size(&header) {
+            Some(size) => offset += size,
+            None => return Ok(offset),
+        }
+    }
+}
+
+/// Reads the ID3v2 tag at the given offset, if there is one.
+pub fn read<R: Read + Seek>(file: &mut R, offset: u64) -> Result<Option<Tags>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut header = [0u8; 10];
+    if read_up_to(file, &mut header)? < header.len() || tag_size(&header).is_none() {
+        return Ok(None);
+    }
+    let body = read_vec(file, syncsafe(&header[6..10]) as u64)?;
+    Ok(Some(parse(header[3], header[5], body)))
+}
+
+/// Parses an ID3v2 tag from a buffer starting with its header, such
+/// as the contents of an AIFF ID3 chunk.
+pub fn parse_tag(data: &[u8]) -> Option<Tags> {
+    match tag_size(data) {
+        Some(_) => {
+            let end = 10 + syncsafe(&data[6..10]) as usize;
+            if end > data.len() {
+                None
+            } else {
+                Some(parse(data[3], data[5], data[10..end].to_vec()))
+            }
+        }
+        None => None,
+    }
+}
+
+/// Reads the ID3v1 tag at the end of the file, if there is one.
+pub fn read_v1<R: Read + Seek>(file: &mut R) -> Result<Option<Tags>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len < 128 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(len - 128))?;
+    let tag = read_vec(file, 128)?;
+    if &tag[0..3] != b"TAG" {
+        return Ok(None);
+    }
+
+    let field = |data: &[u8]| {
+        latin1(data)
+            .trim_end_matches(|c| c == '\0' || c == ' ')
+            .to_owned()
+    };
+    let mut tags = Tags::default();
+    Tags::set(&mut tags.title, &field(&tag[3..33]));
+    Tags::set(&mut tags.artist, &field(&tag[33..63]));
+    Tags::set(&mut tags.album, &field(&tag[63..93]));
+    tags.year = parse_year(&field(&tag[93..97]));
+    // ID3v1.1 stores the track number in the last byte of the comment.
+    if tag[125] == 0 {
+        tags.track_number = tag[126] as u32;
+    }
+    Ok(Some(tags))
+}
+
+/// Reverses the unsynchronisation scheme, where every 0xFF 0x00 was
+/// written in place of 0xFF.
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = 0u8;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0x00) {
+            output.push(byte);
+        }
+        previous = byte;
+    }
+    output
+}
+
+fn parse(version: u8, flags: u8, body: Vec<u8>) -> Tags {
+    let mut tags = Tags::default();
+
+    // ID3v2.4 unsynchronises per-frame instead of per-tag.
+    let body = if version < 4 && flags & 0x80 != 0 {
+        resynchronise(&body)
+    } else {
+        body
+    };
+
+    let mut position = 0;
+    if version >= 3 && flags & 0x40 != 0 && body.len() >= 4 {
+        position = if version == 4 {
+            syncsafe(&body[0..4]) as usize
+        } else {
+            be_u32(&body[0..4]) as usize + 4
+        };
+    }
+
+    let header_len = if version == 2 { 6 } else { 10 };
+    while position + header_len <= body.len() {
+        let header = &body[position..position + header_len];
+        if header[0] == 0 {
+            // We've hit the padding.
+            break;
+        }
+        let (id, size, format_flags) = match version {
+            2 => (v22_frame_id(&header[0..3]), be_u24(&header[3..6]), 0),
+            3 => (String::from_utf8_lossy(&header[0..4]).into_owned(), be_u32(&header[4..8]), header[9]),
+            _ => (String::from_utf8_lossy(&header[0..4]).into_owned(), syncsafe(&header[4..8]), header[9]),
+        };
+        position += header_len;
+        let size = size as usize;
+        if position + size > body.len() {
+            break;
+        }
+        let frame = &body[position..position + size];
+        position += size;
+
+        let frame = match version {
+            4 => {
+                // Skip compressed and encrypted frames.
+                if format_flags & 0x0C != 0 {
+                    continue;
+                }
+                // Grouping identity and data length indicator.
+                let skip = if format_flags & 0x40 != 0 { 1 } else { 0 }
+                    + if format_flags & 0x01 != 0 { 4 } else { 0 };
+                if skip > frame.len() {
+                    continue;
+                }
+                if format_flags & 0x02 != 0 {
+                    resynchronise(&frame[skip..])
+                } else {
+                    frame[skip..].to_vec()
+                }
+            }
+            3 => {
+                if format_flags & 0xC0 != 0 {
+                    continue;
+                }
+                let skip = if format_flags & 0x20 != 0 { 1 } else { 0 };
+                if skip > frame.len() {
+                    continue;
+                }
+                frame[skip..].to_vec()
+            }
+            _ => frame.to_vec(),
+        };
+        apply_frame(&mut tags, &id, &frame, version);
+    }
+    tags
+}
+
+/// Maps ID3v2.2 frame identifiers to their ID3v2.3 counterparts.
+fn v22_frame_id(id: &[u8]) -> String {
+    match id {
+        b"TT2" => "TIT2",
+        b"TP1" => "TPE1",
+        b"TP2" => "TPE2",
+        b"TAL" => "TALB",
+        b"TYE" => "TYER",
+        b"TRK" => "TRCK",
+        b"TPA" => "TPOS",
+        b"UFI" => "UFID",
+        b"PIC" => "APIC",
+        _ => "",
+    }.to_owned()
+}
+
+fn apply_frame(tags: &mut Tags, id: &str, frame: &[u8], version: u8) {
+    if frame.is_empty() {
+        return;
+    }
+    match id {
+        "TIT2" => Tags::set(&mut tags.title, &first_value(frame)),
+        "TPE1" => Tags::set(&mut tags.artist, &first_value(frame)),
+        "TALB" => Tags::set(&mut tags.album, &first_value(frame)),
+        "TPE2" => if tags.album_artists.is_empty() {
+            tags.album_artists = text_values(frame)
+        },
+        "TYER" | "TDRC" => if tags.year == 0 {
+            tags.year = parse_year(&first_value(frame))
+        },
+        "TRCK" => if tags.track_number == 0 {
+            tags.track_number = parse_number(&first_value(frame))
+        },
+        "TPOS" => if tags.disc_number == 0 {
+            let value = first_value(frame);
+            tags.disc_number = parse_number(&value);
+            tags.disc_count = parse_total(&value);
+        },
+        "UFID" => {
+            if let Some(owner_end) = frame.iter().position(|&b| b == 0) {
+                if &frame[..owner_end] == b"http://musicbrainz.org" {
+                    let id = latin1(&frame[owner_end + 1..]);
+                    Tags::set(&mut tags.musicbrainz_track_id, &id);
+                }
+            }
+        }
+        "APIC" => if tags.front_cover.is_none() {
+            tags.front_cover = front_cover(frame, version);
+        },
+        _ => (),
+    }
+}
+
+/// Decodes the values of a text information frame.
+/// ID3v2.4 separates multiple values with null characters.
+fn text_values(frame: &[u8]) -> Vec<String> {
+    decode_text(frame[0], &frame[1..])
+        .split('\0')
+        .map(|value| value.trim_matches('\u{feff}').to_owned())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn first_value(frame: &[u8]) -> String {
+    text_values(frame).into_iter().next().unwrap_or_default()
+}
+
+fn front_cover(frame: &[u8], version: u8) -> Option<FrontCover> {
+    let encoding = frame[0];
+    // ID3v2.2 uses a 3 character image format instead of a MIME type.
+    let mime_end = if version == 2 {
+        4
+    } else {
+        1 + frame[1..].iter().position(|&b| b == 0)? + 1
+    };
+    if mime_end + 1 > frame.len() || frame[mime_end] != FRONT_COVER {
+        return None;
+    }
+    let description = &frame[mime_end + 1..];
+    let data_start = mime_end + 1 + terminator_end(encoding, description)?;
+    let data = &frame[data_start..];
+    let (width, height) = picture::dimensions(data).unwrap_or((0, 0));
+    Some(FrontCover { width, height })
+}
+
+/// Finds the end of a null-terminated string in the given encoding,
+/// including the terminator.
+fn terminator_end(encoding: u8, data: &[u8]) -> Option<usize> {
+    match encoding {
+        1 | 2 => data.chunks(2)
+            .position(|c| c.len() == 2 && c[0] == 0 && c[1] == 0)
+            .map(|i| i * 2 + 2),
+        _ => data.iter().position(|&b| b == 0).map(|i| i + 1),
+    }
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+
+fn utf16(data: &[u8], big_endian: bool) -> String {
+    let units = data.chunks(2)
+        .filter(|c| c.len() == 2)
+        .map(|c| if big_endian {
+            (c[0] as u16) << 8 | c[1] as u16
+        } else {
+            (c[1] as u16) << 8 | c[0] as u16
+        })
+        .collect::<Vec<u16>>();
+    String::from_utf16_lossy(&units)
+}
+
+fn decode_text(encoding: u8, data: &[u8]) -> String {
+    match encoding {
+        0 => latin1(data),
+        1 => {
+            // UTF-16 with a byte order mark, defaulting to little endian.
+            let big_endian = data.len() >= 2 && data[0] == 0xFE && data[1] == 0xFF;
+            utf16(data, big_endian)
+        }
+        2 => utf16(data, true),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
@@ -0,0 +1,333 @@
+//! A pure-Rust tag reader that fills `Track` without libkatatsuki-sys.
+//!
+//! Each container module returns the tags it found alongside the
+//! audio properties of the stream, which are then merged into a `Track`
+//! following the same rules libkatatsuki uses with TagLib#.
+
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::Path;
+
+use chrono::Local;
+
+use track::{Track, TrackFileType};
+
+mod aiff;
+mod ape;
+mod flac;
+mod id3;
+mod mp4;
+mod mpeg;
+mod ogg;
+mod picture;
+mod xiph;
+
+/// The front cover of a track, with its dimensions in pixels.
+/// Dimensions are 0 if the image could not be decoded.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrontCover {
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Tags common to every supported format.
+#[derive(Debug, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artists: Vec<String>,
+    pub year: u32,
+    pub track_number: u32,
+    pub disc_number: u32,
+    /// The number of discs in the release, or 0 if unknown.
+    pub disc_count: u32,
+    pub musicbrainz_track_id: Option<String>,
+    pub front_cover: Option<FrontCover>,
+}
+
+impl Tags {
+    /// Fills any missing tags from a tag of lower priority.
+    pub fn merge(&mut self, other: Tags) {
+        if self.title.is_none() {
+            self.title = other.title;
+        }
+        if self.artist.is_none() {
+            self.artist = other.artist;
+        }
+        if self.album.is_none() {
+            self.album = other.album;
+        }
+        if self.album_artists.is_empty() {
+            self.album_artists = other.album_artists;
+        }
+        if self.year == 0 {
+            self.year = other.year;
+        }
+        if self.track_number == 0 {
+            self.track_number = other.track_number;
+        }
+        if self.disc_number == 0 {
+            self.disc_number = other.disc_number;
+        }
+        if self.disc_count == 0 {
+            self.disc_count = other.disc_count;
+        }
+        if self.musicbrainz_track_id.is_none() {
+            self.musicbrainz_track_id = other.musicbrainz_track_id;
+        }
+        if self.front_cover.is_none() {
+            self.front_cover = other.front_cover;
+        }
+    }
+
+    /// Sets a text value, ignoring empty strings.
+    fn set(field: &mut Option<String>, value: &str) {
+        let value = value.trim_matches('\0');
+        if field.is_none() && !value.is_empty() {
+            *field = Some(value.to_owned());
+        }
+    }
+}
+
+/// Properties of the audio stream.
+#[derive(Debug)]
+pub struct Properties {
+    pub file_type: TrackFileType,
+    /// The duration of the stream in milliseconds.
+    pub duration: i64,
+    /// The average bitrate in kbps.
+    pub bitrate: i32,
+    pub sample_rate: i32,
+}
+
+/// Reads track data with the native backend.
+pub fn read_track(path: &Path, source: Option<&str>) -> Result<Track> {
+    let mut file = BufReader::new(File::open(path)?);
+    let (tags, properties) = read_file(&mut file).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => unsupported(path),
+        _ => err,
+    })?;
+
+    if let TrackFileType::Unknown = properties.file_type {
+        return Err(unsupported(path));
+    }
+
+    let front_cover = tags.front_cover;
+    Ok(Track {
+        file_path: path.to_owned(),
+        file_type: properties.file_type,
+        title: tags.title.unwrap_or("".to_owned()),
+        artist: tags.artist.unwrap_or("".to_owned()),
+        album: tags.album.unwrap_or("".to_owned()),
+        album_artists: tags.album_artists
+            .iter()
+            .map(|c| c.trim())
+            .collect::<Vec<&str>>()
+            .join(";")
+            .split(';')
+            .map(|c| c.to_owned())
+            .collect::<Vec<String>>(),
+        year: tags.year as i32,
+        track_number: tags.track_number as i32,
+        musicbrainz_track_id: tags.musicbrainz_track_id,
+        has_front_cover: front_cover.is_some(),
+        front_cover_width: front_cover.map(|c| c.width).unwrap_or(0),
+        front_cover_height: front_cover.map(|c| c.height).unwrap_or(0),
+        bitrate: properties.bitrate,
+        sample_rate: properties.sample_rate,
+        source: source.unwrap_or("None").to_owned(),
+        disc_number: if tags.disc_number == 0 { 1 } else { tags.disc_number as i32 },
+        disc_count: tags.disc_count as i32,
+        duration: properties.duration as i32,
+        updated: Local::now().format("%Y-%m-%d").to_string(),
+    })
+}
+
+/// Finds the byte ranges of the audio data in the file, as offsets and
+/// lengths. Tags and other metadata are left out, so that the ranges
+/// hold the same data after the file is retagged.
+pub fn audio_ranges(path: &Path) -> Result<Vec<(u64, u64)>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let (format, offset) = detect_format(&mut file).map_err(|_| unsupported(path))?;
+    let ranges = match format {
+        Format::Flac => flac::audio_ranges(&mut file, offset),
+        Format::Ogg => ogg::audio_ranges(&mut file),
+        Format::Mp4 => mp4::audio_ranges(&mut file),
+        Format::Aiff => aiff::audio_ranges(&mut file),
+        Format::MonkeysAudio | Format::Mpeg => {
+            trailing_tags_start(&mut file).map(|end| vec![(offset, end.saturating_sub(offset))])
+        }
+    };
+    ranges.map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof | ErrorKind::InvalidData => unsupported(path),
+        _ => err,
+    })
+}
+
+enum Format {
+    Flac,
+    Ogg,
+    Mp4,
+    Aiff,
+    MonkeysAudio,
+    Mpeg,
+}
+
+/// Determines the format of the file, and the offset of the stream.
+fn detect_format<R: Read + Seek>(file: &mut R) -> Result<(Format, u64)> {
+    // Files may be prefixed with any amount of ID3v2 tags,
+    // so the format is determined from the first byte after them.
+    let offset = id3::skip(file)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut magic = [0u8; 12];
+    read_up_to(file, &mut magic)?;
+
+    let format = if &magic[0..4] == b"fLaC" {
+        Format::Flac
+    } else if &magic[0..4] == b"OggS" {
+        Format::Ogg
+    } else if &magic[4..8] == b"ftyp" {
+        Format::Mp4
+    } else if &magic[0..4] == b"FORM" && (&magic[8..12] == b"AIFF" || &magic[8..12] == b"AIFC") {
+        Format::Aiff
+    } else if &magic[0..4] == b"MAC " {
+        Format::MonkeysAudio
+    } else if mpeg::is_frame_sync(&magic) || offset > 0 {
+        Format::Mpeg
+    } else {
+        return Err(Error::new(ErrorKind::InvalidData, "Unknown file format."));
+    };
+    Ok((format, offset))
+}
+
+fn read_file<R: Read + Seek>(file: &mut R) -> Result<(Tags, Properties)> {
+    let (format, offset) = detect_format(file)?;
+    match format {
+        Format::Flac => flac::read(file, offset),
+        Format::Ogg => ogg::read(file),
+        Format::Mp4 => mp4::read(file),
+        Format::Aiff => aiff::read(file),
+        Format::MonkeysAudio => ape::read(file, offset),
+        Format::Mpeg => mpeg::read(file, offset),
+    }
+}
+
+/// Gets the offset where the APEv2 and ID3v1 tags at the end of the
+/// file begin, which is the end of the file if there are none.
+fn trailing_tags_start<R: Read + Seek>(file: &mut R) -> Result<u64> {
+    let len = stream_len(file)?;
+    if let Some((start, _)) = ape::find_tag(file)? {
+        Ok(start)
+    } else if id3::read_v1(file)?.is_some() {
+        Ok(len - 128)
+    } else {
+        Ok(len)
+    }
+}
+
+fn unsupported(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("File {:?} is unsupported", path),
+    )
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_owned())
+}
+
+/// Fills as much of the buffer as possible, returning the
+/// amount of bytes read.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// Reads exactly `len` bytes without trusting `len` for the allocation,
+/// since it usually comes from the file itself.
+fn read_vec<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(len).read_to_end(&mut buf)?;
+    if (buf.len() as u64) < len {
+        Err(Error::new(ErrorKind::UnexpectedEof, "Unexpected end of file."))
+    } else {
+        Ok(buf)
+    }
+}
+
+fn stream_len<R: Seek>(reader: &mut R) -> Result<u64> {
+    reader.seek(SeekFrom::End(0))
+}
+
+fn be_u16(b: &[u8]) -> u16 {
+    (b[0] as u16) << 8 | b[1] as u16
+}
+
+fn be_u24(b: &[u8]) -> u32 {
+    (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+}
+
+fn be_u64(b: &[u8]) -> u64 {
+    (be_u32(&b[0..4]) as u64) << 32 | be_u32(&b[4..8]) as u64
+}
+
+fn le_u16(b: &[u8]) -> u16 {
+    (b[1] as u16) << 8 | b[0] as u16
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    (b[3] as u32) << 24 | (b[2] as u32) << 16 | (b[1] as u32) << 8 | b[0] as u32
+}
+
+fn le_u64(b: &[u8]) -> u64 {
+    (le_u32(&b[4..8]) as u64) << 32 | le_u32(&b[0..4]) as u64
+}
+
+/// Parses the leading number of strings like "3/12" or " 07".
+fn parse_number(value: &str) -> u32 {
+    value
+        .trim()
+        .chars()
+        .take_while(|c| c.is_digit(10))
+        .collect::<String>()
+        .parse()
+        .unwrap_or(0)
+}
+
+/// Parses the total out of positions like "1/2".
+fn parse_total(value: &str) -> u32 {
+    value.splitn(2, '/').nth(1).map(parse_number).unwrap_or(0)
+}
+
+/// Parses the year out of dates like "2012-04-01" or "2012".
+fn parse_year(value: &str) -> u32 {
+    let year = value.trim();
+    if year.len() >= 4 && year.chars().take(4).all(|c| c.is_digit(10)) {
+        parse_number(&year[0..4])
+    } else {
+        parse_number(year)
+    }
+}
+
+/// Calculates the average bitrate in kbps from the size of
+/// the stream in bytes and its duration in milliseconds.
+fn average_bitrate(stream_bytes: u64, duration: i64) -> i32 {
+    if duration <= 0 {
+        0
+    } else {
+        (stream_bytes * 8 / duration as u64) as i32
+    }
+}
@@ -0,0 +1,330 @@
+//! MPEG-4 containers with AAC or ALAC audio.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use track::TrackFileType;
+
+use super::picture;
+use super::{average_bitrate, be_u16, be_u32, be_u64, invalid, parse_year, read_vec, stream_len,
+            FrontCover, Properties, Tags};
+
+/// Splits a buffer into its child atoms.
+fn atoms(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut children = Vec::new();
+    let mut position = 0;
+    while position + 8 <= data.len() {
+        let size = be_u32(&data[position..position + 4]) as usize;
+        let name = &data[position + 4..position + 8];
+        let (header_len, size) = match size {
+            0 => (8, data.len() - position),
+            1 if position + 16 <= data.len() => {
+                (16, be_u64(&data[position + 8..position + 16]) as usize)
+            }
+            _ => (8, size),
+        };
+        let end = match position.checked_add(size) {
+            Some(end) if size >= header_len && end <= data.len() => end,
+            _ => break,
+        };
+        children.push((name, &data[position + header_len..end]));
+        position = end;
+    }
+    children
+}
+
+fn child<'a>(data: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    atoms(data)
+        .into_iter()
+        .find(|&(n, _)| n == name)
+        .map(|(_, body)| body)
+}
+
+/// Follows a path of atom names, e.g. ["mdia", "minf", "stbl"].
+fn path<'a>(data: &'a [u8], names: &[&[u8]]) -> Option<&'a [u8]> {
+    names.iter().fold(Some(data), |data, name| data.and_then(|d| child(d, name)))
+}
+
+/// Gets the ranges of the mdat atoms, which hold the audio.
+pub fn audio_ranges<R: Read + Seek>(file: &mut R) -> Result<Vec<(u64, u64)>> {
+    let len = stream_len(file)?;
+    let mut position = 0;
+    let mut ranges = Vec::new();
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        let header = read_vec(file, 8)?;
+        let (header_len, size) = match be_u32(&header[0..4]) as u64 {
+            0 => (8, len - position),
+            1 => (16, be_u64(&read_vec(file, 8)?)),
+            size => (8, size),
+        };
+        let end = match position.checked_add(size) {
+            Some(end) if size >= header_len => end,
+            _ => break,
+        };
+        // A truncated file keeps what is left of its audio.
+        if &header[4..8] == b"mdat" {
+            ranges.push((position + header_len, end.min(len).saturating_sub(position + header_len)));
+        }
+        if end > len {
+            break;
+        }
+        position = end;
+    }
+    if ranges.is_empty() {
+        Err(invalid("MPEG-4 file has no mdat atom."))
+    } else {
+        Ok(ranges)
+    }
+}
+
+pub fn read<R: Read + Seek>(file: &mut R) -> Result<(Tags, Properties)> {
+    let len = stream_len(file)?;
+    let mut position = 0;
+    let mut moov = None;
+    let mut mdat_len = 0;
+
+    // Only moov is read into memory, since mdat holds the audio.
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        let header = read_vec(file, 8)?;
+        let (header_len, size) = match be_u32(&header[0..4]) as u64 {
+            0 => (8, len - position),
+            1 => (16, be_u64(&read_vec(file, 8)?)),
+            size => (8, size),
+        };
+        let end = match position.checked_add(size) {
+            Some(end) if size >= header_len => end,
+            _ => break,
+        };
+        match &header[4..8] {
+            b"moov" => moov = Some(read_vec(file, size - header_len)?),
+            b"mdat" => mdat_len += end.min(len).saturating_sub(position + header_len),
+            _ => (),
+        }
+        if end > len {
+            break;
+        }
+        position = end;
+    }
+
+    let moov = moov.ok_or(invalid("MPEG-4 file has no moov atom."))?;
+    let tags = read_tags(&moov);
+
+    // Find the first sound track.
+    let track = atoms(&moov)
+        .into_iter()
+        .filter(|&(name, _)| name == b"trak")
+        .map(|(_, body)| body)
+        .find(|trak| match path(trak, &[b"mdia", b"hdlr"]) {
+            Some(hdlr) if hdlr.len() >= 12 => &hdlr[8..12] == b"soun",
+            _ => false,
+        })
+        .ok_or(invalid("MPEG-4 file has no sound track."))?;
+
+    let duration = match path(track, &[b"mdia", b"mdhd"]) {
+        Some(mdhd) if mdhd.len() >= 32 && mdhd[0] == 1 => {
+            let timescale = be_u32(&mdhd[20..24]) as u64;
+            let duration = be_u64(&mdhd[24..32]);
+            if timescale > 0 { duration * 1000 / timescale } else { 0 }
+        }
+        Some(mdhd) if mdhd.len() >= 20 => {
+            let timescale = be_u32(&mdhd[12..16]) as u64;
+            let duration = be_u32(&mdhd[16..20]) as u64;
+            if timescale > 0 { duration * 1000 / timescale } else { 0 }
+        }
+        _ => 0,
+    } as i64;
+
+    let stsd = path(track, &[b"mdia", b"minf", b"stbl", b"stsd"])
+        .ok_or(invalid("MPEG-4 sound track has no sample description."))?;
+    // Skip the version, flags and entry count.
+    let (codec, entry) = atoms(if stsd.len() >= 8 { &stsd[8..] } else { &[] })
+        .into_iter()
+        .next()
+        .ok_or(invalid("MPEG-4 sound track has no sample description."))?;
+    if entry.len() < 28 {
+        return Err(invalid("MPEG-4 audio sample entry is too short."));
+    }
+
+    // The sample rate is 16.16 fixed point, which is why ALAC stores
+    // its real sample rate again in its magic cookie.
+    let bits_per_sample = be_u16(&entry[18..20]);
+    let mut sample_rate = (be_u32(&entry[24..28]) >> 16) as i32;
+    // QuickTime sound sample description versions 1 and 2
+    // carry extra fields before the extensions.
+    let extensions_start = match be_u16(&entry[8..10]) {
+        1 => 44,
+        2 => 64,
+        _ => 28,
+    };
+    let extensions = if entry.len() >= extensions_start {
+        &entry[extensions_start..]
+    } else {
+        &[]
+    };
+
+    match codec {
+        b"mp4a" => {
+            let bitrate = child(extensions, b"esds")
+                .and_then(esds_average_bitrate)
+                .and_then(|bitrate| if bitrate > 0 { Some((bitrate / 1000) as i32) } else { None })
+                .unwrap_or(average_bitrate(mdat_len, duration));
+            Ok((
+                tags,
+                Properties {
+                    file_type: TrackFileType::AAC,
+                    duration,
+                    bitrate,
+                    sample_rate,
+                },
+            ))
+        }
+        b"alac" => {
+            let mut bits_per_sample = bits_per_sample;
+            // The ALAC specific config, after the version and flags.
+            if let Some(cookie) = child(extensions, b"alac") {
+                if cookie.len() >= 28 {
+                    bits_per_sample = cookie[9] as u16;
+                    sample_rate = be_u32(&cookie[24..28]) as i32;
+                }
+            }
+            let file_type = match bits_per_sample {
+                16 => TrackFileType::ALAC16,
+                24 => TrackFileType::ALAC24,
+                _ => TrackFileType::ALAC,
+            };
+            Ok((
+                tags,
+                Properties {
+                    file_type,
+                    duration,
+                    bitrate: average_bitrate(mdat_len, duration),
+                    sample_rate,
+                },
+            ))
+        }
+        _ => Err(invalid("Unsupported MPEG-4 audio codec.")),
+    }
+}
+
+/// Reads a descriptor length, which is stored as up to 4 bytes
+/// of 7 bits each.
+fn descriptor_len(data: &[u8], position: &mut usize) -> Option<usize> {
+    let mut len = 0;
+    for _ in 0..4 {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        len = len << 7 | (byte & 0x7F) as usize;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Some(len)
+}
+
+/// Gets the average bitrate from the decoder config descriptor of an esds atom.
+fn esds_average_bitrate(esds: &[u8]) -> Option<u32> {
+    // Skip the version and flags.
+    let mut position = 4;
+    if *esds.get(position)? != 0x03 {
+        return None;
+    }
+    position += 1;
+    descriptor_len(esds, &mut position)?;
+    // ES_ID, then optional fields depending on the flags.
+    position += 2;
+    let flags = *esds.get(position)?;
+    position += 1;
+    if flags & 0x80 != 0 {
+        position += 2;
+    }
+    if flags & 0x40 != 0 {
+        position += 1 + *esds.get(position)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        position += 2;
+    }
+    if *esds.get(position)? != 0x04 {
+        return None;
+    }
+    position += 1;
+    descriptor_len(esds, &mut position)?;
+    // Object type, stream type, buffer size and max bitrate.
+    position += 9;
+    if position + 4 > esds.len() {
+        None
+    } else {
+        Some(be_u32(&esds[position..position + 4]))
+    }
+}
+
+/// Gets the values of the data atoms of an ilst item.
+fn data_values(item: &[u8]) -> Vec<&[u8]> {
+    atoms(item)
+        .into_iter()
+        .filter(|&(name, body)| name == b"data" && body.len() >= 8)
+        .map(|(_, body)| &body[8..])
+        .collect()
+}
+
+fn text(item: &[u8]) -> Option<String> {
+    data_values(item)
+        .into_iter()
+        .next()
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+fn read_tags(moov: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+    let ilst = match path(moov, &[b"udta", b"meta"]) {
+        // meta is a full atom, so skip the version and flags.
+        Some(meta) if meta.len() >= 4 => match child(&meta[4..], b"ilst") {
+            Some(ilst) => ilst,
+            None => return tags,
+        },
+        _ => return tags,
+    };
+
+    for (name, item) in atoms(ilst) {
+        match name {
+            b"\xa9nam" => Tags::set(&mut tags.title, &text(item).unwrap_or_default()),
+            b"\xa9ART" => Tags::set(&mut tags.artist, &text(item).unwrap_or_default()),
+            b"\xa9alb" => Tags::set(&mut tags.album, &text(item).unwrap_or_default()),
+            b"aART" => {
+                tags.album_artists = data_values(item)
+                    .into_iter()
+                    .map(|value| String::from_utf8_lossy(value).into_owned())
+                    .filter(|value| !value.trim().is_empty())
+                    .collect()
+            }
+            b"\xa9day" => tags.year = parse_year(&text(item).unwrap_or_default()),
+            b"trkn" => if let Some(value) = data_values(item).into_iter().next() {
+                if value.len() >= 4 {
+                    tags.track_number = be_u16(&value[2..4]) as u32;
+                }
+            },
+            b"disk" => if let Some(value) = data_values(item).into_iter().next() {
+                if value.len() >= 4 {
+                    tags.disc_number = be_u16(&value[2..4]) as u32;
+                }
+                if value.len() >= 6 {
+                    tags.disc_count = be_u16(&value[4..6]) as u32;
+                }
+            },
+            b"covr" => if let Some(value) = data_values(item).into_iter().next() {
+                let (width, height) = picture::dimensions(value).unwrap_or((0, 0));
+                tags.front_cover = Some(FrontCover { width, height });
+            },
+            b"----" => {
+                // Freeform items are keyed by their mean and name atoms.
+                if let Some(name) = child(item, b"name") {
+                    if name.len() >= 4 && &name[4..] == b"MusicBrainz Track Id" {
+                        Tags::set(&mut tags.musicbrainz_track_id, &text(item).unwrap_or_default());
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    tags
+}
@@ -0,0 +1,191 @@
+//! MPEG-1 and MPEG-2 Layer 3 audio, with ID3v2, APEv2 and ID3v1 tags.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use track::TrackFileType;
+
+use super::{ape, id3};
+use super::{average_bitrate, be_u32, invalid, read_up_to, stream_len, Properties, Tags};
+
+/// How far past the tags to search for the first frame.
+const MAX_SYNC_SEARCH: usize = 64 * 1024;
+
+/// Layer 3 bitrates in kbps, for MPEG-1 and MPEG-2/2.5 respectively.
+const BITRATES: [[u32; 16]; 2] = [
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0],
+];
+
+/// Sample rates for MPEG-1, MPEG-2 and MPEG-2.5 respectively.
+const SAMPLE_RATES: [[u32; 4]; 3] = [
+    [44100, 48000, 32000, 0],
+    [22050, 24000, 16000, 0],
+    [11025, 12000, 8000, 0],
+];
+
+#[derive(Debug)]
+struct FrameHeader {
+    /// 0 for MPEG-1, 1 for MPEG-2 and 2 for MPEG-2.5
+    version: usize,
+    layer: u8,
+    bitrate: u32,
+    sample_rate: u32,
+    mono: bool,
+    padding: bool,
+}
+
+impl FrameHeader {
+    fn parse(b: &[u8]) -> Option<FrameHeader> {
+        if b.len() < 4 || b[0] != 0xFF || b[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = match (b[1] >> 3) & 3 {
+            3 => 0,
+            2 => 1,
+            0 => 2,
+            _ => return None,
+        };
+        let layer = match (b[1] >> 1) & 3 {
+            1 => 3,
+            2 => 2,
+            3 => 1,
+            _ => return None,
+        };
+        let bitrate_index = (b[2] >> 4) as usize;
+        let sample_rate_index = ((b[2] >> 2) & 3) as usize;
+        if bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+            return None;
+        }
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate: BITRATES[if version == 0 { 0 } else { 1 }][bitrate_index],
+            sample_rate: SAMPLE_RATES[version][sample_rate_index],
+            mono: (b[3] >> 6) == 3,
+            padding: b[2] & 0x02 != 0,
+        })
+    }
+
+    fn samples_per_frame(&self) -> u64 {
+        if self.version == 0 {
+            1152
+        } else {
+            576
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        (self.samples_per_frame() / 8 * self.bitrate as u64 * 1000 / self.sample_rate as u64)
+            as usize + if self.padding { 1 } else { 0 }
+    }
+
+    /// The offset of the Xing header from the start of the frame,
+    /// which is right after the side information.
+    fn xing_offset(&self) -> usize {
+        4 + match (self.version == 0, self.mono) {
+            (true, false) => 32,
+            (true, true) => 17,
+            (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+}
+
+pub fn is_frame_sync(b: &[u8]) -> bool {
+    FrameHeader::parse(b).is_some()
+}
+
+/// Gets the frame count and stream size from a Xing, Info or VBRI header.
+/// Xing and VBRI headers are written for variable bitrate streams only.
+fn vbr_header(frame: &[u8], header: &FrameHeader) -> Option<(bool, Option<u32>, Option<u32>)> {
+    let xing = header.xing_offset();
+    if frame.len() >= xing + 8 && (&frame[xing..xing + 4] == b"Xing" || &frame[xing..xing + 4] == b"Info") {
+        let flags = be_u32(&frame[xing + 4..xing + 8]);
+        let mut position = xing + 8;
+        let mut frames = None;
+        let mut bytes = None;
+        if flags & 1 != 0 && frame.len() >= position + 4 {
+            frames = Some(be_u32(&frame[position..position + 4]));
+            position += 4;
+        }
+        if flags & 2 != 0 && frame.len() >= position + 4 {
+            bytes = Some(be_u32(&frame[position..position + 4]));
+        }
+        return Some((&frame[xing..xing + 4] == b"Xing", frames, bytes));
+    }
+    // VBRI headers are always 32 bytes after the frame header.
+    if frame.len() >= 36 + 18 && &frame[36..40] == b"VBRI" {
+        return Some((
+            true,
+            Some(be_u32(&frame[50..54])),
+            Some(be_u32(&frame[46..50])),
+        ));
+    }
+    None
+}
+
+/// Reads an MPEG stream whose first frame is at or after the given offset.
+pub fn read<R: Read + Seek>(file: &mut R, offset: u64) -> Result<(Tags, Properties)> {
+    let len = stream_len(file)?;
+    let mut tags = match id3::read(file, 0)? {
+        Some(tags) => tags,
+        None => Tags::default(),
+    };
+    let ape_tag = ape::find_tag(file)?;
+    if let Some(ape) = ape::read_tag(file)? {
+        tags.merge(ape);
+    }
+    let id3v1 = id3::read_v1(file)?;
+    let trailing_tags = ape_tag.map(|(_, len)| len).unwrap_or(0)
+        + if id3v1.is_some() { 128 } else { 0 };
+    if let Some(id3v1) = id3v1 {
+        tags.merge(id3v1);
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0u8; MAX_SYNC_SEARCH];
+    let read = read_up_to(file, &mut buffer)?;
+    buffer.truncate(read);
+
+    // A frame is only accepted if it is followed by another valid frame,
+    // since the sync word can appear in random data.
+    let (start, header) = (0..buffer.len())
+        .filter_map(|i| FrameHeader::parse(&buffer[i..]).map(|header| (i, header)))
+        .find(|&(i, ref header)| {
+            let next = i + header.frame_len();
+            next + 4 > buffer.len() || FrameHeader::parse(&buffer[next..]).is_some()
+        })
+        .ok_or(invalid("Unable to find an MPEG frame."))?;
+
+    let stream_len = len.saturating_sub(offset + start as u64 + trailing_tags);
+    let (is_vbr, frames, bytes) = vbr_header(&buffer[start..], &header).unwrap_or((false, None, None));
+
+    let duration = match frames {
+        Some(frames) if frames > 0 => {
+            (frames as u64 * header.samples_per_frame() * 1000 / header.sample_rate as u64) as i64
+        }
+        _ => (stream_len * 8 / header.bitrate as u64) as i64,
+    };
+    let bitrate = match (frames, bytes) {
+        (Some(frames), _) if frames > 0 => {
+            average_bitrate(bytes.map(|b| b as u64).unwrap_or(stream_len), duration)
+        }
+        _ => header.bitrate as i32,
+    };
+
+    let file_type = match (header.layer, is_vbr) {
+        (3, true) => TrackFileType::MP3VBR,
+        (3, false) => TrackFileType::MP3CBR,
+        _ => TrackFileType::Unknown,
+    };
+
+    Ok((
+        tags,
+        Properties {
+            file_type,
+            duration,
+            bitrate,
+            sample_rate: header.sample_rate as i32,
+        },
+    ))
+}
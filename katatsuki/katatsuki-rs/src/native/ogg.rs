@@ -0,0 +1,176 @@
+//! Ogg Vorbis and Ogg Opus streams.
+
+use std::io::{Read, Result, Seek, SeekFrom};
+
+use track::TrackFileType;
+
+use super::xiph;
+use super::{average_bitrate, invalid, le_u16, le_u32, le_u64, read_up_to, read_vec, stream_len,
+            Properties, Tags};
+
+/// Opus always runs its granule position at 48kHz.
+const OPUS_RATE: u64 = 48000;
+
+/// The furthest from the end of the file the last page can start.
+const MAX_PAGE_SIZE: u64 = 65307;
+
+struct Page {
+    serial: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn read_page<R: Read>(file: &mut R) -> Result<Page> {
+    let header = read_vec(file, 27)?;
+    if &header[0..4] != b"OggS" {
+        return Err(invalid("Expected an Ogg page."));
+    }
+    let segments = read_vec(file, header[26] as u64)?;
+    let data_len = segments.iter().map(|&s| s as u64).sum();
+    Ok(Page {
+        serial: le_u32(&header[14..18]),
+        data: read_vec(file, data_len)?,
+        segments,
+    })
+}
+
+/// Reads the first `count` packets of the first logical stream.
+fn read_packets<R: Read>(file: &mut R, count: usize) -> Result<(u32, Vec<Vec<u8>>)> {
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    let mut serial = None;
+    while packets.len() < count {
+        let page = read_page(file)?;
+        if *serial.get_or_insert(page.serial) != page.serial {
+            // Skip pages of multiplexed streams.
+            continue;
+        }
+        let mut position = 0;
+        for &segment in &page.segments {
+            packet.extend_from_slice(&page.data[position..position + segment as usize]);
+            position += segment as usize;
+            // A lacing value under 255 terminates the packet.
+            if segment < 255 {
+                packets.push(packet);
+                packet = Vec::new();
+                if packets.len() == count {
+                    break;
+                }
+            }
+        }
+    }
+    Ok((serial.unwrap_or(0), packets))
+}
+
+/// Finds the granule position of the last page of the stream.
+fn last_granule_position<R: Read + Seek>(file: &mut R, serial: u32) -> Result<u64> {
+    let len = stream_len(file)?;
+    let start = len.saturating_sub(MAX_PAGE_SIZE);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = vec![0u8; (len - start) as usize];
+    let read = read_up_to(file, &mut tail)?;
+    tail.truncate(read);
+    if tail.len() < 27 {
+        return Ok(0);
+    }
+
+    let mut position = tail.len().saturating_sub(27);
+    loop {
+        if &tail[position..position + 4] == b"OggS"
+            && le_u32(&tail[position + 14..position + 18]) == serial
+        {
+            return Ok(le_u64(&tail[position + 6..position + 14]));
+        }
+        if position == 0 {
+            return Ok(0);
+        }
+        position -= 1;
+    }
+}
+
+/// Gets the ranges of the page bodies that follow the header packets.
+/// Page headers are left out, since their sequence numbers and checksums
+/// change when a tagger adds or removes a page for the comment header.
+pub fn audio_ranges<R: Read + Seek>(file: &mut R) -> Result<Vec<(u64, u64)>> {
+    let len = stream_len(file)?;
+    file.seek(SeekFrom::Start(0))?;
+    let mut ranges = Vec::new();
+    let mut serial = None;
+    let mut header_packets = None;
+    let mut position = 0;
+    while position + 27 <= len {
+        let page = read_page(file)?;
+        let data_position = position + 27 + page.segments.len() as u64;
+        position = data_position + page.data.len() as u64;
+        if *serial.get_or_insert(page.serial) != page.serial {
+            continue;
+        }
+        // Vorbis has three header packets and Opus has two, and the
+        // audio always starts on a new page after them.
+        let remaining = header_packets.get_or_insert(if page.data.starts_with(b"OpusHead") {
+            2
+        } else {
+            3
+        });
+        if *remaining == 0 {
+            ranges.push((data_position, page.data.len() as u64));
+        } else {
+            let packets = page.segments.iter().filter(|&&segment| segment < 255).count();
+            *remaining -= packets.min(*remaining);
+        }
+    }
+    Ok(ranges)
+}
+
+pub fn read<R: Read + Seek>(file: &mut R) -> Result<(Tags, Properties)> {
+    let len = stream_len(file)?;
+    file.seek(SeekFrom::Start(0))?;
+    let (serial, packets) = read_packets(file, 2)?;
+    let identification = &packets[0];
+    let comments = &packets[1];
+    let granule_position = last_granule_position(file, serial)?;
+    let mut tags = Tags::default();
+
+    if identification.starts_with(b"\x01vorbis") && identification.len() >= 28 {
+        let sample_rate = le_u32(&identification[12..16]) as u64;
+        let nominal_bitrate = le_u32(&identification[20..24]) as i32;
+        if comments.starts_with(b"\x03vorbis") {
+            xiph::parse_comments(&comments[7..], &mut tags);
+        }
+        let duration = if sample_rate > 0 {
+            (granule_position * 1000 / sample_rate) as i64
+        } else {
+            0
+        };
+        Ok((
+            tags,
+            Properties {
+                file_type: TrackFileType::Vorbis,
+                duration,
+                bitrate: if nominal_bitrate > 0 {
+                    nominal_bitrate / 1000
+                } else {
+                    average_bitrate(len, duration)
+                },
+                sample_rate: sample_rate as i32,
+            },
+        ))
+    } else if identification.starts_with(b"OpusHead") && identification.len() >= 19 {
+        let pre_skip = le_u16(&identification[10..12]) as u64;
+        if comments.starts_with(b"OpusTags") {
+            xiph::parse_comments(&comments[8..], &mut tags);
+        }
+        let duration = (granule_position.saturating_sub(pre_skip) * 1000 / OPUS_RATE) as i64;
+        Ok((
+            tags,
+            Properties {
+                file_type: TrackFileType::Opus,
+                duration,
+                bitrate: average_bitrate(len, duration),
+                sample_rate: OPUS_RATE as i32,
+            },
+        ))
+    } else {
+        Err(invalid("Unsupported Ogg stream."))
+    }
+}
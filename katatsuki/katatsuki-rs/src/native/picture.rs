@@ -0,0 +1,61 @@
+//! Reads the dimensions of embedded cover art from the image header,
+//! without decoding the image.
+
+use super::{be_u16, be_u32, le_u16, le_u32};
+
+/// Gets the (width, height) of a JPEG, PNG, GIF or BMP image.
+pub fn dimensions(data: &[u8]) -> Option<(i32, i32)> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        jpeg_dimensions(data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") && data.len() >= 24 {
+        Some((be_u32(&data[16..20]) as i32, be_u32(&data[20..24]) as i32))
+    } else if data.starts_with(b"GIF8") && data.len() >= 10 {
+        Some((le_u16(&data[6..8]) as i32, le_u16(&data[8..10]) as i32))
+    } else if data.starts_with(b"BM") && data.len() >= 26 {
+        Some((
+            le_u32(&data[18..22]) as i32,
+            (le_u32(&data[22..26]) as i32).abs(),
+        ))
+    } else {
+        None
+    }
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(i32, i32)> {
+    let mut position = 2;
+    while position + 4 <= data.len() {
+        if data[position] != 0xFF {
+            return None;
+        }
+        let marker = data[position + 1];
+        match marker {
+            // Fill bytes before a marker.
+            0xFF => {
+                position += 1;
+                continue;
+            }
+            // Markers without a length.
+            0x01 | 0xD0..=0xD7 => {
+                position += 2;
+                continue;
+            }
+            // End of image or start of scan without a frame header.
+            0xD9 | 0xDA => return None,
+            _ => (),
+        }
+        let length = be_u16(&data[position + 2..position + 4]) as usize;
+        match marker {
+            // Start of frame, except DHT, JPG and DAC, which share the range.
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                if position + 9 > data.len() {
+                    return None;
+                }
+                let height = be_u16(&data[position + 5..position + 7]) as i32;
+                let width = be_u16(&data[position + 7..position + 9]) as i32;
+                return Some((width, height));
+            }
+            _ => position += 2 + length,
+        }
+    }
+    None
+}
@@ -0,0 +1,142 @@
+//! Xiph comments (Vorbis comments) and FLAC picture blocks,
+//! shared by FLAC and Ogg.
+
+use super::picture;
+use super::{be_u32, le_u32, parse_number, parse_total, parse_year, FrontCover, Tags};
+
+/// The picture type of the front cover.
+const FRONT_COVER: u32 = 3;
+
+/// Parses a Vorbis comment block, starting from the vendor string length.
+pub fn parse_comments(data: &[u8], tags: &mut Tags) {
+    let mut position = 0;
+    let vendor_len = match read_len(data, &mut position) {
+        Some(len) => len,
+        None => return,
+    };
+    position += vendor_len;
+    let count = match read_len(data, &mut position) {
+        Some(count) => count,
+        None => return,
+    };
+
+    let mut album_artists = Vec::new();
+    for _ in 0..count {
+        let len = match read_len(data, &mut position) {
+            Some(len) if position + len <= data.len() => len,
+            _ => break,
+        };
+        let comment = String::from_utf8_lossy(&data[position..position + len]).into_owned();
+        position += len;
+
+        let mut parts = comment.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.to_uppercase(), value),
+            _ => continue,
+        };
+        match key.as_ref() {
+            "TITLE" => Tags::set(&mut tags.title, value),
+            "ARTIST" => Tags::set(&mut tags.artist, value),
+            "ALBUM" => Tags::set(&mut tags.album, value),
+            "ALBUMARTIST" | "ALBUM ARTIST" => if !value.trim().is_empty() {
+                album_artists.push(value.to_owned())
+            },
+            "DATE" | "YEAR" => if tags.year == 0 {
+                tags.year = parse_year(value)
+            },
+            "TRACKNUMBER" => if tags.track_number == 0 {
+                tags.track_number = parse_number(value)
+            },
+            "DISCNUMBER" => if tags.disc_number == 0 {
+                tags.disc_number = parse_number(value);
+                if tags.disc_count == 0 {
+                    tags.disc_count = parse_total(value)
+                }
+            },
+            "DISCTOTAL" | "TOTALDISCS" => tags.disc_count = parse_number(value),
+            "MUSICBRAINZ_TRACKID" => Tags::set(&mut tags.musicbrainz_track_id, value),
+            "METADATA_BLOCK_PICTURE" => if tags.front_cover.is_none() {
+                if let Some(block) = decode_base64(value) {
+                    tags.front_cover = parse_picture(&block);
+                }
+            },
+            _ => (),
+        }
+    }
+    if tags.album_artists.is_empty() {
+        tags.album_artists = album_artists;
+    }
+}
+
+fn read_len(data: &[u8], position: &mut usize) -> Option<usize> {
+    if *position + 4 > data.len() {
+        None
+    } else {
+        let len = le_u32(&data[*position..*position + 4]) as usize;
+        *position += 4;
+        Some(len)
+    }
+}
+
+/// Parses a FLAC picture block, returning the front cover if the
+/// picture is one.
+pub fn parse_picture(block: &[u8]) -> Option<FrontCover> {
+    let field = |position: usize| {
+        if position + 4 > block.len() {
+            None
+        } else {
+            Some(be_u32(&block[position..position + 4]))
+        }
+    };
+
+    if field(0)? != FRONT_COVER {
+        return None;
+    }
+    let mime_len = field(4)? as usize;
+    let description_position = 8 + mime_len;
+    let description_len = field(description_position)? as usize;
+    let properties = description_position + 4 + description_len;
+    let header_width = field(properties)? as i32;
+    let header_height = field(properties + 4)? as i32;
+    let data_len = field(properties + 16)? as usize;
+    let data_start = properties + 20;
+    let data = &block[data_start.min(block.len())..(data_start + data_len).min(block.len())];
+
+    // Prefer the dimensions of the actual image, since some taggers
+    // leave the ones in the block header empty.
+    let (width, height) = picture::dimensions(data).unwrap_or((header_width, header_height));
+    Some(FrontCover { width, height })
+}
+
+fn base64_value(c: u8) -> Option<u32> {
+    match c {
+        b'A'..=b'Z' => Some((c - b'A') as u32),
+        b'a'..=b'z' => Some((c - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((c - b'0') as u32 + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &c in input.as_bytes() {
+        if c == b'=' {
+            break;
+        }
+        if c == b'\r' || c == b'\n' {
+            continue;
+        }
+        buffer = buffer << 6 | base64_value(c)?;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(output)
+}
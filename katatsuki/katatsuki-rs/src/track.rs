@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 use std::str::FromStr;
 
-#[derive(Debug, Primitive)]
+#[derive(Debug, Clone, PartialEq, Primitive)]
 pub enum TrackFileType {
     Unknown = 0,
 
@@ -70,6 +70,8 @@ pub struct Track {
     pub sample_rate: i32,
     pub source: String,
     pub disc_number: i32,
+    /// The number of discs in the release, or 0 if the tags don't say.
+    pub disc_count: i32,
     pub duration: i32,
     pub updated: String,
 }
@@ -20,6 +20,7 @@ namespace libkatatsuki {
         public int SampleRate { get; set; }
         public uint DiscNumber { get; }
         public long Duration { get; set; }
+        public uint DiscCount { get; set; }
         public CTrack(Track track) {
             this.Title = Marshal.StringToCoTaskMemUTF8(track.Title);
             this.Artist = Marshal.StringToCoTaskMemUTF8(track.Artist);
@@ -35,6 +36,7 @@ namespace libkatatsuki {
             this.SampleRate = track.SampleRate;
             this.DiscNumber = track.DiscNumber;
             this.Duration = track.Duration;
+            this.DiscCount = track.DiscCount;
             this.FileType = (uint)track.FileType;
         }
     }
@@ -23,6 +23,7 @@ namespace libkatatsuki
         public int Bitrate { get; set; }
         public int SampleRate { get; set; }
         public uint DiscNumber { get; }
+        public uint DiscCount { get; set; }
         public long Duration { get; set; }
         public TrackFileType FileType { get; set; }
 
@@ -42,6 +43,7 @@ namespace libkatatsuki
                 this.MusicBrainzTrackId = file.Tag.MusicBrainzTrackId;
                 this.Title = file.Tag.Title;
                 this.DiscNumber = file.Tag.Disc == 0 ? 1 : file.Tag.Disc;
+                this.DiscCount = file.Tag.DiscCount;
                 var frontAlbum = from picture in file.Tag.Pictures
                                  where picture.Type == TagLib.PictureType.FrontCover
                                  select picture;
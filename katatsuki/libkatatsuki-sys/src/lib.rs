@@ -23,6 +23,7 @@ pub struct katatsuki_Track {
     pub SampleRate: c_int,
     pub DiscNumber: c_uint,
     pub Duration: c_longlong,
+    pub DiscCount: c_uint,
 }
 
 // #[link(name = "libkatatsuki", kind = "static")]
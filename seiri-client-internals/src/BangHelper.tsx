@@ -12,7 +12,8 @@ take a parameter enclosed in curly braces, for example
 
 **!t{Hotel California}**.
 
-Searching without a bang is equivalent to the *Full Text Search* bang (**!q**). 
+Searching without a bang is equivalent to the *Full Text Search* bang (**!q**), which finds every word you type at
+the start of a word, and puts the best matches first. Wrap words in double quotes to search for them as a phrase. 
 ## Search Bangs
 The following bangs accept a search term case insensitively, and can be capitalized for case-sensitive exact matches.
 
@@ -56,19 +57,29 @@ shorthand for **true**, for example **!dup\`** translates to **!dup{true}**.
 **!c** *Tracks have cover art tag* 
 
 ## Numerical Tags
-These tags take a number, and are used to look up things that are greater than (**gt**) or less than (**lt**)
-a value.
+These tags take a comparison. A number on its own must match exactly, and can also be prefixed with
+**<**, **<=**, **>**, **>=** or **=**, or written as an inclusive range. For example, **!br{>=320}** looks for
+tracks with a bitrate of at least 320kbps, and **!y{1980..1989}** looks for tracks from the 80s.
 
-**!brlt / !brgt** *Bitrate* 
+**!br** *Bitrate* 
 
-**!cwlt / !cwgt** *Cover Art Width (pixels)* 
+**!cw / !ch** *Cover Art Width / Height (pixels)* 
 
-**!chlt / !chgt** *Cover Art Height (pixels)* 
+**!y** *Year* 
+
+**!sr** *Sample Rate (Hz)* 
+
+**!tn** *Track Number* 
+
+**!dn** *Disc Number* 
+
+Each tag can also be followed by **lt** or **gt** as shorthand for less than or greater than, for example **!brlt{320}**.
 
 ## Duration Tags
-These tags take in a duration in the form **0h0m0s**, where **0** is a placeholder for any number. 
+These tags take in a duration in the form **0h0m0s**, where **0** is a placeholder for any number, and can be
+compared like the numerical tags, for example **!d{3m..5m}**. 
 
-**!dlt / !dgt** *Track duration* 
+**!d / !dlt / !dgt** *Track duration* 
 
 ## Updated 
 
@@ -82,6 +93,12 @@ Bangs can also be combined usin the grouping bang, and logical operators.
 For example, **!!{!t{Hotel California} & !ar{The Eagles}} | !!{!t{Hey Jude} & !ar{The Beatles}}** will look for
 tracks with the title "Hotel California" and the artist "The Eagles", or tracks with the title "Hey Jude" and 
 the artist "The Beatles".
+
+A bang can be negated by putting a minus sign (**-**) in front of it, or by wrapping it in the not bang
+(**!not{...}**). For example, **!ar{Queen} & -!s{YouTube}** will look for tracks by Queen that did not come from YouTube.
+
+Negation is applied first, then **&**, then **|**, so **!ar{Queen} | !ar{ABBA} & !c{false}** looks for every track
+by Queen, and the tracks by ABBA without cover art.
     `}
     </Markdown>
   </div>
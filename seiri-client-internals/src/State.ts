@@ -3,4 +3,6 @@ export default interface State {
     tracks: Track[];
     query: string;
     count: number;
+    sortBy: string;
+    sortDirection: "ASC" | "DESC";
 }
\ No newline at end of file
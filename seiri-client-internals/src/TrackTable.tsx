@@ -1,4 +1,4 @@
-import { orderBy as _, range } from "lodash";
+import { range } from "lodash";
 import * as Mousetrap from "mousetrap";
 import * as React from "react";
 import Draggable, { DraggableData } from "react-draggable";
@@ -7,7 +7,6 @@ import {
 
   Column,
   RowMouseEventHandlerParams,
-  SortDirection,
   SortDirectionType,
   SortIndicator,
   Table,
@@ -15,7 +14,7 @@ import {
   WindowScroller
 } from "react-virtualized";
 import "react-virtualized/styles.css"; // only needs to be imported once
-import { updateSelectedCount, updateTracksTick } from "./actions";
+import { updateSelectedCount, updateSort, updateTracksTick } from "./actions";
 import ElectronWindow from "./ElectronWindow";
 import seiri from "./seiri-neon";
 import "./Table.css";
@@ -26,15 +25,14 @@ declare var window: ElectronWindow;
 interface TrackTableProps {
   tracks: Track[];
   query: string;
+  sortBy: string;
+  sortDirection: "ASC" | "DESC";
   dispatch: Dispatch<any>;
   hidden: boolean;
 }
 
 interface TrackTableState {
   widths: { [tableKey: string]: number };
-  sortBy: string;
-  sortDirection: SortDirectionType;
-  sortedList: Track[];
   selected: { [index: number]: boolean | undefined };
   lastSelected: number | undefined;
 }
@@ -45,8 +43,6 @@ const TOTAL_WIDTH = 3000;
 class TrackTable extends React.Component<TrackTableProps, TrackTableState> {
   constructor(props: TrackTableProps) {
     super(props);
-    const sortBy = "album";
-    const sortDirection = SortDirection.ASC;
     this.state = {
       // tslint:disable:object-literal-sort-keys
       widths: {
@@ -67,16 +63,13 @@ class TrackTable extends React.Component<TrackTableProps, TrackTableState> {
         updated: 0.07,
         filePath: 0.7
       },
-      sortBy,
-      sortDirection,
-      sortedList: this.sortList({ list: this.props.tracks, sortBy, sortDirection }),
       selected: [],
       lastSelected: undefined
     };
     Mousetrap.bind(['command+r', 'ctrl+r'], () => {
       // tslint:disable-next-line:no-console
       console.log("bound!")
-      const tracksToRefresh = this.state.sortedList.filter(
+      const tracksToRefresh = this.props.tracks.filter(
         (track, index) => this.state.selected[index] === true
       ).map(track => track.filePath)
 
@@ -105,15 +98,14 @@ class TrackTable extends React.Component<TrackTableProps, TrackTableState> {
   }
 
   public componentWillReceiveProps(newProps: TrackTableProps) {
-    const { sortBy, sortDirection } = this.state;
-    if (newProps.query !== this.props.query || newProps.tracks.length !== this.props.tracks.length) {
+    if (newProps.query !== this.props.query
+      || newProps.tracks.length !== this.props.tracks.length
+      || newProps.sortBy !== this.props.sortBy
+      || newProps.sortDirection !== this.props.sortDirection) {
       this.setState({
-        sortedList: this.sortList({ list: newProps.tracks, sortBy, sortDirection }),
         selected: [],
         lastSelected: undefined
       });
-    } else {
-      this.setState({ sortedList: this.sortList({ list: newProps.tracks, sortBy, sortDirection }) });
     }
   }
 
@@ -134,7 +126,7 @@ class TrackTable extends React.Component<TrackTableProps, TrackTableState> {
   }
 
   private rowGetter = ({ index }: { index: number }) =>
-    this.getDatum(this.state.sortedList, index);
+    this.getDatum(this.props.tracks, index);
 
   private getDatum(list: Track[], index: number) {
     return list[index] || {};
@@ -147,25 +139,7 @@ class TrackTable extends React.Component<TrackTableProps, TrackTableState> {
       sortBy: string;
       sortDirection: SortDirectionType;
     }) {
-    const sortedList = this.sortList({ list: this.props.tracks, sortBy, sortDirection });
-
-    this.setState({ sortBy, sortDirection, sortedList });
-  }
-
-  private sortList({
-    list,
-    sortBy,
-    sortDirection
-  }: {
-      list: Track[];
-      sortBy: string;
-      sortDirection: SortDirectionType;
-    }) {
-    return _(
-      list,
-      [sortBy, "album", "tracknumber"],
-      sortDirection.toLowerCase()
-    );
+    this.props.dispatch(updateSort.action({ sortBy, sortDirection }));
   }
 
   private headerResizeHandler(dataKey: string, event: MouseEvent, { deltaX }: DraggableData) {
@@ -376,8 +350,8 @@ class TrackTable extends React.Component<TrackTableProps, TrackTableState> {
               onRowDoubleClick={this.handleDoubleClick}
               onRowClick={this.handleClick}
               sort={this.sort}
-              sortBy={this.state.sortBy}
-              sortDirection={this.state.sortDirection}
+              sortBy={this.props.sortBy}
+              sortDirection={this.props.sortDirection}
               rowGetter={this.rowGetter}
             >
               <Column
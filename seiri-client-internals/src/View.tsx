@@ -4,9 +4,10 @@ import { connect, Dispatch } from "react-redux";
 import { updateQuery, updateTracksTick } from "./actions";
 import Helper from "./BangHelper";
 import ElectronWindow from "./ElectronWindow";
+import seiri from "./seiri-neon";
 import State from "./State";
 import TrackTable from "./TrackTable";
-import { Track } from "./types";
+import { QueryError, Track } from "./types";
 import "./View.css";
 
 declare var window: ElectronWindow;
@@ -14,14 +15,24 @@ interface ViewProps {
   tracks: Track[];
   query: string;
   count: number;
+  sortBy: string;
+  sortDirection: "ASC" | "DESC";
   dispatch?: Dispatch<any>;
 }
 
 interface ViewState {
   showBangs: boolean;
+  queryText: string;
+  queryError: QueryError | null;
 }
 const mapStateToProps = (state: State): ViewProps => {
-  return { tracks: state.tracks, query: state.query, count: state.count };
+  return {
+    tracks: state.tracks,
+    query: state.query,
+    count: state.count,
+    sortBy: state.sortBy,
+    sortDirection: state.sortDirection
+  };
 };
 
 const mapDispatchToProps = (
@@ -42,6 +53,8 @@ class View extends React.Component<ViewProps, ViewState> {
     }, 0);
     this.state = {
       showBangs: false,
+      queryText: "",
+      queryError: null,
     }
     window.addEventListener("keydown", event => {
       if (!(event.ctrlKey || event.altKey)) {
@@ -72,6 +85,7 @@ class View extends React.Component<ViewProps, ViewState> {
         <button className="btn-quit" onClick={() => this.hide()}>
             &#xe711;
           </button>
+          <div className="bang-input-container">
           <DebounceInput
             placeholder={
               'Type to start searching. Type "??bangs" for bang reference. Ctrl+R to refresh.'
@@ -85,12 +99,19 @@ class View extends React.Component<ViewProps, ViewState> {
             // tslint:disable-next-line:no-console
             onChange={(e: React.ChangeEvent<HTMLInputElement>) => {
               // tslint:disable-next-line:no-console
+              this.checkQuery(e.target.value);
               this.props.dispatch!(
                 updateQuery.action({ query: e.target.value })
               );
             }}
           />
-        <div className="tracks">{this.props.tracks.length + " Tracks " + this.props.count + " Selected"}</div>
+          {this.renderQueryError()}
+          </div>
+        {this.state.queryError
+          ? <div className="tracks bang-error-message" title={this.state.queryError.message}>
+              {this.state.queryError.expected ? "Expected " + this.state.queryError.expected : this.state.queryError.message}
+            </div>
+          : <div className="tracks">{this.props.tracks.length + " Tracks " + this.props.count + " Selected"}</div>}
         </div>
         <div className="main-bar-clear" />
 
@@ -99,6 +120,8 @@ class View extends React.Component<ViewProps, ViewState> {
             hidden={this.state.showBangs}
             tracks={this.props.tracks}
             query={this.props.query}
+            sortBy={this.props.sortBy}
+            sortDirection={this.props.sortDirection}
             dispatch={this.props.dispatch!}
           />
           <Helper hidden={!this.state.showBangs} />
@@ -106,6 +129,36 @@ class View extends React.Component<ViewProps, ViewState> {
       </div>
     );
   }
+  private checkQuery(query: string) {
+    try {
+      this.setState({ queryText: query, queryError: seiri.checkQuery(query) });
+    } catch (err) {
+      // tslint:disable-next-line:no-console
+      console.log(err);
+    }
+  }
+
+  /**
+   * Underlines the part of the query that the error is about, by
+   * laying the query over the input with only that part visible.
+   */
+  private renderQueryError() {
+    const error = this.state.queryError;
+    if (!error) {
+      return null;
+    }
+    const query = this.state.queryText;
+    // An error at the end of the query has no width, so give it a space to underline.
+    const marked = error.end > error.start ? query.substring(error.start, error.end) : "\u00a0";
+    return (
+      <div className="bang-input bang-error" aria-hidden={true}>
+        {query.substring(0, error.start)}
+        <span className="bang-error-span">{marked}</span>
+        {query.substring(error.end)}
+      </div>
+    );
+  }
+
   private hide() {
     window.require<any>('electron').remote.getCurrentWindow().hide();
   }
@@ -24,18 +24,28 @@ export interface UpdateTracksTimerTick {
     type: "UPDATE_TRACKS_TICK",
 }
 
+export interface UpdateSort {
+    type: "UPDATE_SORT",
+    sortBy: string,
+    sortDirection: "ASC" | "DESC",
+}
+
 export interface UpdateSelected {
     type: "UPDATE_SELECTED_COUNT",
     count: number,
 }
 
+// Tracks are sorted by seiri, by the column of the table that was clicked.
+const sortSpec = ({ sortBy, sortDirection }: { sortBy: string, sortDirection: "ASC" | "DESC" }) =>
+    `${sortBy} ${sortDirection.toLowerCase()}`
+
 export const updateSelectedCount = actionCreator<{count: number}>("UPDATE_SELECTED_COUNT")
 
 export const updateTracks = actionCreator<{tracks: Track[]}>("UPDATE_TRACKS")
 
-export const updateQuery = createAsync<{query: string}, {}>("UPDATE_QUERY", (query, dispatch) => {
+export const updateQuery = createAsync<{query: string}, {}>("UPDATE_QUERY", (query, dispatch, getState) => {
     try {
-        const tracks = seiri.queryTracks(query.query)
+        const tracks = seiri.queryTracks(query.query, sortSpec(getState()))
         // tslint:disable-next-line:no-console
         dispatch(updateTracks(tracks))
     } catch {
@@ -48,7 +58,7 @@ export const updateQuery = createAsync<{query: string}, {}>("UPDATE_QUERY", (que
 export const updateTracksTick = createAsync<{}, {}>("UPDATE_TRACKS_TICK", (query, dispatch, getState) => {
     const state = getState();
     try {
-        const tracks = seiri.queryTracks(state.query)
+        const tracks = seiri.queryTracks(state.query, sortSpec(state))
          // tslint:disable-next-line:no-console
         console.log("tick!")
         dispatch(updateTracks(tracks))
@@ -60,4 +70,15 @@ export const updateTracksTick = createAsync<{}, {}>("UPDATE_TRACKS_TICK", (query
     window.setTimeout(() => dispatch(updateTracksTick.action()), 30000)
     return { type: "UPDATE_TRACKS_TICK" }
 })
-export type SeiriAction = UpdateQuery | UpdateTracks;
\ No newline at end of file
+export const updateSort = createAsync<{sortBy: string, sortDirection: "ASC" | "DESC"}, {}>("UPDATE_SORT", (sort, dispatch, getState) => {
+    try {
+        const tracks = seiri.queryTracks(getState().query, sortSpec(sort))
+        dispatch(updateTracks(tracks))
+    } catch (err) {
+        // tslint:disable-next-line:no-console
+        console.log(err);
+    }
+    return { type: "UPDATE_SORT", ...sort }
+})
+
+export type SeiriAction = UpdateQuery | UpdateTracks | UpdateSort;
\ No newline at end of file
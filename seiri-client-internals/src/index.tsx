@@ -9,11 +9,13 @@ import State from "./State"
 import { applyMiddleware, createStore } from "redux";
 import { composeWithDevTools } from "redux-devtools-extension";
 import { reducerWithInitialState } from 'typescript-fsa-reducers';
-import { updateQuery, updateSelectedCount, updateTracks, updateTracksTick } from "./actions";
+import { updateQuery, updateSelectedCount, updateSort, updateTracks, updateTracksTick } from "./actions";
 
 const initialState: State = {
   count: 0,
   query: "",
+  sortBy: "album",
+  sortDirection: "ASC",
   tracks: [],
 };
 
@@ -30,6 +32,11 @@ const reducer = reducerWithInitialState(initialState)
   ...state,
   query: params.query
 }))
+.case(updateSort.async.done, (state, {params}) => ({
+  ...state,
+  sortBy: params.sortBy,
+  sortDirection: params.sortDirection
+}))
 .case(updateTracksTick.async.done, (state) => ({
   ...state,
 }));;
@@ -1,13 +1,23 @@
 import ElectronWindow from "./ElectronWindow";
-import { Track } from "./types";
+import { Track, Album, RescanSummary, RehomeSummary, Operation, QueryError, Completion, QueryExplanation, LintIssue } from "./types";
 
 declare var window : ElectronWindow;
 
 const seiriInstance = window.require<Seiri>("seiri-neon");
 
 interface Seiri {
-    queryTracks: (bang: string) => { tracks: Track[] };
+    queryTracks: (bang: string, sort?: string) => { tracks: Track[] };
+    queryAlbums: (bang: string, sort?: string) => { albums: Album[] };
+    checkQuery: (bang: string) => QueryError | null;
+    completeQuery: (bang: string, cursor: number) => { completions: Completion[] };
+    explainQuery: (bang: string, sort?: string) => QueryExplanation;
+    lintLibrary: () => { issues: LintIssue[] };
     refreshTracks: (filePaths: string[]) => void;
+    rescanLibrary: () => RescanSummary;
+    rehomeLibrary: () => RehomeSummary;
+    getOperations: (limit: number) => { operations: Operation[] };
+    rollbackOperation: (id: number) => void;
+    rollbackBatch: (batch: number) => void;
 }
 
 export default seiriInstance;
@@ -15,10 +15,81 @@ export interface Track {
     sampleRate: number;
     source: string;
     discnumber: number;
+    discCount: number;
     duration: number;
     fileType: TrackFileType;
   }
 
+export interface Album {
+    albumArtists: string[];
+    album: string;
+    year: number;
+    trackCount: number;
+    duration: number;
+    fileTypes: TrackFileType[];
+    hasFrontCover: boolean;
+    problems: string[];
+    tracks: Track[];
+  }
+
+export interface QueryError {
+    message: string;
+    start: number;
+    end: number;
+    expected: string | null;
+  }
+
+export interface Completion {
+    text: string;
+    description: string;
+    start: number;
+    end: number;
+  }
+
+export interface QueryExplanation {
+    query: string;
+    params: { name: string, value: string }[];
+    plan: string[];
+  }
+
+export interface LintIssue {
+    kind: "year" | "split" | "case" | "whitespace";
+    description: string;
+    folders: string[];
+    filePaths: string[];
+  }
+
+export interface RescanSummary {
+    added: number;
+    removed: number;
+    moved: number;
+    updated: number;
+    unchanged: number;
+    hashed: number;
+    skipped: number;
+    errors: string[];
+    batch: number | null;
+  }
+
+export interface RehomeSummary {
+    moved: number;
+    unchanged: number;
+    missing: number;
+    errors: string[];
+    batch: number | null;
+  }
+
+export interface Operation {
+    id: number;
+    batch: number;
+    kind: "move" | "removedir";
+    oldPath: string;
+    newPath: string | null;
+    reason: "newtrack" | "nontrack" | "reconsider" | "rehome" | "rollback";
+    timestamp: string;
+    rolledBack: boolean;
+  }
+
   export enum TrackFileType {
 	Unknown = 0,
 
@@ -0,0 +1,268 @@
+use bangs::Bang;
+use database;
+use error::Result;
+use katatsuki::{FromPrimitive, ToPrimitive, Track, TrackFileType};
+use naming::NamingTemplate;
+use rusqlite::Connection;
+use sort::SortSpec;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+/// The tracks of an album, and what they have in common.
+/// An album is every track with the same album artists and album title.
+#[derive(Debug)]
+pub struct Album {
+    pub album_artists: Vec<String>,
+    pub album: String,
+    /// The year of the first track of the album.
+    pub year: i32,
+    pub track_count: i32,
+    /// The total duration of the tracks, in milliseconds.
+    pub duration: i64,
+    /// Every file type of the tracks, in the order they first appear.
+    pub file_types: Vec<TrackFileType>,
+    /// Whether every track has front cover art.
+    pub has_front_cover: bool,
+    /// The tracks, in order of disc and track number.
+    pub tracks: Vec<Track>,
+}
+
+impl Album {
+    fn new(tracks: Vec<Track>) -> Album {
+        let mut file_types = Vec::<i32>::new();
+        for track in &tracks {
+            let file_type = track.file_type.to_i32().unwrap();
+            if !file_types.contains(&file_type) {
+                file_types.push(file_type);
+            }
+        }
+        Album {
+            album_artists: tracks[0].album_artists.clone(),
+            album: tracks[0].album.to_owned(),
+            year: tracks[0].year,
+            track_count: tracks.len() as i32,
+            duration: tracks.iter().map(|track| track.duration as i64).sum(),
+            file_types: file_types
+                .into_iter()
+                .map(|file_type| TrackFileType::from_i32(file_type).unwrap_or(TrackFileType::Unknown))
+                .collect(),
+            has_front_cover: tracks.iter().all(|track| track.has_front_cover),
+            tracks,
+        }
+    }
+
+    /// Whether the album has tracks of more than one file type.
+    pub fn has_mixed_formats(&self) -> bool {
+        self.file_types.len() > 1
+    }
+
+    /// What is wrong with the numbering of the tracks of the album.
+    /// Only the tracks of the album that are included are checked.
+    pub fn problems(&self) -> Vec<AlbumProblem> {
+        find_album_problems(
+            &self.tracks
+                .iter()
+                .map(TrackPosition::of)
+                .collect::<Vec<TrackPosition>>(),
+        )
+    }
+}
+
+/// Where a track falls in its album.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPosition {
+    pub disc_number: i32,
+    pub track_number: i32,
+    /// The number of discs of the album, or 0 if it is not known.
+    pub disc_count: i32,
+}
+
+impl TrackPosition {
+    pub fn of(track: &Track) -> TrackPosition {
+        TrackPosition {
+            disc_number: track.disc_number,
+            track_number: track.track_number,
+            disc_count: track.disc_count,
+        }
+    }
+
+    /// The disc of the track, where a track without a disc number
+    /// is on the first disc.
+    fn disc(&self) -> i32 {
+        self.disc_number.max(1)
+    }
+}
+
+/// Something wrong with the numbering of the tracks of an album,
+/// which usually means that tracks are missing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlbumProblem {
+    /// Tracks of the disc that have no track number.
+    UnnumberedTracks { disc: i32, count: i32 },
+    /// A track number that more than one track of the disc has.
+    DuplicateTrackNumber { disc: i32, track_number: i32 },
+    /// Track numbers of the disc that come before its last track,
+    /// but that no track has.
+    MissingTracks { disc: i32, track_numbers: Vec<i32> },
+    /// Discs that come before the last disc, or that the disc count
+    /// says there are, but that no track is on.
+    MissingDiscs(Vec<i32>),
+    /// Discs past the number of discs that the album has.
+    ExtraDiscs(Vec<i32>),
+}
+
+/// Names numbered things, like `track 1` or `tracks 2, 4, 5`.
+fn numbered(noun: &str, numbers: &[i32]) -> String {
+    let numbers = numbers
+        .iter()
+        .map(|number| number.to_string())
+        .collect::<Vec<String>>();
+    if numbers.len() == 1 {
+        format!("{} {}", noun, numbers[0])
+    } else {
+        format!("{}s {}", noun, numbers.join(", "))
+    }
+}
+
+impl fmt::Display for AlbumProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AlbumProblem::UnnumberedTracks { disc, count: 1 } => {
+                write!(f, "Disc {} has a track without a track number", disc)
+            }
+            AlbumProblem::UnnumberedTracks { disc, count } => {
+                write!(f, "Disc {} has {} tracks without a track number", disc, count)
+            }
+            AlbumProblem::DuplicateTrackNumber { disc, track_number } => write!(
+                f,
+                "Disc {} has more than one track {}",
+                disc, track_number
+            ),
+            AlbumProblem::MissingTracks {
+                disc,
+                ref track_numbers,
+            } => write!(f, "Disc {} is missing {}", disc, numbered("track", track_numbers)),
+            AlbumProblem::MissingDiscs(ref discs) => {
+                write!(f, "Missing {}", numbered("disc", discs))
+            }
+            AlbumProblem::ExtraDiscs(ref discs) => {
+                write!(f, "Has {} past the disc count", numbered("disc", discs))
+            }
+        }
+    }
+}
+
+/// Finds what is wrong with the numbering of the tracks of an album.
+/// The track numbers of each disc should run from 1 to its last track
+/// once each, and the discs should run from 1 to the disc count, or to
+/// the last disc if the disc count is not known. `!incomplete` matches
+/// the same albums in the database.
+pub fn find_album_problems(positions: &[TrackPosition]) -> Vec<AlbumProblem> {
+    let mut discs = BTreeMap::<i32, Vec<i32>>::new();
+    for position in positions {
+        discs
+            .entry(position.disc())
+            .or_insert_with(Vec::new)
+            .push(position.track_number);
+    }
+
+    let mut problems = Vec::new();
+    for (&disc, track_numbers) in &discs {
+        let unnumbered = track_numbers.iter().filter(|&&number| number < 1).count();
+        if unnumbered > 0 {
+            problems.push(AlbumProblem::UnnumberedTracks {
+                disc,
+                count: unnumbered as i32,
+            });
+        }
+        let mut seen = BTreeSet::new();
+        let mut duplicates = BTreeSet::new();
+        for &number in track_numbers.iter().filter(|&&number| number >= 1) {
+            if !seen.insert(number) {
+                duplicates.insert(number);
+            }
+        }
+        for track_number in duplicates {
+            problems.push(AlbumProblem::DuplicateTrackNumber { disc, track_number });
+        }
+        let last = seen.iter().next_back().cloned().unwrap_or(0);
+        let missing = (1..last)
+            .filter(|number| !seen.contains(number))
+            .collect::<Vec<i32>>();
+        if !missing.is_empty() {
+            problems.push(AlbumProblem::MissingTracks {
+                disc,
+                track_numbers: missing,
+            });
+        }
+    }
+
+    let last_disc = discs.keys().next_back().cloned().unwrap_or(0);
+    let disc_count = positions
+        .iter()
+        .map(|position| position.disc_count)
+        .max()
+        .unwrap_or(0);
+    let missing_discs = (1..last_disc.max(disc_count) + 1)
+        .filter(|disc| !discs.contains_key(disc))
+        .collect::<Vec<i32>>();
+    if !missing_discs.is_empty() {
+        problems.push(AlbumProblem::MissingDiscs(missing_discs));
+    }
+    if disc_count > 0 {
+        let extra_discs = discs
+            .keys()
+            .cloned()
+            .filter(|&disc| disc > disc_count)
+            .collect::<Vec<i32>>();
+        if !extra_discs.is_empty() {
+            problems.push(AlbumProblem::ExtraDiscs(extra_discs));
+        }
+    }
+    problems
+}
+
+/// Groups tracks into albums, in the order that each album first
+/// appears in the tracks.
+pub fn group_albums(tracks: Vec<Track>) -> Vec<Album> {
+    let mut indices = HashMap::<(Vec<String>, String), usize>::new();
+    let mut grouped = Vec::<Vec<Track>>::new();
+    for track in tracks {
+        let key = (track.album_artists.clone(), track.album.to_owned());
+        let index = *indices.entry(key).or_insert_with(|| {
+            grouped.push(Vec::new());
+            grouped.len() - 1
+        });
+        grouped[index].push(track);
+    }
+    grouped
+        .into_iter()
+        .map(|mut tracks| {
+            tracks.sort_by_key(|track| (track.disc_number, track.track_number));
+            Album::new(tracks)
+        })
+        .collect()
+}
+
+/// Queries the albums of the tracks that match the bang. Only the
+/// matching tracks of an album are included, so album bangs like
+/// `!alc` can be used to match whole albums.
+///
+/// Albums are ordered by where their first track falls in the sort,
+/// and the limit and offset count albums rather than tracks.
+pub fn query_albums(
+    bang: Bang,
+    conn: &Connection,
+    template: &NamingTemplate,
+    sort: &SortSpec,
+    limit: Option<i32>,
+    offset: Option<i32>,
+) -> Result<Vec<Album>> {
+    let tracks = database::query_tracks(bang, conn, template, sort, None, None)?;
+    let albums = group_albums(tracks).into_iter().skip(offset.unwrap_or(0).max(0) as usize);
+    Ok(match limit {
+        // A negative limit has no bound, the same as it does for tracks.
+        Some(limit) if limit >= 0 => albums.take(limit as usize).collect(),
+        _ => albums.collect(),
+    })
+}
@@ -1,12 +1,64 @@
 extern crate itertools;
 
-use katatsuki::TrackFileType;
-use error::{Result};
-use super::lexer::{lex_query};
-use super::parser::{parse_token_stream};
+use katatsuki::{ToPrimitive, TrackFileType};
+use lint::LintKind;
+use error::{Error, Result};
+use super::lexer::{lex_query, lex_query_with_macros};
+use super::parser::{parse_token_stream, BangIdentifier, BangType};
+use super::time::format_ticks;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug)]
+/// A numeric column of a track that a bang can compare against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumericField {
+    Bitrate,
+    CoverArtWidth,
+    CoverArtHeight,
+    /// The duration of the track, in ticks.
+    Duration,
+    Year,
+    SampleRate,
+    TrackNumber,
+    DiscNumber,
+}
+
+impl NumericField {
+    /// The identifier of the bang that compares against this field.
+    fn identifier(&self) -> &'static str {
+        match *self {
+            NumericField::Bitrate => "br",
+            NumericField::CoverArtWidth => "cw",
+            NumericField::CoverArtHeight => "ch",
+            NumericField::Duration => "d",
+            NumericField::Year => "y",
+            NumericField::SampleRate => "sr",
+            NumericField::TrackNumber => "tn",
+            NumericField::DiscNumber => "dn",
+        }
+    }
+
+    fn format_value(&self, value: i64) -> String {
+        match *self {
+            NumericField::Duration => format_ticks(value),
+            _ => value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal(i64),
+    LessThan(i64),
+    LessThanOrEqual(i64),
+    GreaterThan(i64),
+    GreaterThanOrEqual(i64),
+    /// Between the two values, inclusive.
+    Between(i64, i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Bang {
     All,
     TitleSearch(String),
@@ -21,19 +73,24 @@ pub enum Bang {
     ArtistExact(String),
     Source(String),
     Format(TrackFileType),
-    BitrateLessThan(i32), 
-    BitrateGreaterThan(i32),
-    CoverArtWidthLessThan(i32),
-    CoverArtWidthGreaterThan(i32),
-    CoverArtHeightLessThan(i32),
-    CoverArtHeightGreaterThan(i32),
-    DurationLessThan(i64),
-    DurationGreaterThan(i64),
+    Compare(NumericField, Comparison),
     HasCoverArt(bool),
     HasMusicbrainzId(bool),
     HasDuplicates(bool),
+    HasDuplicateAudio(bool),
+    /// Whether the album of the track has tracks of more than one format.
+    AlbumHasMixedFormats(bool),
+    /// Whether every track of the album of the track has cover art.
+    AlbumHasCoverArt(bool),
+    /// Whether the album of the track is missing tracks or discs,
+    /// or has tracks with the same track number.
+    AlbumIsIncomplete(bool),
+    /// Whether the track has a problem with its tags of the given kind,
+    /// or of any kind.
+    Lint(Option<LintKind>),
     LogicalAnd(Box<Bang>, Box<Bang>),
     LogicalOr(Box<Bang>, Box<Bang>),
+    LogicalNot(Box<Bang>),
     Grouping(Box<Bang>),
     UpdatedBefore(String),
     UpdatedAfter(String),
@@ -45,6 +102,184 @@ impl Bang {
         let token_stream = lex_query(query)?;
         parse_token_stream(&mut token_stream.iter())
     }
+
+    /// Parses a query that may use the given macros, which are
+    /// expanded into the bangs that they stand for.
+    pub fn with_macros(query: &str, macros: &BTreeMap<String, String>) -> Result<Bang> {
+        let token_stream = lex_query_with_macros(query, macros)?;
+        parse_token_stream(&mut token_stream.iter())
+    }
+
+    /// Checks that every macro can be used in a query, so that mistakes
+    /// in the configuration are found when it is loaded. A macro must be
+    /// named with ASCII letters and digits that are not the name of a
+    /// bang, and its query must parse.
+    pub fn check_macros(macros: &BTreeMap<String, String>) -> Result<()> {
+        for name in macros.keys() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(Error::MacroNameInvalid(
+                    name.to_owned(),
+                    "its name must only have the letters A to Z and digits",
+                ));
+            }
+            if let BangType::Unknown(_) = name.as_bang_type() {
+                Bang::with_macros(&format!("!{}", name), macros)?;
+            } else {
+                return Err(Error::MacroNameInvalid(
+                    name.to_owned(),
+                    "its name is the name of a bang",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the argument of a bang, so that it lexes back to itself.
+pub fn escape_argument(argument: &str) -> String {
+    argument.replace('\\', r"\\").replace('}', r"\}")
+}
+
+/// The formats that `!f` accepts, with the file type that each one
+/// matches, in the order they are suggested.
+pub const FORMATS: &[(&str, TrackFileType)] = &[
+    ("flac", TrackFileType::FLAC),
+    ("flac4", TrackFileType::FLAC4),
+    ("flac8", TrackFileType::FLAC8),
+    ("flac16", TrackFileType::FLAC16),
+    ("flac24", TrackFileType::FLAC24),
+    ("flac32", TrackFileType::FLAC32),
+    ("alac", TrackFileType::ALAC),
+    ("alac16", TrackFileType::ALAC16),
+    ("alac24", TrackFileType::ALAC24),
+    ("mp3", TrackFileType::MP3),
+    ("cbr", TrackFileType::MP3CBR),
+    ("vbr", TrackFileType::MP3VBR),
+    ("aac", TrackFileType::AAC),
+    ("vorbis", TrackFileType::Vorbis),
+    ("opus", TrackFileType::Opus),
+    ("aiff", TrackFileType::AIFF),
+    ("aiff4", TrackFileType::AIFF4),
+    ("aiff8", TrackFileType::AIFF8),
+    ("aiff16", TrackFileType::AIFF16),
+    ("aiff24", TrackFileType::AIFF24),
+    ("aiff32", TrackFileType::AIFF32),
+    ("ape", TrackFileType::MonkeysAudio),
+    ("ape8", TrackFileType::MonkeysAudio8),
+    ("ape16", TrackFileType::MonkeysAudio16),
+    ("ape24", TrackFileType::MonkeysAudio24),
+];
+
+/// The name of a format, as `!f` accepts it.
+fn format_name(format: &TrackFileType) -> &'static str {
+    FORMATS
+        .iter()
+        .find(|&&(_, ref file_type)| file_type.to_i32() == format.to_i32())
+        .map(|&(name, _)| name)
+        .unwrap_or("unknown")
+}
+
+/// How tightly a bang binds. Negation and single bangs bind
+/// tightest, followed by AND, then OR.
+fn precedence(bang: &Bang) -> u8 {
+    match *bang {
+        Bang::LogicalOr(_, _) => 0,
+        Bang::LogicalAnd(_, _) => 1,
+        Bang::Grouping(ref bang) => precedence(bang),
+        _ => 2,
+    }
+}
+
+/// Writes a bang, grouping it if it binds looser than the given precedence.
+fn write_operand(f: &mut fmt::Formatter, bang: &Bang, min_precedence: u8) -> fmt::Result {
+    if precedence(bang) < min_precedence {
+        write!(f, "!!{{{}}}", bang)
+    } else {
+        write!(f, "{}", bang)
+    }
+}
+
+fn write_bang(f: &mut fmt::Formatter, identifier: &str, argument: &str) -> fmt::Result {
+    write!(f, "!{}{{{}}}", identifier, escape_argument(argument))
+}
+
+/// Writes the bang as canonical bang syntax, which parses back to an
+/// equivalent bang. Groupings are only written where they are needed
+/// to keep the order of operations, so they may not round trip, but
+/// the bang will match the same tracks.
+///
+/// `Bang::All` is written as the empty query, and so only round trips
+/// on its own. `Bang::FilePath` is only made by seiri to look up a track,
+/// and has no bang syntax, so it is written as `!path{...}` to be read
+/// in logs, which does not parse.
+impl fmt::Display for Bang {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bang::All => Ok(()),
+            Bang::TitleSearch(ref search) => write_bang(f, "t", search),
+            Bang::TitleSearchExact(ref search) => write_bang(f, "T", search),
+            Bang::FullTextSearch(ref search) => write_bang(f, "q", search),
+            Bang::FullTextSearchExact(ref search) => write_bang(f, "Q", search),
+            Bang::AlbumTitle(ref search) => write_bang(f, "al", search),
+            Bang::AlbumTitleExact(ref search) => write_bang(f, "AL", search),
+            Bang::AlbumArtists(ref search) => write_bang(f, "alar", search),
+            Bang::AlbumArtistsExact(ref search) => write_bang(f, "ALAR", search),
+            Bang::Artist(ref search) => write_bang(f, "ar", search),
+            Bang::ArtistExact(ref search) => write_bang(f, "AR", search),
+            Bang::Source(ref search) => write_bang(f, "s", search),
+            Bang::Format(ref format) => write_bang(f, "f", format_name(format)),
+            Bang::Compare(field, comparison) => {
+                let argument = match comparison {
+                    Comparison::Equal(value) => field.format_value(value),
+                    Comparison::LessThan(value) => format!("<{}", field.format_value(value)),
+                    Comparison::LessThanOrEqual(value) => {
+                        format!("<={}", field.format_value(value))
+                    }
+                    Comparison::GreaterThan(value) => format!(">{}", field.format_value(value)),
+                    Comparison::GreaterThanOrEqual(value) => {
+                        format!(">={}", field.format_value(value))
+                    }
+                    Comparison::Between(low, high) => format!(
+                        "{}..{}",
+                        field.format_value(low),
+                        field.format_value(high)
+                    ),
+                };
+                write_bang(f, field.identifier(), &argument)
+            }
+            Bang::HasCoverArt(value) => write_bang(f, "c", &value.to_string()),
+            Bang::HasMusicbrainzId(value) => write_bang(f, "mb", &value.to_string()),
+            Bang::HasDuplicates(value) => write_bang(f, "dup", &value.to_string()),
+            Bang::HasDuplicateAudio(value) => write_bang(f, "dupaudio", &value.to_string()),
+            Bang::AlbumHasMixedFormats(value) => write_bang(f, "almixed", &value.to_string()),
+            Bang::AlbumHasCoverArt(value) => write_bang(f, "alc", &value.to_string()),
+            Bang::AlbumIsIncomplete(value) => write_bang(f, "incomplete", &value.to_string()),
+            Bang::Lint(kind) => write_bang(f, "lint", kind.map(|kind| kind.name()).unwrap_or("any")),
+            Bang::LogicalAnd(ref lhs, ref rhs) => {
+                // Operators associate to the left, so a right hand side
+                // of the same precedence has to be grouped.
+                write_operand(f, lhs, 1)?;
+                f.write_str(" & ")?;
+                write_operand(f, rhs, 2)
+            }
+            Bang::LogicalOr(ref lhs, ref rhs) => {
+                write_operand(f, lhs, 0)?;
+                f.write_str(" | ")?;
+                write_operand(f, rhs, 1)
+            }
+            Bang::LogicalNot(ref bang) => {
+                if precedence(bang) < 2 {
+                    write!(f, "!not{{{}}}", bang)
+                } else {
+                    write!(f, "-{}", bang)
+                }
+            }
+            Bang::Grouping(ref bang) => write!(f, "{}", bang),
+            Bang::UpdatedBefore(ref date) => write_bang(f, "ubf", date),
+            Bang::UpdatedAfter(ref date) => write_bang(f, "uaf", date),
+            Bang::FilePath(ref path) => write_bang(f, "path", path),
+        }
+    }
 }
 
 impl From<PathBuf> for Bang {
@@ -0,0 +1,144 @@
+use database;
+use database::Connection;
+use error::Result;
+use sort::SortColumn;
+use super::bangs::{escape_argument, FORMATS};
+use super::lexer::{lex_partial_query, LexerMode, Span, Token};
+use super::parser::{BangIdentifier, BangType, BANG_IDENTIFIERS};
+
+/// The most values to suggest from the library for a single argument.
+const MAX_VALUE_COMPLETIONS: u32 = 25;
+
+/// The kinds of lint that `!lint` accepts.
+const LINTS: &[&str] = &["any", "year", "split", "case", "whitespace"];
+
+/// A suggestion for what to type at the cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// The text to replace the span with. Arguments are already escaped.
+    pub text: String,
+    /// What the suggestion is, such as what a bang matches.
+    pub description: String,
+    /// The span of the query that the completion replaces,
+    /// which is what has already been typed of it.
+    pub span: Span,
+}
+
+impl Completion {
+    fn new(text: &str, description: &str, span: Span) -> Completion {
+        Completion {
+            text: text.to_owned(),
+            description: description.to_owned(),
+            span,
+        }
+    }
+}
+
+fn complete_bang_identifier(typed: &str, span: Span) -> Vec<Completion> {
+    BANG_IDENTIFIERS
+        .iter()
+        .filter(|&&(identifier, _)| identifier.starts_with(typed))
+        .map(|&(identifier, description)| Completion::new(identifier, description, span))
+        .collect()
+}
+
+fn complete_argument(
+    identifier: &str,
+    typed: &str,
+    span: Span,
+    conn: &Connection,
+) -> Result<Vec<Completion>> {
+    let typed_lowercase = typed.to_lowercase();
+    let from_list = |values: &[&str], description: &str| -> Vec<Completion> {
+        values
+            .iter()
+            .filter(|value| value.starts_with(&typed_lowercase))
+            .map(|value| Completion::new(value, description, span))
+            .collect()
+    };
+    let column = match identifier.as_bang_type() {
+        BangType::Format => {
+            let formats = FORMATS.iter().map(|&(name, _)| name).collect::<Vec<&str>>();
+            return Ok(from_list(&formats, "Format"));
+        }
+        BangType::Lint => return Ok(from_list(LINTS, "Lint")),
+        BangType::HasCoverArt
+        | BangType::HasMusicbrainzId
+        | BangType::HasDuplicates
+        | BangType::HasDuplicateAudio
+        | BangType::AlbumHasMixedFormats
+        | BangType::AlbumHasCoverArt
+        | BangType::AlbumIsIncomplete => return Ok(from_list(&["true", "false"], "Boolean")),
+        BangType::Artist | BangType::ArtistExact => SortColumn::Artist,
+        BangType::AlbumTitle | BangType::AlbumTitleExact => SortColumn::Album,
+        BangType::AlbumArtists | BangType::AlbumArtistsExact => SortColumn::AlbumArtists,
+        BangType::Source => SortColumn::Source,
+        _ => return Ok(Vec::new()),
+    };
+    let description = match column {
+        SortColumn::Artist => "Artist",
+        SortColumn::Album => "Album",
+        SortColumn::AlbumArtists => "Album artists",
+        _ => "Source",
+    };
+    Ok(
+        database::get_distinct_values(column, typed, MAX_VALUE_COMPLETIONS, conn)?
+            .iter()
+            .map(|value| Completion::new(&escape_argument(value), description, span))
+            .collect(),
+    )
+}
+
+/// Suggests what could be typed at the cursor, which is a byte offset
+/// into the query. Only the part of the query before the cursor is
+/// considered.
+///
+/// After a `!`, the names of bangs are suggested. In the argument of a
+/// bang, formats are suggested for `!f`, `true` and `false` for bangs
+/// that take booleans, and artists, albums, album artists and sources
+/// from the library for `!ar`, `!al`, `!alar` and `!s`, and their exact
+/// versions.
+///
+/// A query that is not a bang, or that is wrong before the cursor,
+/// has no suggestions.
+pub fn complete_query(query: &str, cursor: usize, conn: &Connection) -> Result<Vec<Completion>> {
+    if cursor > query.len() || !query.is_char_boundary(cursor) {
+        return Ok(Vec::new());
+    }
+    let (tokens, mode) = match lex_partial_query(&query[..cursor]) {
+        Ok(lexed) => lexed,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let at_cursor = Span::new(cursor, cursor);
+    let mut last_tokens = tokens.iter().rev().map(|token| (&token.token, token.span));
+
+    match (mode, last_tokens.next(), last_tokens.next(), last_tokens.next()) {
+        // A bang prefix that has just been typed.
+        (LexerMode::BangIdentifier, _, _, _) => Ok(complete_bang_identifier("", at_cursor)),
+        // The name of a bang that is being typed.
+        (LexerMode::ArgumentEdge, Some((&Token::BangIdentifier(ref identifier), span)), _, _)
+            if span.end == cursor =>
+        {
+            Ok(complete_bang_identifier(identifier, span))
+        }
+        // An opening brace that has just been typed.
+        (
+            LexerMode::Argument,
+            Some((&Token::ArgumentBegin, _)),
+            Some((&Token::BangIdentifier(ref identifier), _)),
+            _,
+        ) => complete_argument(identifier, "", at_cursor, conn),
+        // An argument that is being typed.
+        (
+            LexerMode::ArgumentEdge,
+            Some((&Token::Argument(ref argument), span)),
+            Some((&Token::ArgumentBegin, _)),
+            Some((&Token::BangIdentifier(ref identifier), _)),
+        ) if span.end == cursor =>
+        {
+            complete_argument(identifier, argument, span, conn)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
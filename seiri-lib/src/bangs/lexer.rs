@@ -1,8 +1,7 @@
 use error::{Error, Result};
 use itertools::Itertools;
-use itertools::multipeek;
-use itertools::MultiPeek;
-use std::str::Chars;
+use std::collections::BTreeMap;
+use super::parser::{parse_token_stream, BangIdentifier, BangType};
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum Token {
@@ -12,10 +11,15 @@ pub enum Token {
 
     /// BangPrefix is the '!' prepended before starting
     /// A Bang. A token stream always starts with either
-    /// MatchAll, or BangPrefix, and BangPrefix is
+    /// MatchAll, LogicalNot, or BangPrefix, and BangPrefix is
     /// followed only by BangIdentifier.
     BangPrefix(char),
 
+    /// LogicalNot is the '-' that negates the bang after it.
+    /// It is always followed by either another LogicalNot,
+    /// or a BangPrefix.
+    LogicalNot,
+
     /// BangIdentifier is the name of the bang
     /// It is always preceeded by BangPrefix,
     /// and is followed by ArgumentBegin.
@@ -39,7 +43,7 @@ pub enum Token {
 
     /// LogicalOperator represents a binary operator on two bangs.
     /// Hence it is always preceeded by an ArgumentEnd token,
-    /// and followed by a BangPrefix bang token.
+    /// and followed by a BangPrefix or LogicalNot token.
     LogicalOperator(char),
 
     /// InputEnd represents the end of a the query, and is
@@ -54,6 +58,26 @@ pub enum Token {
     PreprocessTokenExpand(Vec<Token>),
 }
 
+/// The bytes of the query that a token was lexed from, or that an error is about.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// A token, and where in the query it came from.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum LexerMode {
     Bang,
@@ -62,6 +86,85 @@ pub enum LexerMode {
     Argument,
 }
 
+impl LexerMode {
+    /// What the lexer expects to find next in this mode.
+    pub fn expected(&self) -> &'static str {
+        match *self {
+            LexerMode::Bang => "'!' or '-'",
+            LexerMode::BangIdentifier => "the name of a bang",
+            LexerMode::ArgumentEdge => "'{', '}', '&' or '|'",
+            LexerMode::Argument => "an argument",
+        }
+    }
+}
+
+/// The characters of a query, which keeps track of how far into the
+/// query it is, and can peek any number of characters ahead.
+#[derive(Clone)]
+struct Cursor<'a> {
+    query: &'a str,
+    offset: usize,
+    peek_offset: usize,
+    peeked: Option<char>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(query: &'a str) -> Cursor<'a> {
+        Cursor {
+            query,
+            offset: 0,
+            peek_offset: 0,
+            peeked: None,
+        }
+    }
+
+    /// The byte offset of the next character.
+    fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The span of the character at the given offset.
+    fn span_at(&self, offset: usize) -> Span {
+        let length = self.query[offset..]
+            .chars()
+            .next()
+            .map(|c| c.len_utf8())
+            .unwrap_or(0);
+        Span::new(offset, offset + length)
+    }
+
+    /// The empty span at the end of the query.
+    fn end_span(&self) -> Span {
+        Span::new(self.query.len(), self.query.len())
+    }
+
+    /// Peeks at the character after the last one peeked at.
+    fn peek(&mut self) -> Option<&char> {
+        self.peeked = self.query[self.peek_offset..].chars().next();
+        if let Some(c) = self.peeked {
+            self.peek_offset += c.len_utf8();
+        }
+        self.peeked.as_ref()
+    }
+
+    fn reset_peek(&mut self) {
+        self.peek_offset = self.offset;
+    }
+}
+
+impl<'a> Iterator for Cursor<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.query[self.offset..].chars().next();
+        if let Some(c) = c {
+            self.offset += c.len_utf8();
+        }
+        self.peek_offset = self.offset;
+        c
+    }
+}
+
 trait LexerProperties {
     fn is_valid_bang_identifier(&self) -> bool;
     fn is_argument_start_identifier(&self) -> bool;
@@ -76,14 +179,22 @@ impl LexerProperties for char {
     }
 }
 
-fn match_bang(c: &char, characters: &mut MultiPeek<Chars>) -> Result<Option<(Token, LexerMode)>> {
+fn match_bang(c: &char, characters: &mut Cursor) -> Result<Option<(Token, LexerMode)>> {
     if !c.is_whitespace() {
         return match c {
             &'!' => {
                 characters.next();
                 Ok(Some((Token::BangPrefix(*c), LexerMode::BangIdentifier)))
             }
-            _ => Err(Error::LexerUnexpectedCharacter(*c, LexerMode::Bang)),
+            &'-' => {
+                characters.next();
+                Ok(Some((Token::LogicalNot, LexerMode::Bang)))
+            }
+            _ => Err(Error::LexerUnexpectedCharacter(
+                *c,
+                LexerMode::Bang,
+                characters.span_at(characters.offset()),
+            )),
         };
     }
     characters.next();
@@ -92,7 +203,7 @@ fn match_bang(c: &char, characters: &mut MultiPeek<Chars>) -> Result<Option<(Tok
 
 fn match_bang_identifier(
     c: &char,
-    characters: &mut MultiPeek<Chars>,
+    characters: &mut Cursor,
 ) -> Result<Option<(Token, LexerMode)>> {
     if c.is_alphanumeric() {
         let token = Token::BangIdentifier(
@@ -111,13 +222,14 @@ fn match_bang_identifier(
         return Err(Error::LexerUnexpectedCharacter(
             *c,
             LexerMode::BangIdentifier,
+            characters.span_at(characters.offset()),
         ));
     }
 }
 
 fn match_argument_edge(
     c: &char,
-    characters: &mut MultiPeek<Chars>,
+    characters: &mut Cursor,
 ) -> Result<Option<(Token, LexerMode)>> {
     if c.is_whitespace() {
         characters.next();
@@ -136,7 +248,13 @@ fn match_argument_edge(
                 ]),
                 LexerMode::Argument,
             )),
-            _ => return Err(Error::LexerUnexpectedCharacter(*c, LexerMode::ArgumentEdge)),
+            _ => {
+                return Err(Error::LexerUnexpectedCharacter(
+                    *c,
+                    LexerMode::ArgumentEdge,
+                    characters.span_at(characters.offset()),
+                ))
+            }
         };
         characters.next();
         Ok(token)
@@ -145,7 +263,7 @@ fn match_argument_edge(
 
 /// Searches a multipeek for the next character not equal to the specified character.
 /// Advances the peek cursor.
-fn next_non_match_character<F>(f: F, chars: &mut MultiPeek<Chars>) -> Result<(char, usize)>
+fn next_non_match_character<F>(f: F, chars: &mut Cursor) -> Result<(char, usize)>
 where
     F: Fn(&char) -> bool,
 {
@@ -156,20 +274,33 @@ where
             _ => return Ok((c, index)),
         }
     }
-    Err(Error::LexerUnexpectedEndOfInput)
+    Err(Error::LexerUnexpectedEndOfInput(
+        chars.end_span(),
+        LexerMode::BangIdentifier.expected(),
+    ))
 }
 
-fn confirm_bang_sequence(characters: &mut MultiPeek<Chars>) -> Result<bool> {
+fn confirm_bang_sequence(characters: &mut Cursor, context: &MacroContext) -> Result<bool> {
     // We found a bang!, we have to make triple sure it's a legit bang.
     // This is assuming that the current peek position is at the bang position.
     match characters.peek().cloned() {
         // We're going to see if the next token is a bang identifier followed by
-        // An argument opener.
+        // An argument opener, or is a macro, which needs no argument.
         Some(bang_ident) if bang_ident.is_valid_bang_identifier() => {
-            match next_non_match_character(|&c| c.is_valid_bang_identifier(), characters) {
-                Ok(next_character) if next_character.0.is_argument_start_identifier() => Ok(true),
-                Ok(_) => Ok(false),
-                Err(err) => Err(err),
+            let mut identifier = bang_ident.to_string();
+            while let Some(c) = characters.peek().cloned() {
+                if !c.is_valid_bang_identifier() {
+                    return Ok(c.is_argument_start_identifier() || context.get(&identifier).is_some());
+                }
+                identifier.push(c);
+            }
+            if context.get(&identifier).is_some() {
+                Ok(true)
+            } else {
+                Err(Error::LexerUnexpectedEndOfInput(
+                    characters.end_span(),
+                    LexerMode::BangIdentifier.expected(),
+                ))
             }
         }
         _ => Ok(false),
@@ -178,13 +309,14 @@ fn confirm_bang_sequence(characters: &mut MultiPeek<Chars>) -> Result<bool> {
 
 fn match_argument(
     c: &char,
-    characters: &mut MultiPeek<Chars>,
-    tokens: &[Token],
+    characters: &mut Cursor,
+    tokens: &[SpannedToken],
 ) -> Result<Option<(Token, LexerMode)>> {
-    if let &Some(ref token) = &tokens.iter().rev().nth(1) {
+    if let Some(&SpannedToken { ref token, .. }) = tokens.iter().rev().nth(1) {
         match token {
-            &&Token::BangIdentifier(ref token) => match token.as_ref() {
-                "!" => return match_bang(c, characters),
+            // The arguments of the grouping and not bangs are bangs themselves.
+            &Token::BangIdentifier(ref token) => match token.as_ref() {
+                "!" | "not" => return match_bang(c, characters),
                 _ => (),
             },
             _ => (),
@@ -209,13 +341,17 @@ fn match_argument(
             }
             //Support escapes as well.
             '\\' => {
+                let escape_offset = characters.offset();
                 characters.next(); // Consume this '\' without adding it to the buffer.
                 if let Some(escape_after) = characters.next() {
                     argument.push(escape_after);
                     characters.reset_peek();
                 } else {
                     // If we try an escape at the end of the striing
-                    return Err(Error::LexerUnexpectedEscapeCharacter(LexerMode::Argument));
+                    return Err(Error::LexerUnexpectedEscapeCharacter(
+                        LexerMode::Argument,
+                        characters.span_at(escape_offset),
+                    ));
                 };
             }
             _ => {
@@ -229,11 +365,12 @@ fn match_argument(
     Ok(Some((Token::Argument(argument), LexerMode::ArgumentEdge)))
 }
 
-fn match_title(query: &str, characters: &mut MultiPeek<Chars>) -> Option<Token> {
+fn match_title(query: &str, characters: &mut Cursor, context: &MacroContext) -> Option<Token> {
     // We want the lexer to consider non bang openers as title peeks.
-    match next_non_match_character(|&c| c == ' ', characters) {
+    // A bang may be negated, so skip past any '-' before it.
+    match next_non_match_character(|&c| c == ' ' || c == '-', characters) {
         Ok(character) if character.0 == '!' => {
-            match confirm_bang_sequence(characters) {
+            match confirm_bang_sequence(characters, context) {
                 Ok(not_bang) if !not_bang => {
                     // No bang found, return the title.
                     characters.reset_peek();
@@ -265,89 +402,268 @@ fn match_title(query: &str, characters: &mut MultiPeek<Chars>) -> Option<Token>
     }
 }
 
-fn ensure_arguments_balanced(tokens: &Vec<Token>) -> bool {
+/// Checks that every bang in the token stream has a name and an argument,
+/// and returns what is missing at the end of the query if not.
+fn missing_at_end(tokens: &[SpannedToken], mode: &LexerMode) -> Option<&'static str> {
     let mut argument_begin = 0;
     let mut argument_end = 0;
     let mut bang_prefix = 0;
     let mut bang_ident = 0;
     for token in tokens {
-        match token {
-            &Token::ArgumentBegin => argument_begin += 1,
-            &Token::ArgumentEnd => argument_end += 1,
-            &Token::BangPrefix(_) => bang_prefix += 1,
-            &Token::BangIdentifier(_) => bang_ident += 1,
+        match token.token {
+            Token::ArgumentBegin => argument_begin += 1,
+            Token::ArgumentEnd => argument_end += 1,
+            Token::BangPrefix(_) => bang_prefix += 1,
+            Token::BangIdentifier(_) => bang_ident += 1,
             _ => (),
         }
     }
 
-    ((argument_begin == argument_end) && (bang_prefix == bang_ident)
-        && (bang_prefix == argument_begin))
+    // A stray closing brace is left for the parser,
+    // which knows what was expected in its place.
+    if argument_begin > argument_end {
+        Some("'}'")
+    } else if bang_prefix > bang_ident {
+        Some(mode.expected())
+    } else if bang_ident > argument_begin {
+        Some("'{'")
+    } else {
+        None
+    }
+}
+
+/// The macros that a query can use, and the macros that are being
+/// expanded, to catch macros that expand to themselves.
+struct MacroContext<'a> {
+    macros: &'a BTreeMap<String, String>,
+    expanding: Vec<String>,
 }
+
+impl<'a> MacroContext<'a> {
+    fn new(macros: &'a BTreeMap<String, String>) -> MacroContext<'a> {
+        MacroContext {
+            macros,
+            expanding: Vec::new(),
+        }
+    }
+
+    /// The query that the macro with the given name expands to.
+    /// Bangs always take precedence over macros of the same name,
+    /// though the configuration rejects such macros when it is loaded.
+    fn get(&self, name: &str) -> Option<&'a str> {
+        match name.as_bang_type() {
+            BangType::Unknown(_) => self.macros.get(name).map(|query| query.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Expands the macro with the given name into a grouping of the
+    /// tokens of its query, so that it binds like a single bang.
+    fn expand(&self, name: &str, span: Span) -> Result<Vec<Token>> {
+        let query = self.get(name).unwrap_or("");
+        if let Some(index) = self.expanding.iter().position(|expanding| expanding == name) {
+            let cycle = self.expanding[index..]
+                .iter()
+                .chain(Some(&name.to_owned()))
+                .map(|name| format!("!{}", name))
+                .collect::<Vec<String>>()
+                .join(" -> ");
+            return Err(Error::MacroCycle(name.to_owned(), cycle, span));
+        }
+        if query.trim().is_empty() {
+            return Err(Error::MacroInvalid(
+                name.to_owned(),
+                "it is empty".to_owned(),
+                span,
+            ));
+        }
+
+        // Errors in the query of the macro are reported at the macro.
+        let invalid = |err: Error| match err {
+            Error::MacroCycle(name, cycle, _) => Error::MacroCycle(name, cycle, span),
+            err => Error::MacroInvalid(name.to_owned(), err.to_string(), span),
+        };
+        let mut context = MacroContext {
+            macros: self.macros,
+            expanding: self.expanding.clone(),
+        };
+        context.expanding.push(name.to_owned());
+        let tokens = lex(query, &context).map_err(&invalid)?;
+        parse_token_stream(&mut tokens.iter()).map_err(&invalid)?;
+
+        let mut expansion = vec![
+            Token::BangPrefix('!'),
+            Token::BangIdentifier("!".to_owned()),
+            Token::ArgumentBegin,
+        ];
+        expansion.extend(
+            tokens
+                .into_iter()
+                .map(|token| token.token)
+                .filter(|token| token != &Token::InputEnd),
+        );
+        expansion.push(Token::ArgumentEnd);
+        Ok(expansion)
+    }
+}
+
+/// Consumes the empty argument that may follow a macro, as in `!hires{}`.
+fn match_macro_argument(name: &str, characters: &mut Cursor) -> Result<()> {
+    let query = characters.query;
+    let rest = &query[characters.offset()..];
+    let argument = rest.trim_start();
+    if !argument.starts_with('{') {
+        return Ok(());
+    }
+    let argument_start = characters.offset() + rest.len() - argument.len();
+    let inside = argument[1..].trim_start();
+    if !inside.starts_with('}') {
+        let argument_end = argument
+            .find('}')
+            .map(|end| argument_start + end + 1)
+            .unwrap_or(query.len());
+        return Err(Error::MacroTakesNoArgument(
+            name.to_owned(),
+            Span::new(argument_start, argument_end),
+        ));
+    }
+    let argument_end = characters.offset() + rest.len() - inside.len() + 1;
+    while characters.offset() < argument_end {
+        characters.next();
+    }
+    Ok(())
+}
+
+/// Lexes the bangs of a query from the start of the cursor, and
+/// returns the mode that the lexer was left in at the end of the query.
+fn lex_bangs(
+    characters: &mut Cursor,
+    tokens: &mut Vec<SpannedToken>,
+    context: &MacroContext,
+) -> Result<LexerMode> {
+    let mut mode = LexerMode::Bang;
+    while let Some(c) = characters.peek().cloned() {
+        let start = characters.offset();
+        let result = match mode {
+            LexerMode::Bang => match_bang(&c, characters),
+            LexerMode::BangIdentifier => match_bang_identifier(&c, characters),
+            LexerMode::ArgumentEdge => match_argument_edge(&c, characters),
+            LexerMode::Argument => match_argument(&c, characters, tokens),
+        };
+        let span = Span::new(start, characters.offset());
+        match result {
+            Ok(some) => match some {
+                Some(token) => {
+                    mode = token.1;
+                    match token.0 {
+                        // A macro replaces the '!' before it with its expansion.
+                        Token::BangIdentifier(ref name) if context.get(name).is_some() => {
+                            let start = tokens.pop().map(|prefix| prefix.span.start).unwrap_or(start);
+                            match_macro_argument(name, characters)?;
+                            let span = Span::new(start, characters.offset());
+                            tokens.extend(
+                                context
+                                    .expand(name, span)?
+                                    .into_iter()
+                                    .map(|token| SpannedToken { token, span }),
+                            );
+                        }
+                        Token::PreprocessTokenExpand(expansion) => tokens.extend(
+                            expansion
+                                .into_iter()
+                                .map(|token| SpannedToken { token, span }),
+                        ),
+                        _ => tokens.push(SpannedToken {
+                            token: token.0,
+                            span,
+                        }),
+                    }
+                }
+                None => (),
+            },
+            Err(err) => return Err(err),
+        }
+        characters.reset_peek();
+    }
+    Ok(mode)
+}
+
 /// Lexes the given query string, and
 /// returns an ordered vector of tokens.
 ///
 /// The lexer is guaranteed to either error or
-/// return a valid token stream.
+/// return a valid token stream. Every token carries the span
+/// of the query it was lexed from, and tokens that come from
+/// desugaring carry the span of the sugar.
 ///
 /// A valid token stream is either [MatchAll, InputEnd],
 /// or starts with [BangPrefix, BangIdentifier, ArgumentBegin, ...],
+/// optionally preceeded by any number of LogicalNot,
 /// and ends with [..., ArgumentEnd, InputEnd].
 ///
 /// The lexer also handles desugaring of bang-less title searches
 /// and the true tick sugar ` -> {true}
-pub fn lex_query(query: &str) -> Result<Vec<Token>> {
-    let mut tokens = Vec::<Token>::new();
-    let mut mode = LexerMode::Bang;
+pub fn lex_query(query: &str) -> Result<Vec<SpannedToken>> {
+    lex_query_with_macros(query, &BTreeMap::new())
+}
+
+/// Lexes the given query string like `lex_query`, and also expands the
+/// given macros. A macro is used like a bang without an argument, as
+/// `!hires` or `!hires{}`, and is expanded into a grouping of its query.
+/// Macros may use other macros, but not themselves.
+pub fn lex_query_with_macros(
+    query: &str,
+    macros: &BTreeMap<String, String>,
+) -> Result<Vec<SpannedToken>> {
+    lex(query, &MacroContext::new(macros))
+}
+
+fn lex(query: &str, context: &MacroContext) -> Result<Vec<SpannedToken>> {
+    let mut tokens = Vec::<SpannedToken>::new();
+    let spanned = |token: Token, span: Span| SpannedToken { token, span };
 
     // The empty query matches all tracks
     if query.chars().count() == 0 {
-        tokens.push(Token::MatchAll);
-        tokens.push(Token::InputEnd);
+        tokens.push(spanned(Token::MatchAll, Span::new(0, 0)));
+        tokens.push(spanned(Token::InputEnd, Span::new(0, 0)));
         return Ok(tokens);
     };
 
-    let query = query.to_owned();
-    let mut characters = multipeek(query.chars());
+    let mut characters = Cursor::new(query);
 
-    match match_title(&query, &mut characters) {
+    match match_title(query, &mut characters, context) {
         Some(Token::PreprocessTokenExpand(title)) => {
-            tokens.extend(title.into_iter());
+            let span = Span::new(0, query.len());
+            tokens.extend(title.into_iter().map(|token| spanned(token, span)));
             return Ok(tokens);
         }
         _ => (),
     }
 
     characters.reset_peek();
-    while let Some(c) = characters.peek().cloned() {
-        let result = match mode {
-            LexerMode::Bang => match_bang(&c, &mut characters),
-            LexerMode::BangIdentifier => match_bang_identifier(&c, &mut characters),
-            LexerMode::ArgumentEdge => match_argument_edge(&c, &mut characters),
-            LexerMode::Argument => match_argument(&c, &mut characters, &tokens),
-        };
-        match result {
-            Ok(some) => match some {
-                Some(token) => {
-                    mode = token.1;
-                    match token.0 {
-                        Token::PreprocessTokenExpand(expansion) => {
-                            tokens.extend(expansion.into_iter())
-                        }
-                        _ => tokens.push(token.0),
-                    }
-                }
-                None => (),
-            },
-            Err(err) => return Err(err),
+    let mode = lex_bangs(&mut characters, &mut tokens, context)?;
+
+    match missing_at_end(&tokens, &mode) {
+        None => {
+            tokens.push(spanned(Token::InputEnd, characters.end_span()));
+            Ok(tokens)
         }
-        characters.reset_peek();
+        Some(expected) => Err(Error::LexerUnexpectedEndOfInput(
+            characters.end_span(),
+            expected,
+        )),
     }
+}
 
-    if ensure_arguments_balanced(&tokens) {
-        tokens.push(Token::InputEnd);
-        Ok(tokens)
-    } else {
-        Err(Error::LexerUnexpectedEndOfInput)
-    }
-    //Ok(tokens)
+/// Lexes a query that is still being typed, which may end in the
+/// middle of a bang. Returns the tokens lexed so far, without an
+/// InputEnd, and the mode the lexer was left in.
+///
+/// Bang-less title searches are not desugared, so a query that
+/// does not start with a bang is an error.
+pub fn lex_partial_query(query: &str) -> Result<(Vec<SpannedToken>, LexerMode)> {
+    let mut tokens = Vec::<SpannedToken>::new();
+    let mut characters = Cursor::new(query);
+    let mode = lex_bangs(&mut characters, &mut tokens, &MacroContext::new(&BTreeMap::new()))?;
+    Ok((tokens, mode))
 }
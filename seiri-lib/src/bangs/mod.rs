@@ -1,11 +1,15 @@
 mod lexer;
 mod bangs;
+mod completion;
 mod parser;
 mod time;
 //pub use self::lexer::lex_query;
-pub use self::bangs::Bang;
+pub use self::bangs::{Bang, Comparison, NumericField};
+pub use self::completion::{complete_query, Completion};
 pub use self::lexer::LexerMode;
+pub use self::lexer::Span;
 pub use self::lexer::Token;
 pub use self::time::ticks_to_ms;
 pub use self::time::ms_to_ticks;
+pub use self::time::parse_date;
 //pub use self::parser::parse_token_stream;
\ No newline at end of file
@@ -1,14 +1,68 @@
 use std::slice::Iter;
 use std::str::FromStr;
-use super::lexer::Token;
-use super::bangs::Bang;
+use super::lexer::{LexerMode, Span, SpannedToken, Token};
+use super::bangs::{Bang, Comparison, NumericField};
 use katatsuki::TrackFileType;
+use lint::LintKind;
 use error::{Error, Result};
 use humantime::Duration;
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use super::time::*;
 
-trait BangIdentifier {
+/// Every bang identifier, and what it matches.
+/// This must be kept in step with `as_bang_type`.
+pub const BANG_IDENTIFIERS: &[(&str, &str)] = &[
+    ("t", "Track title"),
+    ("T", "Exact track title"),
+    ("q", "Full text search"),
+    ("Q", "Exact full text search"),
+    ("al", "Album title"),
+    ("AL", "Exact album title"),
+    ("alar", "Album artists"),
+    ("ALAR", "Exact album artists"),
+    ("ar", "Artist"),
+    ("AR", "Exact artist"),
+    ("s", "Source"),
+    ("f", "Format"),
+    ("br", "Bitrate"),
+    ("brlt", "Bitrate less than"),
+    ("brgt", "Bitrate greater than"),
+    ("cw", "Cover art width"),
+    ("cwlt", "Cover art width less than"),
+    ("cwgt", "Cover art width greater than"),
+    ("ch", "Cover art height"),
+    ("chlt", "Cover art height less than"),
+    ("chgt", "Cover art height greater than"),
+    ("d", "Duration"),
+    ("dlt", "Duration less than"),
+    ("dgt", "Duration greater than"),
+    ("y", "Year"),
+    ("ylt", "Year less than"),
+    ("ygt", "Year greater than"),
+    ("sr", "Sample rate"),
+    ("srlt", "Sample rate less than"),
+    ("srgt", "Sample rate greater than"),
+    ("tn", "Track number"),
+    ("tnlt", "Track number less than"),
+    ("tngt", "Track number greater than"),
+    ("dn", "Disc number"),
+    ("dnlt", "Disc number less than"),
+    ("dngt", "Disc number greater than"),
+    ("c", "Has cover art"),
+    ("mb", "Has MusicBrainz IDs"),
+    ("dup", "Is a duplicate"),
+    ("dupaudio", "Has duplicate audio"),
+    ("almixed", "Album has mixed formats"),
+    ("alc", "Every track of the album has cover art"),
+    ("incomplete", "Album is missing tracks or discs"),
+    ("lint", "Has a problem with its tags"),
+    ("ubf", "Updated before"),
+    ("uaf", "Updated after"),
+    ("not", "Negation"),
+    ("!", "Group"),
+];
+
+pub trait BangIdentifier {
     fn as_bang_type(&self) -> BangType;
 }
 
@@ -27,26 +81,50 @@ impl BangIdentifier for str {
             "AR" => BangType::ArtistExact,
             "s" => BangType::Source,
             "f" => BangType::Format,
-            "dlt" => BangType::DurationLessThan,
-            "dgt" => BangType::DurationGreaterThan,
-            "brlt" => BangType::BitrateLessThan,
-            "brgt" => BangType::BitrateGreaterThan,
-            "cwlt" => BangType::CoverArtWidthLessThan,
-            "cwgt" => BangType::CoverArtWidthGreaterThan,
-            "chlt" => BangType::CoverArtHeightLessThan,
-            "chgt" => BangType::CoverArtHeightGreaterThan,
+            // Numeric bangs take a comparison, and the lt and gt bangs
+            // are kept as shorthand for < and >.
+            "br" => BangType::Compare(NumericField::Bitrate),
+            "brlt" => BangType::LessThan(NumericField::Bitrate),
+            "brgt" => BangType::GreaterThan(NumericField::Bitrate),
+            "cw" => BangType::Compare(NumericField::CoverArtWidth),
+            "cwlt" => BangType::LessThan(NumericField::CoverArtWidth),
+            "cwgt" => BangType::GreaterThan(NumericField::CoverArtWidth),
+            "ch" => BangType::Compare(NumericField::CoverArtHeight),
+            "chlt" => BangType::LessThan(NumericField::CoverArtHeight),
+            "chgt" => BangType::GreaterThan(NumericField::CoverArtHeight),
+            "d" => BangType::Compare(NumericField::Duration),
+            "dlt" => BangType::LessThan(NumericField::Duration),
+            "dgt" => BangType::GreaterThan(NumericField::Duration),
+            "y" => BangType::Compare(NumericField::Year),
+            "ylt" => BangType::LessThan(NumericField::Year),
+            "ygt" => BangType::GreaterThan(NumericField::Year),
+            "sr" => BangType::Compare(NumericField::SampleRate),
+            "srlt" => BangType::LessThan(NumericField::SampleRate),
+            "srgt" => BangType::GreaterThan(NumericField::SampleRate),
+            "tn" => BangType::Compare(NumericField::TrackNumber),
+            "tnlt" => BangType::LessThan(NumericField::TrackNumber),
+            "tngt" => BangType::GreaterThan(NumericField::TrackNumber),
+            "dn" => BangType::Compare(NumericField::DiscNumber),
+            "dnlt" => BangType::LessThan(NumericField::DiscNumber),
+            "dngt" => BangType::GreaterThan(NumericField::DiscNumber),
             "c" => BangType::HasCoverArt,
             "mb" => BangType::HasMusicbrainzId,
             "dup" => BangType::HasDuplicates,
+            "dupaudio" => BangType::HasDuplicateAudio,
+            "almixed" => BangType::AlbumHasMixedFormats,
+            "alc" => BangType::AlbumHasCoverArt,
+            "incomplete" => BangType::AlbumIsIncomplete,
+            "lint" => BangType::Lint,
             "ubf" => BangType::UpdatedBefore,
             "uaf" => BangType::UpdatedAfter,
+            "not" => BangType::LogicalNot,
             "!" => BangType::Grouping,
             unknown => BangType::Unknown(unknown.to_owned()),
         }
     }
 }
 
-enum BangType {
+pub enum BangType {
     TitleSearch,
     TitleSearchExact,
     FullTextSearch,
@@ -59,91 +137,288 @@ enum BangType {
     ArtistExact,
     Source,
     Format,
-    BitrateLessThan,
-    BitrateGreaterThan,
-    DurationLessThan,
-    DurationGreaterThan,
-    CoverArtWidthLessThan,
-    CoverArtWidthGreaterThan,
-    CoverArtHeightLessThan,
-    CoverArtHeightGreaterThan,
+    Compare(NumericField),
+    LessThan(NumericField),
+    GreaterThan(NumericField),
     HasCoverArt,
     HasMusicbrainzId,
     HasDuplicates,
+    HasDuplicateAudio,
+    AlbumHasMixedFormats,
+    AlbumHasCoverArt,
+    AlbumIsIncomplete,
+    Lint,
     UpdatedBefore,
     UpdatedAfter,
+    LogicalNot,
     Grouping,
     Unknown(String),
 }
 
-/// Takes 3 tokens from the iterator,
-/// and returns the middle one.
-/// Intended to extract the argument token, will panic if
-/// mismatches.
-fn extract_argument(tokens: &mut Iter<Token>) -> Token {
-    let mut tokens = tokens.take(3);
-    let argument = tokens.nth(1).cloned().unwrap();
-    tokens.next(); // Advance past the ArgumentEnd.
-    argument
+impl BangType {
+    /// What the argument of this bang should look like.
+    fn expected_argument(&self) -> &'static str {
+        match *self {
+            BangType::Format => "a format like flac",
+            BangType::Compare(NumericField::Duration) => "a comparison like >=3m30s or 3m..5m",
+            BangType::Compare(_) => "a comparison like >=320 or 128..256",
+            BangType::LessThan(NumericField::Duration)
+            | BangType::GreaterThan(NumericField::Duration) => "a duration like 3m30s",
+            BangType::LessThan(_) | BangType::GreaterThan(_) => "a number",
+            BangType::HasCoverArt
+            | BangType::HasMusicbrainzId
+            | BangType::HasDuplicates
+            | BangType::HasDuplicateAudio
+            | BangType::AlbumHasMixedFormats
+            | BangType::AlbumHasCoverArt
+            | BangType::AlbumIsIncomplete => "true or false",
+            BangType::UpdatedBefore | BangType::UpdatedAfter => {
+                "a date like 2018-01-31, or a relative date like 2weeks or yesterday"
+            }
+            BangType::Lint => "a lint like year, split, case, whitespace or any",
+            BangType::LogicalNot | BangType::Grouping => "a bang",
+            _ => "a search",
+        }
+    }
+}
+
+/// The error for a token that is not what was expected.
+/// Running into the end of the token stream is reported as
+/// the end of the input.
+fn unexpected(token: Option<SpannedToken>, expected: &'static str) -> Error {
+    match token {
+        Some(SpannedToken {
+            token: Token::InputEnd,
+            span,
+        }) => Error::LexerUnexpectedEndOfInput(span, expected),
+        Some(SpannedToken { token, span }) => Error::ParserUnexpectedToken(token, span, expected),
+        // Token streams from the lexer always end with InputEnd,
+        // so this is only reached if a stream was cut short.
+        None => Error::LexerUnexpectedEndOfInput(Span::default(), expected),
+    }
 }
 
-fn parse_bang<F, T>(producer: F, argument: Token) -> Result<Bang>
+/// Takes the sequence [ArgumentBegin, Argument, ArgumentEnd]
+/// from the iterator, and returns the argument and its span.
+fn extract_argument(tokens: &mut Iter<SpannedToken>) -> Result<(String, Span)> {
+    match tokens.next().cloned() {
+        Some(SpannedToken {
+            token: Token::ArgumentBegin,
+            ..
+        }) => (),
+        token => return Err(unexpected(token, "'{'")),
+    }
+    let argument = match tokens.next().cloned() {
+        Some(SpannedToken {
+            token: Token::Argument(argument),
+            span,
+        }) => (argument, span),
+        token => return Err(unexpected(token, "an argument")),
+    };
+    match tokens.next().cloned() {
+        Some(SpannedToken {
+            token: Token::ArgumentEnd,
+            ..
+        }) => Ok(argument),
+        token => Err(unexpected(token, "'}'")),
+    }
+}
+
+fn parse_bang<F, T>(producer: F, argument: (String, Span), expected: &'static str) -> Result<Bang>
 where
     T: FromStr,
     F: Fn(T) -> Bang,
 {
-    if let Token::Argument(argument) = argument {
-        let parsed = argument.parse::<T>();
-        if let Ok(parsed) = parsed {
-            Ok(producer(parsed))
-        } else {
-            Err(Error::ParserInvalidInput(argument))
+    let (argument, span) = argument;
+    match argument.parse::<T>() {
+        Ok(parsed) => Ok(producer(parsed)),
+        Err(_) => Err(Error::ParserInvalidInput(argument, span, expected)),
+    }
+}
+
+/// Parses a date for `!ubf` or `!uaf`. Dates like `2018-01-31` are kept
+/// as they are, but relative dates like `2weeks` are kept as written,
+/// so that they are resolved against the clock when the bang is queried.
+fn parse_date_bang<F>(producer: F, argument: (String, Span), expected: &'static str) -> Result<Bang>
+where
+    F: Fn(String) -> Bang,
+{
+    let (argument, span) = argument;
+    let value = argument.trim().to_owned();
+    if let Ok(date) = value.parse::<NaiveDate>() {
+        return Ok(producer(date.format("%Y-%m-%d").to_string()));
+    }
+    match parse_date(&value, Local::now().naive_local().date()) {
+        Some(_) => Ok(producer(value)),
+        None => Err(Error::ParserInvalidInput(argument, span, expected)),
+    }
+}
+
+/// Parses the kind of lint for `!lint`, where `any` matches every kind.
+fn parse_lint_bang(argument: (String, Span), expected: &'static str) -> Result<Bang> {
+    let (argument, span) = argument;
+    if argument.trim().eq_ignore_ascii_case("any") {
+        return Ok(Bang::Lint(None));
+    }
+    match argument.parse::<LintKind>() {
+        Ok(kind) => Ok(Bang::Lint(Some(kind))),
+        Err(_) => Err(Error::ParserInvalidInput(argument, span, expected)),
+    }
+}
+
+/// Parses a number to compare the given field against.
+/// Durations are written like `3m30s`, and are compared in ticks.
+fn parse_numeric_value(field: NumericField, value: &str) -> Option<i64> {
+    match field {
+        NumericField::Duration => value
+            .trim()
+            .parse::<Duration>()
+            .ok()
+            .map(|duration| duration.to_ticks()),
+        _ => value.trim().parse::<i32>().ok().map(|value| value as i64),
+    }
+}
+
+/// Parses a comparison such as `>=320`, `<1000`, `=44100` or the
+/// inclusive range `128..256`. A number on its own must match exactly.
+fn parse_comparison(field: NumericField, argument: &str) -> Option<Comparison> {
+    let value = |value: &str| parse_numeric_value(field, value);
+    let argument = argument.trim();
+    if argument.starts_with(">=") {
+        value(&argument[2..]).map(Comparison::GreaterThanOrEqual)
+    } else if argument.starts_with("<=") {
+        value(&argument[2..]).map(Comparison::LessThanOrEqual)
+    } else if argument.starts_with('>') {
+        value(&argument[1..]).map(Comparison::GreaterThan)
+    } else if argument.starts_with('<') {
+        value(&argument[1..]).map(Comparison::LessThan)
+    } else if argument.starts_with('=') {
+        value(&argument[1..]).map(Comparison::Equal)
+    } else if let Some(index) = argument.find("..") {
+        match (value(&argument[..index]), value(&argument[index + 2..])) {
+            (Some(low), Some(high)) if low <= high => Some(Comparison::Between(low, high)),
+            _ => None,
         }
     } else {
-        Err(Error::LexerUnexpectedEndOfInput)
+        value(argument).map(Comparison::Equal)
     }
 }
 
-pub fn take_until_braces_balanced<'a, 'b>(tokens: &'a mut Iter<Token>) -> Result<Vec<Token>> {
-    let mut group = Vec::<Token>::new();
-    // Assume that we have an argument begin here.
-    if let Some(&Token::ArgumentBegin) = tokens.next() {
-        let mut counter = 1;
-        while let Some(token) = tokens.next().cloned() {
-            match token {
-                Token::ArgumentBegin => counter += 1,
-                Token::ArgumentEnd => counter -= 1,
-                _ => (),
-            };
-            if counter != 0 {
-                group.push(token);
-            };
-            if counter == 0 {
-                // We need to pad the grouping with the
-                // InputEnd token, since parse_token_stream
-                // expects an InputEnd at the end.
-                group.push(Token::InputEnd);
-                return Ok(group);
-            }
+fn parse_numeric_bang<F>(
+    field: NumericField,
+    comparison: F,
+    argument: (String, Span),
+    expected: &'static str,
+) -> Result<Bang>
+where
+    F: Fn(NumericField, &str) -> Option<Comparison>,
+{
+    let (argument, span) = argument;
+    match comparison(field, &argument) {
+        Some(comparison) => Ok(Bang::Compare(field, comparison)),
+        None => Err(Error::ParserInvalidInput(argument, span, expected)),
+    }
+}
+
+/// Takes the braces of a grouping bang and the tokens between them.
+/// The group is padded with an InputEnd that has the span of the
+/// closing brace, so that it can be parsed as a token stream of its own.
+pub fn take_until_braces_balanced(tokens: &mut Iter<SpannedToken>) -> Result<Vec<SpannedToken>> {
+    let mut group = Vec::<SpannedToken>::new();
+    let mut end = match tokens.next().cloned() {
+        Some(SpannedToken {
+            token: Token::ArgumentBegin,
+            span,
+        }) => span.end,
+        token => return Err(unexpected(token, "'{'")),
+    };
+    let mut counter = 1;
+    while let Some(token) = tokens.next().cloned() {
+        end = token.span.end;
+        match token.token {
+            Token::ArgumentBegin => counter += 1,
+            Token::ArgumentEnd => counter -= 1,
+            _ => (),
+        };
+        if counter == 0 {
+            // We need to pad the grouping with the
+            // InputEnd token, since parse_token_stream
+            // expects an InputEnd at the end.
+            group.push(SpannedToken {
+                token: Token::InputEnd,
+                span: token.span,
+            });
+            return Ok(group);
         }
-        Err(Error::LexerUnexpectedEndOfInput)
-    } else {
-        panic!("Sent the wrong token!");
+        group.push(token);
     }
+    Err(Error::LexerUnexpectedEndOfInput(Span::new(end, end), "'}'"))
 }
 
-pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
+/// Parses a token stream into a bang.
+///
+/// Negation binds tightest, followed by AND, then OR, so
+/// `!a{} | -!b{} & !c{}` is parsed as `!a{} | ((NOT !b{}) & !c{})`.
+/// Operators of the same precedence associate to the left.
+pub fn parse_token_stream(tokens: &mut Iter<SpannedToken>) -> Result<Bang> {
     // We're assuming that the slice begins at the
     // start of a token stream.
     // valid tokens at the beginning are either a bang prefix (!),
-    // or the match all bang.
+    // a negation (-), or the match all bang.
+    if let Some(&Token::MatchAll) = peek(tokens) {
+        return Ok(Bang::All);
+    }
+
+    let bang = parse_or(tokens)?;
+    match tokens.next().cloned() {
+        Some(SpannedToken {
+            token: Token::InputEnd,
+            ..
+        }) => Ok(bang),
+        token => Err(unexpected(token, "'&' or '|'")),
+    }
+}
+
+/// The next token, without advancing the iterator.
+fn peek<'a>(tokens: &Iter<'a, SpannedToken>) -> Option<&'a Token> {
+    tokens.as_slice().first().map(|token| &token.token)
+}
+
+fn parse_or(tokens: &mut Iter<SpannedToken>) -> Result<Bang> {
+    let mut lhs = parse_and(tokens)?;
+    while let Some(&Token::LogicalOperator('|')) = peek(tokens) {
+        tokens.next();
+        lhs = Bang::LogicalOr(Box::new(lhs), Box::new(parse_and(tokens)?));
+    }
+    Ok(lhs)
+}
+
+fn parse_and(tokens: &mut Iter<SpannedToken>) -> Result<Bang> {
+    let mut lhs = parse_not(tokens)?;
+    while let Some(&Token::LogicalOperator('&')) = peek(tokens) {
+        tokens.next();
+        lhs = Bang::LogicalAnd(Box::new(lhs), Box::new(parse_not(tokens)?));
+    }
+    Ok(lhs)
+}
+
+fn parse_not(tokens: &mut Iter<SpannedToken>) -> Result<Bang> {
+    if let Some(&Token::LogicalNot) = peek(tokens) {
+        tokens.next();
+        return Ok(Bang::LogicalNot(Box::new(parse_not(tokens)?)));
+    }
+    parse_bang_expression(tokens)
+}
 
+/// Parses a single bang, starting from its bang prefix.
+fn parse_bang_expression(tokens: &mut Iter<SpannedToken>) -> Result<Bang> {
     let opening_token = tokens.next().cloned();
     match opening_token {
-        Some(Token::BangPrefix(_)) => (),
-        Some(Token::MatchAll) => return Ok(Bang::All),
-        Some(token) => return Err(Error::ParserUnexpectedToken(token)),
-        None => return Err(Error::LexerUnexpectedEndOfInput),
+        Some(SpannedToken {
+            token: Token::BangPrefix(_),
+            ..
+        }) => (),
+        token => return Err(unexpected(token, LexerMode::Bang.expected())),
     }
 
     // At this point the opening_token is a bang prefix,
@@ -151,138 +426,289 @@ pub fn parse_token_stream(tokens: &mut Iter<Token>) -> Result<Bang> {
 
     let bang_ident = tokens.next().cloned();
 
-    let lhs = if let Some(Token::BangIdentifier(bang_ident)) = bang_ident {
-        match bang_ident.as_bang_type() {
+    if let Some(SpannedToken {
+        token: Token::BangIdentifier(bang_ident),
+        span,
+    }) = bang_ident
+    {
+        let bang_type = bang_ident.as_bang_type();
+        let expected = bang_type.expected_argument();
+        match bang_type {
             // For all bangs that aren't groupings, we can just
             // assume that it follows the sequence
             // [ArgumentBegin, Argument, ArgumentEnd]
             BangType::TitleSearch => parse_bang(
                 |search: String| Bang::TitleSearch(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::TitleSearchExact => parse_bang(
                 |search: String| Bang::TitleSearchExact(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::FullTextSearch => parse_bang(
                 |search: String| Bang::FullTextSearch(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::FullTextSearchExact => parse_bang(
                 |search: String| Bang::FullTextSearchExact(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::AlbumTitle => parse_bang(
                 |search: String| Bang::AlbumTitle(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::AlbumTitleExact => parse_bang(
                 |search: String| Bang::AlbumTitleExact(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::AlbumArtists => parse_bang(
                 |search: String| Bang::AlbumArtists(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::AlbumArtistsExact => parse_bang(
                 |search: String| Bang::AlbumArtistsExact(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::Artist => parse_bang(
                 |search: String| Bang::Artist(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::ArtistExact => parse_bang(
                 |search: String| Bang::ArtistExact(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::Source => parse_bang(
                 |search: String| Bang::Source(search),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::Format => parse_bang(
                 |format: TrackFileType| Bang::Format(format),
-                extract_argument(tokens),
-            ),
-            BangType::DurationLessThan => parse_bang(
-                |duration: Duration| Bang::DurationLessThan(duration.to_ticks()),
-                extract_argument(tokens),
-            ),
-            BangType::DurationGreaterThan => parse_bang(
-                |duration: Duration| Bang::DurationGreaterThan(duration.to_ticks()),
-                extract_argument(tokens),
-            ),
-            BangType::BitrateLessThan => parse_bang(
-                |bitrate: i32| Bang::BitrateLessThan(bitrate),
-                extract_argument(tokens),
-            ),
-            BangType::BitrateGreaterThan => parse_bang(
-                |bitrate: i32| Bang::BitrateGreaterThan(bitrate),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
-            BangType::CoverArtWidthLessThan => parse_bang(
-                |cw: i32| Bang::CoverArtWidthLessThan(cw),
-                extract_argument(tokens),
-            ),
-            BangType::CoverArtWidthGreaterThan => parse_bang(
-                |cw: i32| Bang::CoverArtWidthGreaterThan(cw),
-                extract_argument(tokens),
+            BangType::Compare(field) => {
+                parse_numeric_bang(field, parse_comparison, extract_argument(tokens)?, expected)
+            }
+            BangType::LessThan(field) => parse_numeric_bang(
+                field,
+                |field, value| parse_numeric_value(field, value).map(Comparison::LessThan),
+                extract_argument(tokens)?,
+                expected,
             ),
-            BangType::CoverArtHeightLessThan => parse_bang(
-                |ch: i32| Bang::CoverArtHeightLessThan(ch),
-                extract_argument(tokens),
+            BangType::GreaterThan(field) => parse_numeric_bang(
+                field,
+                |field, value| parse_numeric_value(field, value).map(Comparison::GreaterThan),
+                extract_argument(tokens)?,
+                expected,
             ),
-            BangType::CoverArtHeightGreaterThan => parse_bang(
-                |ch: i32| Bang::CoverArtHeightGreaterThan(ch),
-                extract_argument(tokens),
+            BangType::HasCoverArt => parse_bang(
+                |c: bool| Bang::HasCoverArt(c),
+                extract_argument(tokens)?,
+                expected,
             ),
-            BangType::HasCoverArt => {
-                parse_bang(|c: bool| Bang::HasCoverArt(c), extract_argument(tokens))
-            }
             BangType::HasMusicbrainzId => parse_bang(
                 |mb: bool| Bang::HasMusicbrainzId(mb),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
             ),
             BangType::HasDuplicates => parse_bang(
                 |dup: bool| Bang::HasDuplicates(dup),
-                extract_argument(tokens),
+                extract_argument(tokens)?,
+                expected,
+            ),
+            BangType::HasDuplicateAudio => parse_bang(
+                |dup: bool| Bang::HasDuplicateAudio(dup),
+                extract_argument(tokens)?,
+                expected,
+            ),
+            BangType::AlbumHasMixedFormats => parse_bang(
+                |mixed: bool| Bang::AlbumHasMixedFormats(mixed),
+                extract_argument(tokens)?,
+                expected,
+            ),
+            BangType::AlbumHasCoverArt => parse_bang(
+                |c: bool| Bang::AlbumHasCoverArt(c),
+                extract_argument(tokens)?,
+                expected,
             ),
-            BangType::UpdatedBefore => parse_bang(
-                |ubf: NaiveDate| Bang::UpdatedBefore(ubf.format("%Y-%m-%d").to_string()),
-                extract_argument(tokens),
+            BangType::AlbumIsIncomplete => parse_bang(
+                |incomplete: bool| Bang::AlbumIsIncomplete(incomplete),
+                extract_argument(tokens)?,
+                expected,
             ),
-            BangType::UpdatedAfter => parse_bang(
-                |uaf: NaiveDate| Bang::UpdatedAfter(uaf.format("%Y-%m-%d").to_string()),
-                extract_argument(tokens),
+            BangType::Lint => parse_lint_bang(extract_argument(tokens)?, expected),
+            BangType::UpdatedBefore => parse_date_bang(
+                |ubf: String| Bang::UpdatedBefore(ubf),
+                extract_argument(tokens)?,
+                expected,
             ),
+            BangType::UpdatedAfter => parse_date_bang(
+                |uaf: String| Bang::UpdatedAfter(uaf),
+                extract_argument(tokens)?,
+                expected,
+            ),
+            BangType::LogicalNot => {
+                let negated_token_stream = take_until_braces_balanced(tokens)?;
+                Ok(Bang::LogicalNot(Box::new(parse_token_stream(
+                    &mut negated_token_stream.iter(),
+                )?)))
+            }
             BangType::Grouping => {
-                let mut grouping_token_stream = take_until_braces_balanced(tokens)?;
+                let grouping_token_stream = take_until_braces_balanced(tokens)?;
                 Ok(Bang::Grouping(Box::new(parse_token_stream(
                     &mut grouping_token_stream.iter(),
                 )?)))
             }
 
-            BangType::Unknown(unknown) => return Err(Error::ParserUnknownBang(unknown)),
+            BangType::Unknown(unknown) => Err(Error::ParserUnknownBang(unknown, span)),
         }
     } else {
-        return Err(Error::LexerUnexpectedEndOfInput);
-    };
+        Err(unexpected(bang_ident, LexerMode::BangIdentifier.expected()))
+    }
+}
 
-    // At this point, three tokens minimum should have been consumed.
-    match tokens.next().cloned() {
-        Some(Token::InputEnd) => lhs,
-        Some(Token::LogicalOperator(operator)) => match operator {
-            '|' => Ok(Bang::LogicalOr(
-                Box::new(lhs?),
-                Box::new(parse_token_stream(tokens)?),
-            )),
-            '&' => Ok(Bang::LogicalAnd(
-                Box::new(lhs?),
-                Box::new(parse_token_stream(tokens)?),
-            )),
-            c => Err(Error::ParserUnknownBang(c.to_string())),
-        },
-        Some(t) => Err(Error::ParserUnexpectedToken(t)),
-        None => Err(Error::LexerUnexpectedEndOfInput),
+#[cfg(test)]
+mod tests {
+    use super::super::bangs::{Bang, Comparison, NumericField};
+    use katatsuki::{FromPrimitive, TrackFileType};
+    use lint::LintKind;
+    use proptest::prelude::*;
+
+    /// Removes every grouping from the bang. The printer only writes
+    /// groupings where they are needed, so only the rest of the tree
+    /// round trips.
+    fn ungroup(bang: Bang) -> Bang {
+        match bang {
+            Bang::Grouping(bang) => ungroup(*bang),
+            Bang::LogicalAnd(lhs, rhs) => {
+                Bang::LogicalAnd(Box::new(ungroup(*lhs)), Box::new(ungroup(*rhs)))
+            }
+            Bang::LogicalOr(lhs, rhs) => {
+                Bang::LogicalOr(Box::new(ungroup(*lhs)), Box::new(ungroup(*rhs)))
+            }
+            Bang::LogicalNot(bang) => Bang::LogicalNot(Box::new(ungroup(*bang))),
+            bang => bang,
+        }
+    }
+
+    /// Searches with the characters that have to be escaped, or that
+    /// mean something outside of an argument.
+    fn search() -> BoxedStrategy<String> {
+        "[a-zA-Z0-9 éß日{}!&|`\\\\-]{0,8}".boxed()
+    }
+
+    fn numeric_field() -> BoxedStrategy<NumericField> {
+        prop_oneof![
+            Just(NumericField::Bitrate),
+            Just(NumericField::CoverArtWidth),
+            Just(NumericField::CoverArtHeight),
+            Just(NumericField::Duration),
+            Just(NumericField::Year),
+            Just(NumericField::SampleRate),
+            Just(NumericField::TrackNumber),
+            Just(NumericField::DiscNumber),
+        ].boxed()
+    }
+
+    fn comparison(field: NumericField) -> BoxedStrategy<Comparison> {
+        let value = match field {
+            // Durations are in ticks, and are written down to the millisecond.
+            NumericField::Duration => (0i64..100_000_000).prop_map(|ms| ms * 10_000).boxed(),
+            _ => (-5i64..100_000).boxed(),
+        };
+        prop_oneof![
+            value.clone().prop_map(Comparison::Equal),
+            value.clone().prop_map(Comparison::LessThan),
+            value.clone().prop_map(Comparison::LessThanOrEqual),
+            value.clone().prop_map(Comparison::GreaterThan),
+            value.clone().prop_map(Comparison::GreaterThanOrEqual),
+            (value.clone(), value)
+                .prop_map(|(low, high)| Comparison::Between(low.min(high), low.max(high))),
+        ].boxed()
+    }
+
+    fn lint_kind() -> BoxedStrategy<Option<LintKind>> {
+        prop_oneof![
+            Just(None),
+            Just(Some(LintKind::InconsistentYear)),
+            Just(Some(LintKind::SplitAlbum)),
+            Just(Some(LintKind::CaseCollision)),
+            Just(Some(LintKind::SuspiciousWhitespace)),
+        ].boxed()
+    }
+
+    /// Every bang that takes an argument or stands on its own.
+    fn leaf() -> BoxedStrategy<Bang> {
+        prop_oneof![
+            search().prop_map(Bang::TitleSearch),
+            search().prop_map(Bang::TitleSearchExact),
+            search().prop_map(Bang::FullTextSearch),
+            search().prop_map(Bang::FullTextSearchExact),
+            search().prop_map(Bang::AlbumTitle),
+            search().prop_map(Bang::AlbumTitleExact),
+            search().prop_map(Bang::AlbumArtists),
+            search().prop_map(Bang::AlbumArtistsExact),
+            search().prop_map(Bang::Artist),
+            search().prop_map(Bang::ArtistExact),
+            search().prop_map(Bang::Source),
+            (0i32..25)
+                .prop_map(|file_type| Bang::Format(TrackFileType::from_i32(file_type).unwrap())),
+            Just(Bang::Format(TrackFileType::MP3)),
+            numeric_field().prop_flat_map(|field| {
+                comparison(field).prop_map(move |comparison| Bang::Compare(field, comparison))
+            }),
+            any::<bool>().prop_map(Bang::HasCoverArt),
+            any::<bool>().prop_map(Bang::HasMusicbrainzId),
+            any::<bool>().prop_map(Bang::HasDuplicates),
+            any::<bool>().prop_map(Bang::HasDuplicateAudio),
+            any::<bool>().prop_map(Bang::AlbumHasMixedFormats),
+            any::<bool>().prop_map(Bang::AlbumHasCoverArt),
+            any::<bool>().prop_map(Bang::AlbumIsIncomplete),
+            lint_kind().prop_map(Bang::Lint),
+            Just(Bang::UpdatedBefore("2018-01-31".to_owned())),
+            Just(Bang::UpdatedAfter("2018-01-31".to_owned())),
+        ].boxed()
+    }
+
+    fn bang() -> BoxedStrategy<Bang> {
+        leaf()
+            .prop_recursive(4, 32, 2, |inner| {
+                prop_oneof![
+                    (inner.clone(), inner.clone())
+                        .prop_map(|(lhs, rhs)| Bang::LogicalAnd(Box::new(lhs), Box::new(rhs))),
+                    (inner.clone(), inner.clone())
+                        .prop_map(|(lhs, rhs)| Bang::LogicalOr(Box::new(lhs), Box::new(rhs))),
+                    inner.clone().prop_map(|bang| Bang::LogicalNot(Box::new(bang))),
+                    inner.prop_map(|bang| Bang::Grouping(Box::new(bang))),
+                ]
+            })
+            .boxed()
+    }
+
+    proptest! {
+        #[test]
+        fn printed_bangs_parse_back(bang in bang()) {
+            let printed = bang.to_string();
+            let parsed = Bang::new(&printed);
+            prop_assert!(parsed.is_ok(), "{:?} did not parse: {:?}", printed, parsed);
+            prop_assert_eq!(ungroup(parsed.unwrap()), ungroup(bang), "{:?}", printed);
+        }
+    }
+
+    #[test]
+    fn all_prints_as_the_empty_query() {
+        assert_eq!(Bang::All.to_string(), "");
+        assert_eq!(Bang::new("").unwrap(), Bang::All);
     }
 }
@@ -6,8 +6,10 @@
 const TICKS_PER_MS: i64 = 10000;
 const NS_PER_TICK: i64 = 100;
 const SEC_PER_MS: i64 = 1000;
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
 use humantime::Duration;
 use chrono::NaiveDate;
+use chrono::Duration as ChronoDuration;
 
 pub fn ticks_to_ms(ticks: i64) -> i32 {
     (ticks / TICKS_PER_MS) as i32
@@ -29,3 +31,61 @@ impl TickRepr for Duration {
         ticks
     }
 }
+
+/// Formats a duration in ticks like `3m30s`, so that it
+/// parses back to the same number of ticks.
+pub fn format_ticks(ticks: i64) -> String {
+    if ticks == 0 {
+        return "0s".to_owned();
+    }
+    let units = [
+        ("h", 60 * 60 * SEC_PER_MS * TICKS_PER_MS),
+        ("m", 60 * SEC_PER_MS * TICKS_PER_MS),
+        ("s", SEC_PER_MS * TICKS_PER_MS),
+        ("ms", TICKS_PER_MS),
+        ("us", 1000 / NS_PER_TICK),
+        ("ns", 1),
+    ];
+    let mut formatted = String::new();
+    let mut remaining = ticks;
+    for &(unit, ticks_per_unit) in units.iter() {
+        let count = remaining / ticks_per_unit;
+        remaining %= ticks_per_unit;
+        if count > 0 {
+            // Nanoseconds are counted in ticks, which are 100ns each.
+            let count = if unit == "ns" { count * NS_PER_TICK } else { count };
+            formatted.push_str(&format!("{}{}", count, unit));
+        }
+    }
+    formatted
+}
+
+/// The longest relative date that is accepted, so that it can not
+/// overflow the calendar.
+const MAX_RELATIVE_DAYS: u64 = 365 * 10000;
+
+/// Parses a date like `2018-01-31`, or a date relative to today like
+/// `today`, `yesterday`, `2weeks` or `3 months ago`. Relative dates
+/// are counted back from today in whole days.
+pub fn parse_date(value: &str, today: NaiveDate) -> Option<NaiveDate> {
+    let value = value.trim();
+    if let Ok(date) = value.parse::<NaiveDate>() {
+        return Some(date);
+    }
+    if value.eq_ignore_ascii_case("today") {
+        return Some(today);
+    }
+    if value.eq_ignore_ascii_case("yesterday") {
+        return today.pred_opt();
+    }
+    let value = if value.ends_with("ago") {
+        value[..value.len() - 3].trim()
+    } else {
+        value
+    };
+    let days = value.parse::<Duration>().ok()?.as_secs() / SECS_PER_DAY;
+    if days > MAX_RELATIVE_DAYS {
+        return None;
+    }
+    today.checked_sub_signed(ChronoDuration::days(days as i64))
+}
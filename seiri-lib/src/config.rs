@@ -1,6 +1,10 @@
 use std::env::home_dir;
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::path::Path;
+use bangs::Bang;
+use events::Event;
+use naming::NamingTemplate;
 use paths::*;
 use std::fs;
 use toml;
@@ -9,6 +13,13 @@ use toml;
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub music_folder: String,
+    /// Where tracks are placed in the music folder.
+    /// Configurations from before templates keep the original layout.
+    #[serde(default)]
+    pub naming_template: NamingTemplate,
+    /// Queries that can be used in other queries by name, like bangs.
+    #[serde(default)]
+    pub macros: BTreeMap<String, String>,
 }
 
 impl Default for Config {
@@ -16,7 +27,11 @@ impl Default for Config {
         let mut home_dir = home_dir().unwrap();
         home_dir.push("Music");
         home_dir.push("seiri");
-        Config { music_folder: home_dir.to_str().unwrap().to_owned() }
+        Config {
+            music_folder: home_dir.to_str().unwrap().to_owned(),
+            naming_template: NamingTemplate::default(),
+            macros: BTreeMap::new(),
+        }
     }
 }
 
@@ -30,18 +45,24 @@ pub fn get_config() -> Config {
     config_path.push("config.toml");
     if !config_path.exists() {
         if let None = write_default_config(config_path.as_path()) {
-            eprintln!("CONFIGWRITEERR~Unable to write default configuration.");
+            Event::ConfigWriteError.emit();
             panic!("CONFIGWRITEERR~Unable to write default configuration.");
         }
     }
 
     // Should be safe to unwrap since 
     let config_string = fs::read_to_string(config_path).unwrap();
-    let config = toml::from_str(&config_string);
-    if let Ok(config) = config {
-        config
-    } else {
-        eprintln!("CONFIGINVALID~Configuration file is in invalid format!");
-        panic!("CONFIGWRITEERR~Unable to write default configuration.");
+    let config = toml::from_str::<Config>(&config_string)
+        .map_err(|err| err.to_string())
+        .and_then(|config| match Bang::check_macros(&config.macros) {
+            Ok(()) => Ok(config),
+            Err(err) => Err(err.to_string()),
+        });
+    match config {
+        Ok(config) => config,
+        Err(message) => {
+            Event::ConfigInvalid { message }.emit();
+            panic!("CONFIGWRITEERR~Unable to write default configuration.");
+        }
     }
 }
\ No newline at end of file
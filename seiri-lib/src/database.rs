@@ -5,55 +5,71 @@ use bangs::ticks_to_ms;
 use r2d2::{CustomizeConnection, Pool};
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Error, Result};
-use bangs::Bang;
-use rand::{thread_rng, Rng};
+use bangs::{parse_date, Bang, Comparison, NumericField};
+use chrono::Local;
 use regex::Regex;
 use rusqlite::types::ToSql;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use katatsuki::Track;
 use katatsuki::TrackFileType;
 use katatsuki::{ToPrimitive, FromPrimitive};
 use paths::get_appdata_path;
+use migrations;
+use error::Error as SeiriError;
+use fingerprint;
+use events::Event;
+use albums::TrackPosition;
+use lint::{self, FolderIndex, LintKind};
+use matching::{search_tokens, TrackLookup};
+use naming::NamingTemplate;
+use sort::{SortColumn, SortSpec};
 
 pub use rusqlite::Connection;
 
 pub type ConnectionPool = Pool<SqliteConnectionManager>;
 
-#[derive(Copy, Clone, Debug)]
-struct SeiriConnectionCustomizer;
+#[derive(Clone, Debug)]
+struct SeiriConnectionCustomizer {
+    database_path: PathBuf,
+}
+
 impl CustomizeConnection<Connection, Error> for SeiriConnectionCustomizer {
     fn on_acquire(&self, conn: &mut Connection) -> Result<()> {
         enable_wal_mode(conn).unwrap();
         add_regexp_function(conn).unwrap();
-        create_database(conn);
-        Ok(())
+        migrations::migrate(conn, &self.database_path)
+            .map_err(|err| Error::UserFunctionError(Box::new(err)))
     }
 }
 
-pub fn get_database_connection() -> Connection {
+fn get_database_path() -> PathBuf {
     let mut database_path = get_appdata_path();
     database_path.push("tracks.db");
+    database_path
+}
+
+pub fn get_database_connection() -> Connection {
+    let database_path = get_database_path();
     let conn = Connection::open(database_path.as_path()).unwrap();
     enable_wal_mode(&conn).unwrap();
     add_regexp_function(&conn).unwrap();
-    create_database(&conn);
+    migrate_database(&conn, &database_path);
     conn
 }
 
 pub fn get_connection_pool() -> ConnectionPool {
-    let mut database_path = get_appdata_path();
-    database_path.push("tracks.db");
+    let database_path = get_database_path();
     let manager = SqliteConnectionManager::file(&database_path);
     let pool = Pool::builder()
-        .connection_customizer(Box::new(SeiriConnectionCustomizer))
+        .connection_customizer(Box::new(SeiriConnectionCustomizer { database_path }))
         .build(manager)
         .unwrap();
     pool
 }
 
-
 fn escape_regex_search(string: &str) -> String {
     string.replace('\\', r"\\")
           .replace('?', r"\?")
@@ -87,40 +103,52 @@ pub fn add_regexp_function(db: &Connection) -> Result<()> {
             }
         };
 
-        let captures = regex.captures(&text);
-        let capture_ok = captures
-            .and_then(|capture| capture.get(1))
-            .and_then(|m| Some(m.end() > 0))
-            .unwrap_or(false);
-        Ok(capture_ok)
+        Ok(regexp_matches(regex, &text))
     })
 }
 
-#[allow(dead_code)]
-pub fn create_database(conn: &Connection) {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS tracks ( 
-        FilePath TEXT PRIMARY KEY,
-        Title TEXT,
-        Artist TEXT,
-        AlbumArtists TEXT,
-        Album TEXT,
-        Year INTEGER,
-        TrackNumber INTEGER, 
-        MusicBrainzTrackId TEXT,
-        HasFrontCover INTEGER,
-        FrontCoverWidth INTEGER,
-        FrontCoverHeight INTEGER,
-        Bitrate INTEGER,
-        SampleRate INTEGER,
-        Source TEXT,
-        DiscNumber INTEGER,
-        Duration INTEGER,
-        FileType INTEGER,
-        Updated DATE
-    )",
-        &[],
-    ).unwrap();
+/// Whether the text matches a pattern the way `REGEXP` does,
+/// which is when the first group of the pattern matches after the
+/// start of the text.
+pub fn regexp_matches(regex: &Regex, text: &str) -> bool {
+    regex
+        .captures(text)
+        .and_then(|capture| capture.get(1))
+        .map(|m| m.end() > 0)
+        .unwrap_or(false)
+}
+
+/// The pattern that album artists are matched against to find an
+/// artist that contains the search, regardless of case.
+pub fn album_artists_search_pattern(search: &str) -> String {
+    format!("(?:^|;)(?:.*?)((?i:{}))(?:.*?)(?:;|$)", escape_regex_search(search))
+}
+
+/// The pattern that album artists are matched against to find an
+/// artist that is exactly the search.
+pub fn album_artists_exact_pattern(search: &str) -> String {
+    format!("(?:^|;)({})(?:;|$)", escape_regex_search(search))
+}
+
+/// Applies any pending schema migrations to the database.
+/// Panics if the database is newer than this build of seiri,
+/// or could not be migrated.
+pub fn migrate_database(conn: &Connection, database_path: &Path) {
+    match migrations::migrate(conn, database_path) {
+        Ok(()) => (),
+        Err(err @ SeiriError::DatabaseTooNew(..)) => {
+            Event::DatabaseTooNew {
+                message: err.to_string(),
+            }.emit();
+            panic!("DATABASETOONEW~{}", err);
+        }
+        Err(err) => {
+            Event::DatabaseMigrateError {
+                message: err.to_string(),
+            }.emit();
+            panic!("DATABASEMIGRATEERR~{}", err);
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -130,44 +158,141 @@ pub fn enable_wal_mode(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
-#[allow(dead_code)]
-pub fn query_tracks(
+/// The parameters of a query, which are named in the order they are
+/// made, so that a bang always compiles to the same query.
+#[derive(Debug, Default)]
+struct QueryParams {
+    params: Vec<(String, String)>,
+    names: usize,
+}
+
+impl QueryParams {
+    fn next_name(&mut self) -> String {
+        self.names += 1;
+        format!(":p{}", self.names)
+    }
+
+    fn push(&mut self, param: (String, String)) {
+        self.params.push(param)
+    }
+
+    fn as_named_params(&self) -> Vec<(&str, &dyn ToSql)> {
+        self.params
+            .iter()
+            .map(|c| (c.0.as_ref(), &c.1 as &dyn ToSql))
+            .collect()
+    }
+}
+
+/// Compiles a bang into a query of the tracks table, and its parameters.
+fn compile_query(
     bang: Bang,
-    conn: &Connection,
+    sort: &SortSpec,
     limit: Option<i32>,
     offset: Option<i32>,
-) -> Result<Vec<Track>> {
-    let mut params = Vec::<(String, String)>::new();
-    let mut query = if let Bang::All = bang {
-        "SELECT * FROM tracks".to_string()
+) -> (String, QueryParams) {
+    let mut params = QueryParams::default();
+
+    // A full text search on its own is ordered by relevance, unless a sort is given.
+    let ranked_search = match bang {
+        Bang::FullTextSearch(ref search) if sort.keys.is_empty() => to_fts_query(search),
+        _ => None,
+    };
+
+    let mut query = if let Some(fts_query) = ranked_search {
+        let param_name = params.next_name();
+        let query = format!(
+            "SELECT tracks.* FROM (SELECT rowid AS SearchRowId, rank AS SearchRank FROM tracks_fts WHERE tracks_fts MATCH {}) AS search \
+             JOIN tracks ON tracks.rowid = search.SearchRowId ORDER BY search.SearchRank, {}",
+            param_name,
+            sort.to_order_by_clause()
+        );
+        params.push((param_name, fts_query));
+        query
     } else {
-        format!(
-            "SELECT * FROM tracks WHERE ({})",
-            to_query_string(bang, &mut params)
-        )
+        let query = if let Bang::All = bang {
+            "SELECT * FROM tracks".to_string()
+        } else {
+            format!(
+                "SELECT * FROM tracks WHERE ({})",
+                to_query_string(bang, &mut params)
+            )
+        };
+        format!("{} ORDER BY {}", query, sort.to_order_by_clause())
     };
 
-    if let Some(limit) = limit {
-        query.push_str(&format!(" LIMIT {}", limit));
+    // SQLite only allows an offset after a limit, and a negative limit has no bound.
+    if limit.is_some() || offset.is_some() {
+        query.push_str(&format!(" LIMIT {}", limit.unwrap_or(-1)));
     }
 
     if let Some(offset) = offset {
         query.push_str(&format!(" OFFSET {}", offset));
     }
 
-    query.push_str(" ORDER BY CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists,Album,TrackNumber");
+    (query, params)
+}
 
-    let mut tracks = Vec::<Track>::new();
-    println!("Executing query: {:?}", query);
-    let mut statement = conn.prepare(&query)?;
-    println!("Preparing parameters: {:?}", params);
+/// What a bang compiles to, for debugging queries.
+#[derive(Debug)]
+pub struct QueryExplanation {
+    /// The SQL of the query.
+    pub query: String,
+    /// The name and value of each parameter of the query, in order of name.
+    pub params: Vec<(String, String)>,
+    /// The detail of each step of SQLite's query plan for the query.
+    pub plan: Vec<String>,
+}
 
-    let params = params
-        .iter()
-        .map(|c| (c.0.as_ref(), &c.1 as &ToSql))
-        .collect::<Vec<(&str, &ToSql)>>();
+/// Compiles a bang the same way `query_tracks` does, and asks SQLite
+/// how it would run the query, without running it. There is no plan for
+/// a bang that looks for case collisions, since the table of collisions
+/// is only filled when the query is run.
+pub fn explain(
+    bang: Bang,
+    conn: &Connection,
+    sort: &SortSpec,
+    limit: Option<i32>,
+    offset: Option<i32>,
+) -> Result<QueryExplanation> {
+    let has_plan = !uses_case_collisions(&bang);
+    let (query, params) = compile_query(bang, sort, limit, offset);
+    let mut plan = Vec::new();
+    if has_plan {
+        let mut statement = conn.prepare(&format!("EXPLAIN QUERY PLAN {}", query))?;
+        let mut rows = statement.query_named(params.as_named_params().as_slice())?;
+        while let Some(Ok(row)) = rows.next() {
+            // The detail is the fourth column, whichever version of SQLite this is.
+            plan.push(row.get_checked(3)?);
+        }
+    }
+    let mut params = params.params;
+    params.sort_by_key(|&(ref name, _)| name[2..].parse::<usize>().unwrap_or(0));
+    Ok(QueryExplanation {
+        query,
+        params,
+        plan,
+    })
+}
 
-    let mut rows = statement.query_named(params.as_slice())?;
+/// Queries the tracks that match the bang. The naming template is
+/// used to find the tracks whose folders collide apart from case.
+#[allow(dead_code)]
+pub fn query_tracks(
+    bang: Bang,
+    conn: &Connection,
+    template: &NamingTemplate,
+    sort: &SortSpec,
+    limit: Option<i32>,
+    offset: Option<i32>,
+) -> Result<Vec<Track>> {
+    if uses_case_collisions(&bang) {
+        find_case_collisions(conn, template)?;
+    }
+    let (query, params) = compile_query(bang, sort, limit, offset);
+    let mut tracks = Vec::<Track>::new();
+    let mut statement = conn.prepare(&query)?;
+    let mut rows = statement.query_named(params.as_named_params().as_slice())?;
     while let Some(Ok(row)) = rows.next() {
         let track = Track {
             file_path: PathBuf::from(&row.get_checked::<_, String>(0)?),
@@ -191,7 +316,8 @@ pub fn query_tracks(
             duration: ticks_to_ms(row.get_checked(15)?),
             file_type: TrackFileType::from_i32(row.get_checked::<_, i32>(16)?)
                 .unwrap_or(TrackFileType::Unknown),
-            updated: row.get_checked::<_, String>(17)?
+            updated: row.get_checked::<_, String>(17)?,
+            disc_count: row.get_checked(19).ok().unwrap_or(0),
         };
         tracks.push(track)
     }
@@ -199,75 +325,236 @@ pub fn query_tracks(
     Ok(tracks)
 }
 
-#[allow(dead_code)]
-fn get_rand_param() -> String {
-    let mut rng = thread_rng();
-    format!(":{}", rng.gen_ascii_chars().take(10).collect::<String>()).to_owned()
+/// Resolves a relative date like `2weeks` against the local clock,
+/// into the same form as the updated date of a track.
+pub fn resolve_date(date: &str) -> String {
+    match parse_date(date, Local::now().naive_local().date()) {
+        Some(date) => date.format("%Y-%m-%d").to_string(),
+        None => date.to_owned(),
+    }
 }
 
-#[allow(dead_code)]
-fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
+fn numeric_column_name(field: NumericField) -> &'static str {
+    match field {
+        NumericField::Bitrate => "Bitrate",
+        NumericField::CoverArtWidth => "FrontCoverWidth",
+        NumericField::CoverArtHeight => "FrontCoverHeight",
+        NumericField::Duration => "Duration",
+        NumericField::Year => "Year",
+        NumericField::SampleRate => "SampleRate",
+        NumericField::TrackNumber => "TrackNumber",
+        NumericField::DiscNumber => "DiscNumber",
+    }
+}
+
+/// Characters of scripts that aren't written with spaces between words.
+/// The search index can only find whole words or their prefixes, so
+/// searches for these are done without it.
+fn is_unspaced_script(c: char) -> bool {
+    match c as u32 {
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xF900..=0xFAFF
+        | 0xFF66..=0xFF9F => true,
+        _ => false,
+    }
+}
+
+fn is_indexable(search: &str) -> bool {
+    !search_tokens(search).is_empty() && !search.chars().any(is_unspaced_script)
+}
+
+/// Splits a search into the phrases that the search index matches as
+/// prefixes, which are each word, and words in double quotes together.
+/// Returns None if the search can't be answered from the index.
+pub fn search_phrases(search: &str) -> Option<Vec<&str>> {
+    if !is_indexable(search) {
+        return None;
+    }
+    let mut phrases = Vec::new();
+    for (index, term) in search.split('"').enumerate() {
+        // Every other part of the search is between double quotes.
+        if index % 2 == 1 {
+            phrases.push(term);
+        } else {
+            phrases.extend(term.split_whitespace());
+        }
+    }
+    phrases.retain(|phrase| !search_tokens(phrase).is_empty());
+    Some(phrases)
+}
+
+/// Converts a search into a query of the search index, where each word
+/// matches as a prefix, and words in double quotes match as a phrase.
+/// Returns None if the search can't be answered from the index.
+fn to_fts_query(search: &str) -> Option<String> {
+    search_phrases(search).map(|phrases| {
+        phrases
+            .iter()
+            .map(|phrase| format!("\"{}\"*", phrase))
+            .collect::<Vec<String>>()
+            .join(" ")
+    })
+}
+
+/// Converts a search into a query of the search index that matches
+/// the whole search as a phrase.
+fn to_fts_phrase(search: &str) -> Option<String> {
+    if is_indexable(search) {
+        Some(format!("\"{}\"", search.replace('"', "\"\"")))
+    } else {
+        None
+    }
+}
+
+/// Rebuilds the full text search index from the tracks table.
+pub fn rebuild_search_index(conn: &Connection) -> Result<()> {
+    conn.execute_batch("INSERT INTO tracks_fts(tracks_fts) VALUES('rebuild');")
+}
+
+/// The album artists and title of every album that `find_album_problems`
+/// finds a problem with. Each disc is checked for tracks without a
+/// track number, duplicate track numbers and gaps before its last track,
+/// then the album is checked for gaps in its discs, and for a disc count
+/// that its last disc does not match. Tracks without a disc number are
+/// on the first disc.
+const INCOMPLETE_ALBUMS_QUERY: &str = "\
+    SELECT AlbumArtists, Album FROM (\
+        SELECT AlbumArtists, Album, MAX(COALESCE(DiscNumber, 0), 1) AS Disc, \
+            COALESCE(MAX(DiscCount), 0) AS Discs, COUNT(*) AS Tracks, \
+            COUNT(DISTINCT COALESCE(TrackNumber, 0)) AS Numbers, \
+            MIN(COALESCE(TrackNumber, 0)) AS First, MAX(COALESCE(TrackNumber, 0)) AS Last \
+        FROM tracks GROUP BY AlbumArtists, Album, Disc) \
+    GROUP BY AlbumArtists, Album \
+    HAVING MAX(First < 1 OR Tracks != Numbers OR Last != Numbers) \
+        OR COUNT(*) != MAX(Disc) OR (MAX(Discs) > 0 AND MAX(Discs) != MAX(Disc))";
+
+/// Whether the column has spaces at its start or end, runs of spaces,
+/// or whitespace other than spaces.
+fn suspicious_whitespace_condition(column: &str) -> String {
+    format!(
+        "(substr({0}, 1, 1) = ' ' OR substr({0}, -1) = ' ' OR instr({0}, '  ') > 0 \
+         OR instr({0}, char(9)) > 0 OR instr({0}, char(10)) > 0 OR instr({0}, char(13)) > 0 \
+         OR instr({0}, char(160)) > 0)",
+        column
+    )
+}
+
+/// Whether the bang looks for case collisions, which need the table
+/// that `find_case_collisions` fills.
+fn uses_case_collisions(bang: &Bang) -> bool {
+    match *bang {
+        Bang::Lint(None) | Bang::Lint(Some(LintKind::CaseCollision)) => true,
+        Bang::LogicalAnd(ref lhs, ref rhs) | Bang::LogicalOr(ref lhs, ref rhs) => {
+            uses_case_collisions(lhs) || uses_case_collisions(rhs)
+        }
+        Bang::LogicalNot(ref bang) | Bang::Grouping(ref bang) => uses_case_collisions(bang),
+        _ => false,
+    }
+}
+
+/// Fills a temporary table with the tracks whose folders collide apart
+/// from case. SQL can't render the naming template, so the folders are
+/// found in Rust each time a query looks for case collisions.
+fn find_case_collisions(conn: &Connection, template: &NamingTemplate) -> Result<()> {
+    let tracks = query_tracks(Bang::All, conn, template, &SortSpec::default(), None, None)?;
+    conn.execute_batch(
+        "CREATE TEMP TABLE IF NOT EXISTS case_collisions(FilePath TEXT PRIMARY KEY);
+         DELETE FROM temp.case_collisions;",
+    )?;
+    for (group, _) in lint::case_collisions(&tracks, template) {
+        for track in group {
+            conn.execute(
+                "INSERT OR IGNORE INTO temp.case_collisions (FilePath) VALUES (?1)",
+                &[&track.file_path.to_string_lossy().into_owned()],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The condition for tracks with the given kind of lint, which matches
+/// the same tracks that `lint_tracks` reports. `lower` and `trim` only
+/// fold ASCII letters and trim spaces, which the lint does the same way.
+/// Case collisions are found in Rust by `find_case_collisions` instead.
+fn lint_condition(kind: LintKind) -> String {
+    match kind {
+        LintKind::InconsistentYear => {
+            "((AlbumArtists, Album) in (select AlbumArtists, Album from tracks group by AlbumArtists, Album having count(distinct Year) > 1))".to_owned()
+        }
+        LintKind::SplitAlbum => {
+            "((lower(trim(AlbumArtists)), lower(trim(Album))) in (select lower(trim(AlbumArtists)), lower(trim(Album)) from (select distinct AlbumArtists, Album from tracks) group by 1, 2 having count(*) > 1))".to_owned()
+        }
+        LintKind::CaseCollision => "(FilePath in (select FilePath from temp.case_collisions))".to_owned(),
+        // Album artists are stored separated by semicolons, so each of them is
+        // checked for spaces at its start or end too.
+        LintKind::SuspiciousWhitespace => format!(
+            "({} OR {} OR {} OR {} OR instr(AlbumArtists, '; ') > 0 OR instr(AlbumArtists, ' ;') > 0)",
+            suspicious_whitespace_condition("Title"),
+            suspicious_whitespace_condition("Artist"),
+            suspicious_whitespace_condition("Album"),
+            suspicious_whitespace_condition("AlbumArtists")
+        ),
+    }
+}
+
+fn to_query_string(bang: Bang, params: &mut QueryParams) -> String {
     match bang {
         Bang::FilePath(path) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(FilePath = {})", param_name);
             params.push((param_name, format!("{}", path)));
             format
         }
         Bang::TitleSearch(title) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Title LIKE {})", param_name);
             params.push((param_name, format!("%{}%", title)));
             format
         }
         Bang::TitleSearchExact(title) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Title = {})", param_name);
             params.push((param_name, title));
             format
         }
         Bang::AlbumTitle(title) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Album LIKE {})", param_name);
             params.push((param_name, format!("%{}%", title)));
             format
         }
         Bang::AlbumTitleExact(title) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Album = {})", param_name);
             params.push((param_name, title));
             format
         }
         Bang::Artist(artist) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Artist LIKE {})", param_name);
             params.push((param_name, format!("%{}%", artist)));
             format
         }
         Bang::ArtistExact(artist) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Artist = {})", param_name);
             params.push((param_name, format!("{}", artist)));
             format
         }
         // todo: (Might want to make this smarter?)
         Bang::AlbumArtists(artist) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(AlbumArtists REGEXP {})", param_name);
-            params.push((
-                param_name,
-                format!("(?:^|;)(?:.*?)((?i){})(?:.*?)(?:;|$)", escape_regex_search(&artist)),
-            ));
+            params.push((param_name, album_artists_search_pattern(&artist)));
             format
         }
         Bang::AlbumArtistsExact(artist) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(AlbumArtists REGEXP {})", param_name);
-            params.push((param_name, format!("(?:^|;)({})(?:;|$)", escape_regex_search(&artist))));
+            params.push((param_name, album_artists_exact_pattern(&artist)));
             format
         }
         Bang::Source(source) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Source = {} COLLATE NOCASE)", param_name);
             params.push((param_name, format!("{}", source)));
             format
@@ -276,117 +563,98 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
 
             match filetype {
                 TrackFileType::FLAC => {
-                    let param_name_lesser = get_rand_param();
-                    let param_name_greater = get_rand_param();
+                    let param_name_lesser = params.next_name();
+                    let param_name_greater = params.next_name();
                     let format = format!("(FileType BETWEEN {} AND {})", param_name_lesser, param_name_greater);
                     params.push((param_name_lesser, format!("{}", TrackFileType::FLAC4.to_i32().unwrap())));
                     params.push((param_name_greater, format!("{}", TrackFileType::FLAC.to_i32().unwrap())));
                     format
                 }
                 TrackFileType::AIFF => {
-                    let param_name_lesser = get_rand_param();
-                    let param_name_greater = get_rand_param();
+                    let param_name_lesser = params.next_name();
+                    let param_name_greater = params.next_name();
                     let format = format!("(FileType BETWEEN {} AND {})", param_name_lesser, param_name_greater);
                     params.push((param_name_lesser, format!("{}", TrackFileType::AIFF4.to_i32().unwrap())));
                     params.push((param_name_greater, format!("{}", TrackFileType::AIFF.to_i32().unwrap())));
                     format
                 }
                 TrackFileType::ALAC => {
-                    let param_name_lesser = get_rand_param();
-                    let param_name_greater = get_rand_param();
+                    let param_name_lesser = params.next_name();
+                    let param_name_greater = params.next_name();
                     let format = format!("(FileType BETWEEN {} AND {})", param_name_lesser, param_name_greater);
                     params.push((param_name_lesser, format!("{}", TrackFileType::ALAC16.to_i32().unwrap())));
                     params.push((param_name_greater, format!("{}", TrackFileType::ALAC.to_i32().unwrap())));
                     format
                 }
                 TrackFileType::MonkeysAudio => {
-                    let param_name_lesser = get_rand_param();
-                    let param_name_greater = get_rand_param();
+                    let param_name_lesser = params.next_name();
+                    let param_name_greater = params.next_name();
                     let format = format!("(FileType BETWEEN {} AND {})", param_name_lesser, param_name_greater);
                     params.push((param_name_lesser, format!("{}", TrackFileType::MonkeysAudio8.to_i32().unwrap())));
                     params.push((param_name_greater, format!("{}", TrackFileType::MonkeysAudio.to_i32().unwrap())));
                     format
                 }
                 TrackFileType::MP3 => {
-                    let param_name_lesser = get_rand_param();
-                    let param_name_greater = get_rand_param();
+                    let param_name_lesser = params.next_name();
+                    let param_name_greater = params.next_name();
                     let format = format!("(FileType = {} OR FileType = {})", param_name_lesser, param_name_greater);
                     params.push((param_name_lesser, format!("{}", TrackFileType::MP3CBR.to_i32().unwrap())));
                     params.push((param_name_greater, format!("{}", TrackFileType::MP3VBR.to_i32().unwrap())));
                     format
                 }
                 _ => {
-                    let param_name = get_rand_param();
+                    let param_name = params.next_name();
                     let format = format!("(FileType = {})", param_name);
                     params.push((param_name, format!("{}", filetype.to_i32().unwrap())));
                     format
                 }
             }
         }
-        Bang::BitrateLessThan(bitrate) => {
-            let param_name = get_rand_param();
-            let format = format!("(Bitrate < {})", param_name);
-            params.push((param_name, format!("{}", bitrate)));
-            format
-        }
-        Bang::BitrateGreaterThan(bitrate) => {
-            let param_name = get_rand_param();
-            let format = format!("(Bitrate > {})", param_name);
-            params.push((param_name, format!("{}", bitrate)));
-            format
-        }
-        Bang::CoverArtWidthGreaterThan(width) => {
-            let param_name = get_rand_param();
-            let format = format!("(FrontCoverWidth > {})", param_name);
-            params.push((param_name, format!("{}", width)));
-            format
-        }
-        Bang::CoverArtWidthLessThan(width) => {
-            let param_name = get_rand_param();
-            let format = format!("(FrontCoverWidth < {})", param_name);
-            params.push((param_name, format!("{}", width)));
-            format
-        }
-        Bang::CoverArtHeightGreaterThan(height) => {
-            let param_name = get_rand_param();
-            let format = format!("(FrontCoverHeight > {})", param_name);
-            params.push((param_name, format!("{}", height)));
-            format
-        }
-        Bang::CoverArtHeightLessThan(height) => {
-            let param_name = get_rand_param();
-            let format = format!("(FrontCoverHeight < {})", param_name);
-            params.push((param_name, format!("{}", height)));
-            format
-        }
-        Bang::DurationGreaterThan(duration) => {
-            let param_name = get_rand_param();
-            let format = format!("(Duration > {})", param_name);
-            params.push((param_name, format!("{}", duration)));
-            format
-        }
-        Bang::DurationLessThan(duration) => {
-            let param_name = get_rand_param();
-            let format = format!("(Duration < {})", param_name);
-            params.push((param_name, format!("{}", duration)));
+        Bang::Compare(field, comparison) => {
+            let column = numeric_column_name(field);
+            let param_name = params.next_name();
+            let (format, value) = match comparison {
+                Comparison::Equal(value) => (format!("({} = {})", column, param_name), value),
+                Comparison::LessThan(value) => (format!("({} < {})", column, param_name), value),
+                Comparison::LessThanOrEqual(value) => {
+                    (format!("({} <= {})", column, param_name), value)
+                }
+                Comparison::GreaterThan(value) => {
+                    (format!("({} > {})", column, param_name), value)
+                }
+                Comparison::GreaterThanOrEqual(value) => {
+                    (format!("({} >= {})", column, param_name), value)
+                }
+                Comparison::Between(low, high) => {
+                    let high_param_name = params.next_name();
+                    let format = format!(
+                        "({} BETWEEN {} AND {})",
+                        column, param_name, high_param_name
+                    );
+                    params.push((high_param_name, format!("{}", high)));
+                    (format, low)
+                }
+            };
+            params.push((param_name, format!("{}", value)));
             format
         }
         Bang::UpdatedBefore(date) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Updated < {})", param_name);
-            params.push((param_name, format!("{}", date)));
+            params.push((param_name, resolve_date(&date)));
             format
         }
         Bang::UpdatedAfter(date) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(Updated > {})", param_name);
-            params.push((param_name, format!("{}", date)));
+            params.push((param_name, resolve_date(&date)));
             format
         }
         Bang::HasCoverArt(has) => {
-            let param_name = get_rand_param();
+            let param_name = params.next_name();
             let format = format!("(HasFrontCover = {})", param_name);
-            params.push((param_name, format!("{}", has)));
+            // Booleans are stored as integers.
+            params.push((param_name, format!("{}", has as i32)));
             format
         }
         Bang::HasMusicbrainzId(has) => (if has {
@@ -399,29 +667,76 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
         } else {
             "(Title, AlbumArtists) not in (select Title, AlbumArtists from tracks group by Title, AlbumArtists having count(*) > 1)"
         }).to_owned(),
+        // A track without a content hash is not a duplicate of anything.
+        Bang::HasDuplicateAudio(has) => (if has {
+            "(ContentHash IS NOT NULL AND ContentHash IN (select ContentHash from tracks where ContentHash IS NOT NULL group by ContentHash having count(*) > 1))"
+        } else {
+            "(ContentHash IS NULL OR ContentHash NOT IN (select ContentHash from tracks where ContentHash IS NOT NULL group by ContentHash having count(*) > 1))"
+        }).to_owned(),
+        // An album is every track with the same album artists and album title.
+        Bang::AlbumHasMixedFormats(mixed) => (if mixed {
+            "(AlbumArtists, Album) in (select AlbumArtists, Album from tracks group by AlbumArtists, Album having count(distinct FileType) > 1)"
+        } else {
+            "(AlbumArtists, Album) not in (select AlbumArtists, Album from tracks group by AlbumArtists, Album having count(distinct FileType) > 1)"
+        }).to_owned(),
+        Bang::AlbumHasCoverArt(has) => (if has {
+            "(AlbumArtists, Album) not in (select AlbumArtists, Album from tracks where HasFrontCover = 0)"
+        } else {
+            "(AlbumArtists, Album) in (select AlbumArtists, Album from tracks where HasFrontCover = 0)"
+        }).to_owned(),
+        Bang::AlbumIsIncomplete(incomplete) => format!(
+            "((AlbumArtists, Album) {} ({}))",
+            if incomplete { "in" } else { "not in" },
+            INCOMPLETE_ALBUMS_QUERY
+        ),
+        Bang::Lint(Some(kind)) => lint_condition(kind),
+        Bang::Lint(None) => format!(
+            "({} OR {} OR {} OR {})",
+            lint_condition(LintKind::InconsistentYear),
+            lint_condition(LintKind::SplitAlbum),
+            lint_condition(LintKind::CaseCollision),
+            lint_condition(LintKind::SuspiciousWhitespace)
+        ),
         Bang::FullTextSearch(search) => {
-            let param_name = get_rand_param();
-            let album_artists_param = get_rand_param();
+            if let Some(fts_query) = to_fts_query(&search) {
+                let param_name = params.next_name();
+                let format = format!(
+                    "(rowid IN (SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH {}))",
+                    param_name
+                );
+                params.push((param_name, fts_query));
+                return format;
+            }
+            let param_name = params.next_name();
+            let album_artists_param = params.next_name();
             let format = format!("(Title LIKE {} OR Album LIKE {} OR Artist LIKE {} OR AlbumArtists REGEXP {} COLLATE NOCASE)", 
                 param_name, param_name, param_name, album_artists_param);
             params.push((param_name, format!("%{}%", search)));
-            params.push((
-                album_artists_param,
-                format!("(?:^|;)(?:.*?)((?i){})(?:.*?)(?:;|$)", escape_regex_search(&search)),
-            ));
+            params.push((album_artists_param, album_artists_search_pattern(&search)));
 
             format
         }
         Bang::FullTextSearchExact(search) => {
-            let param_name = get_rand_param();
-            let album_artists_param = get_rand_param();
+            let param_name = params.next_name();
+            let album_artists_param = params.next_name();
 
             let format = format!(
                 "(Title = {} OR Album = {} OR Artist = {} OR AlbumArtists REGEXP {} COLLATE NOCASE)",
                 param_name, param_name, param_name, album_artists_param
             );
             params.push((param_name, format!("{}", search)));
-            params.push((album_artists_param, format!("(?:^|;)({})(?:;|$)", escape_regex_search(&search))));
+            params.push((album_artists_param, album_artists_exact_pattern(&search)));
+            // The index finds the tracks with the search as a phrase anywhere,
+            // which are then checked for an exact match.
+            if let Some(fts_phrase) = to_fts_phrase(&search) {
+                let fts_param_name = params.next_name();
+                let format = format!(
+                    "(rowid IN (SELECT rowid FROM tracks_fts WHERE tracks_fts MATCH {}) AND {})",
+                    fts_param_name, format
+                );
+                params.push((fts_param_name, fts_phrase));
+                return format;
+            }
             format
         }
         Bang::LogicalAnd(lhs, rhs) => {
@@ -434,6 +749,12 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
             let rhs = to_query_string(*rhs, params);
             format!("({}) OR ({})", lhs, rhs)
         }
+        Bang::LogicalNot(bang) => {
+            let bang = to_query_string(*bang, params);
+            // A comparison with a NULL column is neither true nor false,
+            // so treat it as false for the track to be matched when negated.
+            format!("NOT COALESCE(({}), 0)", bang)
+        }
         Bang::Grouping(bang) => {
             let bang = to_query_string(*bang, params);
             format!("({})", bang)
@@ -444,6 +765,138 @@ fn to_query_string(bang: Bang, params: &mut Vec<(String, String)>) -> String {
     }
 }
 
+/// Looks up duplicates and albums in the database, the same way their
+/// bangs do when they are queried.
+pub struct LibraryLookup<'a> {
+    conn: &'a Connection,
+    /// The naming template that puts the tracks in folders, which case
+    /// collisions are found in.
+    template: &'a NamingTemplate,
+    /// The folders of the tracks in the library, which are found the
+    /// first time a track is checked for case collisions.
+    folders: RefCell<Option<FolderIndex>>,
+}
+
+impl<'a> LibraryLookup<'a> {
+    pub fn new(conn: &'a Connection, template: &'a NamingTemplate) -> LibraryLookup<'a> {
+        LibraryLookup {
+            conn,
+            template,
+            folders: RefCell::new(None),
+        }
+    }
+}
+
+impl<'a> TrackLookup for LibraryLookup<'a> {
+    fn has_duplicates(&self, track: &Track) -> bool {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM tracks WHERE Title = ?1 AND AlbumArtists = ?2 AND FilePath != ?3)",
+            &[
+                &track.title,
+                &track.album_artists.join(";"),
+                &track.file_path.to_string_lossy().into_owned(),
+            ],
+            |row| row.get::<_, bool>(0),
+        ).unwrap_or(false)
+    }
+
+    fn has_duplicate_audio(&self, track: &Track) -> bool {
+        // A track that is not in the library yet is hashed from its file.
+        let content_hash = fingerprint::ensure_content_hash(track, get_content_hash(&track.file_path, self.conn));
+        match content_hash {
+            Some(ref content_hash) => self.conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM tracks WHERE ContentHash = ?1 AND FilePath != ?2)",
+                &[content_hash, &track.file_path.to_string_lossy().into_owned()],
+                |row| row.get::<_, bool>(0),
+            ).unwrap_or(false),
+            None => false,
+        }
+    }
+
+    fn album_has_other_format(&self, track: &Track) -> bool {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM tracks WHERE AlbumArtists = ?1 AND Album = ?2 AND FileType != ?3 AND FilePath != ?4)",
+            &[
+                &track.album_artists.join(";"),
+                &track.album,
+                &track.file_type.to_i32().unwrap(),
+                &track.file_path.to_string_lossy().into_owned(),
+            ],
+            |row| row.get::<_, bool>(0),
+        ).unwrap_or(false)
+    }
+
+    fn album_positions(&self, track: &Track) -> Vec<TrackPosition> {
+        let positions = || -> Result<Vec<TrackPosition>> {
+            let mut statement = self.conn.prepare(
+                "SELECT COALESCE(DiscNumber, 0), COALESCE(TrackNumber, 0), COALESCE(DiscCount, 0) FROM tracks \
+                 WHERE AlbumArtists = ?1 AND Album = ?2 AND FilePath != ?3",
+            )?;
+            let mut rows = statement.query(&[
+                &track.album_artists.join(";"),
+                &track.album,
+                &track.file_path.to_string_lossy().into_owned(),
+            ])?;
+            let mut positions = Vec::new();
+            while let Some(Ok(row)) = rows.next() {
+                positions.push(TrackPosition {
+                    disc_number: row.get_checked(0)?,
+                    track_number: row.get_checked(1)?,
+                    disc_count: row.get_checked(2)?,
+                });
+            }
+            Ok(positions)
+        };
+        positions().unwrap_or_default()
+    }
+
+    fn has_conflict(&self, track: &Track, kind: LintKind) -> bool {
+        let album_artists = track.album_artists.join(";");
+        let file_path = track.file_path.to_string_lossy().into_owned();
+        let exists = |condition: &str, params: &[&dyn ToSql]| {
+            self.conn.query_row(
+                &format!("SELECT EXISTS(SELECT 1 FROM tracks WHERE {} AND FilePath != ?1)", condition),
+                params,
+                |row| row.get::<_, bool>(0),
+            ).unwrap_or(false)
+        };
+        match kind {
+            LintKind::InconsistentYear => exists(
+                "AlbumArtists = ?2 AND Album = ?3 AND Year != ?4",
+                &[&file_path, &album_artists, &track.album, &track.year],
+            ),
+            LintKind::SplitAlbum => exists(
+                "lower(trim(AlbumArtists)) = lower(trim(?2)) AND lower(trim(Album)) = lower(trim(?3)) \
+                 AND (AlbumArtists != ?2 OR Album != ?3)",
+                &[&file_path, &album_artists, &track.album],
+            ),
+            // The naming template is rendered in Rust, so the folders of the
+            // library are found once for every track that is checked.
+            LintKind::CaseCollision => {
+                let mut folders = self.folders.borrow_mut();
+                let folders = folders.get_or_insert_with(|| {
+                    let tracks = query_tracks(Bang::All, self.conn, self.template, &SortSpec::default(), None, None);
+                    FolderIndex::new(&tracks.unwrap_or_default(), self.template)
+                });
+                folders.has_case_collision(track, self.template)
+            }
+            LintKind::SuspiciousWhitespace => false,
+        }
+    }
+
+    fn album_has_missing_cover_art(&self, track: &Track) -> bool {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM tracks WHERE AlbumArtists = ?1 AND Album = ?2 AND HasFrontCover = 0 AND FilePath != ?3)",
+            &[
+                &track.album_artists.join(";"),
+                &track.album,
+                &track.file_path.to_string_lossy().into_owned(),
+            ],
+            |row| row.get::<_, bool>(0),
+        ).unwrap_or(false)
+    }
+}
+
 #[allow(dead_code)]
 pub fn remove_track(track: &Track, conn: &Connection) {
     conn.execute(
@@ -452,8 +905,9 @@ pub fn remove_track(track: &Track, conn: &Connection) {
     ).unwrap();
 }
 
+/// Adds a track with the content hash of its audio, if it is known.
 #[allow(dead_code)]
-pub fn add_track(track: &Track, conn: &Connection) {
+pub fn add_track(track: &Track, content_hash: Option<&String>, conn: &Connection) {
     conn.execute(
         "INSERT OR REPLACE INTO tracks(
                 FilePath, 
@@ -473,9 +927,11 @@ pub fn add_track(track: &Track, conn: &Connection) {
                 DiscNumber,
                 Duration,
                 FileType,
-                Updated) 
+                Updated,
+                ContentHash,
+                DiscCount) 
                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7,
-                        ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+                        ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
         &[
             &track.file_path.as_os_str().to_string_lossy().into_owned(),
             &track.title,
@@ -495,6 +951,145 @@ pub fn add_track(track: &Track, conn: &Connection) {
             &ms_to_ticks(track.duration),
             &track.file_type.to_i32().unwrap(),
             &track.updated,
+            &content_hash,
+            &track.disc_count,
         ],
     ).unwrap();
 }
+
+#[allow(dead_code)]
+pub fn remove_track_by_path(file_path: &Path, conn: &Connection) {
+    conn.execute(
+        "DELETE FROM tracks WHERE FilePath = ?1",
+        &[&file_path.to_string_lossy().into_owned()],
+    ).unwrap();
+}
+
+/// Changes the path of a track, without changing its updated date.
+#[allow(dead_code)]
+pub fn update_track_path(old_path: &Path, new_path: &Path, conn: &Connection) {
+    conn.execute(
+        "UPDATE tracks SET FilePath = ?1 WHERE FilePath = ?2",
+        &[
+            &new_path.to_string_lossy().into_owned(),
+            &old_path.to_string_lossy().into_owned(),
+        ],
+    ).unwrap();
+}
+
+/// Whether the track at the given path is in the library.
+pub fn has_track(file_path: &Path, conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM tracks WHERE FilePath = ?1)",
+        &[&file_path.to_string_lossy().into_owned()],
+        |row| row.get::<_, bool>(0),
+    )
+}
+
+/// Gets the content hash of the track at the given path, if it is in
+/// the library and its audio has been hashed.
+pub fn get_content_hash(file_path: &Path, conn: &Connection) -> Option<String> {
+    conn.query_row(
+        "SELECT ContentHash FROM tracks WHERE FilePath = ?1",
+        &[&file_path.to_string_lossy().into_owned()],
+        |row| row.get_checked::<_, String>(0).ok(),
+    ).ok()
+        .and_then(|content_hash| content_hash)
+}
+
+/// Sets the content hash of a track, without changing its updated date.
+#[allow(dead_code)]
+pub fn set_content_hash(track: &Track, content_hash: &str, conn: &Connection) {
+    conn.execute(
+        "UPDATE tracks SET ContentHash = ?1 WHERE FilePath = ?2",
+        &[&content_hash, &track.file_path.to_string_lossy().into_owned()],
+    ).unwrap();
+}
+
+/// Counts of what is in the library.
+#[derive(Debug)]
+pub struct LibraryStats {
+    pub tracks: i64,
+    pub albums: i64,
+    pub album_artists: i64,
+    /// The total duration of every track, in milliseconds.
+    pub duration: i64,
+    /// The number of tracks of each file type.
+    pub file_types: Vec<(TrackFileType, i64)>,
+}
+
+pub fn get_library_stats(conn: &Connection) -> Result<LibraryStats> {
+    let (tracks, albums, album_artists, duration) = conn.query_row(
+        "SELECT COUNT(*),
+                COUNT(DISTINCT AlbumArtists || ';' || Album),
+                COUNT(DISTINCT AlbumArtists),
+                COALESCE(SUM(Duration), 0)
+         FROM tracks",
+        &[],
+        |row| -> Result<(i64, i64, i64, i64)> {
+            Ok((
+                row.get_checked(0)?,
+                row.get_checked(1)?,
+                row.get_checked(2)?,
+                row.get_checked(3)?,
+            ))
+        },
+    )??;
+
+    let mut statement = conn.prepare("SELECT FileType, COUNT(*) FROM tracks GROUP BY FileType")?;
+    let mut rows = statement.query(&[])?;
+    let mut file_types = Vec::new();
+    while let Some(Ok(row)) = rows.next() {
+        file_types.push((
+            TrackFileType::from_i32(row.get_checked::<_, i32>(0)?).unwrap_or(TrackFileType::Unknown),
+            row.get_checked(1)?,
+        ));
+    }
+
+    Ok(LibraryStats {
+        tracks,
+        albums,
+        album_artists,
+        // Durations are stored in ticks, which would overflow milliseconds as an i32.
+        duration: duration / 10000,
+        file_types,
+    })
+}
+
+/// The distinct values of a column that start with the given prefix,
+/// ignoring case, in alphabetical order. Case is folded in Rust rather
+/// than with LIKE, which only folds ASCII letters. Each album artist is
+/// a value of its own.
+pub fn get_distinct_values(
+    column: SortColumn,
+    prefix: &str,
+    limit: u32,
+    conn: &Connection,
+) -> Result<Vec<String>> {
+    let query = format!(
+        "SELECT DISTINCT {0} FROM tracks WHERE {0} IS NOT NULL",
+        column.column_name()
+    );
+    let mut statement = conn.prepare(&query)?;
+    let mut rows = statement.query(&[])?;
+    let prefix = prefix.to_lowercase();
+    let mut values = BTreeSet::new();
+    while let Some(Ok(row)) = rows.next() {
+        let value = row.get_checked::<_, String>(0)?;
+        let candidates = match column {
+            SortColumn::AlbumArtists => value.split(';').map(|c| c.to_owned()).collect(),
+            _ => vec![value],
+        };
+        for candidate in candidates {
+            let folded = candidate.to_lowercase();
+            if !folded.is_empty() && folded.starts_with(&prefix) {
+                values.insert((folded, candidate));
+            }
+        }
+    }
+    Ok(values
+        .into_iter()
+        .map(|(_, value)| value)
+        .take(limit as usize)
+        .collect())
+}
@@ -1,9 +1,11 @@
 extern crate quick_error;
 
 use bangs::LexerMode;
+use bangs::Span;
 use bangs::Token;
 use std::result;
 use std::path::PathBuf;
+use rusqlite;
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -22,6 +24,10 @@ quick_error! {
             description("The file could not be moved")
             display(r#"The file {} could not be moved."#, file_name)
         }
+        UnableToCopy(file_name: String, reason: String) {
+            description("The file could not be copied to another drive")
+            display(r#"The file {} could not be copied to another drive: {}"#, file_name, reason)
+        }
         FileIOError(file_name:  PathBuf) {
             description("The file could not be processed.")
             display(r#"The file {:?} could not be processed."#, file_name)
@@ -38,29 +44,134 @@ quick_error! {
             description("Track does not contain the required tag.")
             display(r#"The track "{}" does not have the required tag {}"#, file_name, tag_name)
         }
-        LexerUnexpectedCharacter(character: char, mode: LexerMode) {
+        LexerUnexpectedCharacter(character: char, mode: LexerMode, span: Span) {
             description("Unexpected character when lexing query string.")
-            display(r#"Unexpected "{}" when lexing {:?}"#, character, mode)
+            display(r#"Unexpected "{}" at {}, expected {}"#, character, span.start, mode.expected())
         }
-        LexerUnexpectedEscapeCharacter(mode: LexerMode) {
+        LexerUnexpectedEscapeCharacter(mode: LexerMode, span: Span) {
             description("Escape character occurred at the end of the input.")
-            display(r#"Unexpected escape '\\' when lexing {:?}"#, mode)
+            display(r#"Unexpected escape '\\' at {} when lexing {:?}"#, span.start, mode)
         }
-        LexerUnexpectedEndOfInput {
+        LexerUnexpectedEndOfInput(span: Span, expected: &'static str) {
             description("Unexpected end of input")
-            display(r#"Input ended before argument was fully parsed."#)
+            display(r#"Input ended at {}, expected {}"#, span.start, expected)
         }
-        ParserUnexpectedToken(t: Token) {
+        ParserUnexpectedToken(t: Token, span: Span, expected: &'static str) {
             description("Unexpected token during parsing of query token stream.")
-            display(r#"Unexpected "{:?}" when parsing query"#, t)
+            display(r#"Unexpected "{:?}" at {}, expected {}"#, t, span.start, expected)
         }
-        ParserUnknownBang(b: String) {
+        ParserUnknownBang(b: String, span: Span) {
             description("Unknown bang during parsing of query token stream.")
-            display(r#"Unknown bang !"{:?}" when parsing query"#, b)
+            display(r#"Unknown bang "!{}" at {}"#, b, span.start)
+        }
+        DatabaseTooNew(version: i32, supported: i32) {
+            description("The database was created by a newer version of seiri.")
+            display(r#"The database is at schema version {}, but only version {} is supported."#, version, supported)
+        }
+        DatabaseMigrationError(version: i32, reason: String) {
+            description("The database could not be migrated.")
+            display(r#"Unable to migrate the database to schema version {}: {}"#, version, reason)
+        }
+        UnableToBackupDatabase(backup_name: String) {
+            description("The database could not be backed up.")
+            display(r#"The database could not be backed up to {}."#, backup_name)
+        }
+        OperationNotFound(id: i64) {
+            description("The operation could not be found in the journal.")
+            display(r#"The operation {} could not be found in the journal."#, id)
+        }
+        BatchNotFound(batch: i64) {
+            description("The batch could not be found in the journal.")
+            display(r#"The batch {} could not be found in the journal."#, batch)
+        }
+        OperationAlreadyRolledBack(id: i64) {
+            description("The operation was already rolled back.")
+            display(r#"The operation {} was already rolled back."#, id)
+        }
+        RollbackPathExists(file_name: String) {
+            description("A file already exists where the operation would be rolled back to.")
+            display(r#"Unable to roll back, the file {} already exists."#, file_name)
+        }
+        DatabaseError(err: rusqlite::Error) {
+            from()
+            description("An error occurred when accessing the database.")
+            display(r#"Database error: {}"#, err)
+        }
+        InvalidNamingTemplate(template: String, reason: String) {
+            description("The naming template is invalid.")
+            display(r#"The naming template "{}" is invalid: {}"#, template, reason)
+        }
+        InvalidSortKey(key: String) {
+            description("The sort key is invalid.")
+            display(r#"The sort key "{}" is invalid"#, key)
+        }
+        SavedQueryNotFound(name: String) {
+            description("The saved query could not be found.")
+            display(r#"The saved query "{}" could not be found."#, name)
+        }
+        SavedQueryExists(name: String) {
+            description("A saved query with the name already exists.")
+            display(r#"A saved query named "{}" already exists."#, name)
         }
-        ParserInvalidInput(input: String) {
+        InvalidSavedQueryName(name: String) {
+            description("The name of the saved query is invalid.")
+            display(r#"The name "{}" is not a valid name for a saved query."#, name)
+        }
+        UnknownLintKind(kind: String) {
+            description("The kind of lint is unknown.")
+            display(r#"The lint "{}" is unknown"#, kind)
+        }
+        ParserInvalidInput(input: String, span: Span, expected: &'static str) {
             description("Invalid input when parsing bang argument")
-            display(r#"Invalid input "{}" when parsing bang"#, input)
+            display(r#"Invalid input "{}" at {}, expected {}"#, input, span.start, expected)
+        }
+        MacroCycle(name: String, cycle: String, span: Span) {
+            description("A macro expands to itself.")
+            display(r#"The macro "!{}" at {} expands to itself: {}"#, name, span.start, cycle)
+        }
+        MacroInvalid(name: String, reason: String, span: Span) {
+            description("The query of a macro is invalid.")
+            display(r#"The macro "!{}" at {} is invalid: {}"#, name, span.start, reason)
+        }
+        MacroTakesNoArgument(name: String, span: Span) {
+            description("A macro was given an argument.")
+            display(r#"The macro "!{}" at {} does not take an argument"#, name, span.start)
+        }
+        MacroNameInvalid(name: String, reason: &'static str) {
+            description("The name of a macro can not be used in a query.")
+            display(r#"The macro "{}" can not be used in a query: {}"#, name, reason)
+        }
+    }
+}
+
+impl Error {
+    /// The span of the query that a lexer, parser or macro error is about.
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Error::LexerUnexpectedCharacter(_, _, span)
+            | Error::LexerUnexpectedEscapeCharacter(_, span)
+            | Error::LexerUnexpectedEndOfInput(span, _)
+            | Error::ParserUnexpectedToken(_, span, _)
+            | Error::ParserUnknownBang(_, span)
+            | Error::ParserInvalidInput(_, span, _)
+            | Error::MacroCycle(_, _, span)
+            | Error::MacroInvalid(_, _, span)
+            | Error::MacroTakesNoArgument(_, span) => Some(span),
+            _ => None,
+        }
+    }
+
+    /// What a lexer or parser error expected to find instead.
+    pub fn expected(&self) -> Option<&'static str> {
+        match *self {
+            Error::LexerUnexpectedCharacter(_, ref mode, _) => Some(mode.expected()),
+            Error::LexerUnexpectedEscapeCharacter(_, _) => Some("a character after '\\'"),
+            Error::LexerUnexpectedEndOfInput(_, expected)
+            | Error::ParserUnexpectedToken(_, _, expected)
+            | Error::ParserInvalidInput(_, _, expected) => Some(expected),
+            Error::ParserUnknownBang(_, _) => Some("the name of a bang"),
+            Error::MacroTakesNoArgument(_, _) => Some("'}'"),
+            _ => None,
         }
     }
 }
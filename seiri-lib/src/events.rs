@@ -0,0 +1,128 @@
+use serde_json;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static JSON_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// How events are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventFormat {
+    /// An error code and its message, separated by a tilde, as documented in stderr.md.
+    Tilde,
+    /// One JSON object per line, with the kind of event in the `event` field.
+    Json,
+}
+
+/// Sets the format of every event emitted after this call.
+pub fn set_event_format(format: EventFormat) {
+    JSON_EVENTS.store(format == EventFormat::Json, Ordering::SeqCst);
+}
+
+pub fn get_event_format() -> EventFormat {
+    if JSON_EVENTS.load(Ordering::SeqCst) {
+        EventFormat::Json
+    } else {
+        EventFormat::Tilde
+    }
+}
+
+/// Something that happened in seiri that the client should know about.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum Event {
+    /// A track was moved into the library and added to the database.
+    TrackAdded { artist: String, title: String },
+    /// A file that is not a track was moved out of the way.
+    NonTrack { name: String },
+    /// A track is missing a tag that is required to add it.
+    MissingTag { name: String, tag: String },
+    /// A track could not be moved to the library.
+    TrackMoveError { message: String },
+    /// A track could not be copied to the library on another drive.
+    TrackCopyError { message: String },
+    /// A track could not be added for an unknown reason.
+    TrackError,
+    /// A large file is being copied to another drive.
+    CopyProgress { path: String, percent: u64 },
+    /// The music folder of the given path could not be found.
+    LibraryNotFound { path: String },
+    /// The file watcher reported an error.
+    WatchError { message: String },
+    /// The file watcher died, and is being restarted.
+    WatcherKeepAliveFail,
+    /// Access to the watched folder was lost.
+    WatcherFolderAccessLost { folder: String },
+    /// The file watcher is being restarted.
+    WatcherRestart,
+    /// The default configuration could not be written.
+    ConfigWriteError,
+    /// The configuration file could not be read.
+    ConfigInvalid { message: String },
+    /// The database was created by a newer version of seiri.
+    DatabaseTooNew { message: String },
+    /// The database could not be upgraded to the current schema.
+    DatabaseMigrateError { message: String },
+    /// A track could not be reconciled during a library rescan.
+    RescanError { message: String },
+    /// A library rescan finished.
+    RescanComplete { summary: String },
+    /// A track could not be moved to where the naming template places it.
+    RehomeError { message: String },
+    /// The library was moved to the naming template.
+    RehomeComplete { summary: String },
+}
+
+impl Event {
+    /// Formats the event in the tilde format that clients have always parsed.
+    pub fn to_tilde_string(&self) -> String {
+        match *self {
+            Event::TrackAdded {
+                ref artist,
+                ref title,
+            } => format!("TRACKADDED~{} – {}", artist, title),
+            Event::NonTrack { ref name } => format!("NONTRACK~{}", name),
+            Event::MissingTag { ref name, ref tag } => {
+                format!("MISSINGTAG~Track {} is missing tag {}.", name, tag)
+            }
+            Event::TrackMoveError { ref message } => format!("TRACKMOVEERROR~{}", message),
+            Event::TrackCopyError { ref message } => format!("TRACKCOPYERROR~{}", message),
+            Event::TrackError => "TRACKERROR~".to_owned(),
+            Event::CopyProgress { ref path, percent } => format!("COPYPROGRESS~{}#{}", path, percent),
+            Event::LibraryNotFound { ref path } => format!("LIBRARYNOTFOUND~{}.", path),
+            Event::WatchError { ref message } => format!("WATCHERROR~{}", message),
+            Event::WatcherKeepAliveFail => "WATCHERKEEPALIVEFAIL~Keep-alive failed. Watcher thread probably panicked. Restarting Watcher Thread...".to_owned(),
+            Event::WatcherFolderAccessLost { ref folder } => {
+                format!("WATCHERFOLDERACCESSLOST~{}", folder)
+            }
+            Event::WatcherRestart => {
+                "WATCHERRESTART~Requested watcher thread exit. Restarting Watcher Thread...".to_owned()
+            }
+            Event::ConfigWriteError => {
+                "CONFIGWRITEERR~Unable to write default configuration.".to_owned()
+            }
+            Event::ConfigInvalid { .. } => {
+                "CONFIGINVALID~Configuration file is in invalid format!".to_owned()
+            }
+            Event::DatabaseTooNew { ref message } => format!("DATABASETOONEW~{}", message),
+            Event::DatabaseMigrateError { ref message } => {
+                format!("DATABASEMIGRATEERR~{}", message)
+            }
+            Event::RescanError { ref message } => format!("RESCANERROR~{}", message),
+            Event::RescanComplete { ref summary } => format!("RESCANCOMPLETE~{}", summary),
+            Event::RehomeError { ref message } => format!("REHOMEERROR~{}", message),
+            Event::RehomeComplete { ref summary } => format!("REHOMECOMPLETE~{}", summary),
+        }
+    }
+
+    /// Formats the event as a single line of JSON.
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Writes the event to stderr in the current event format.
+    pub fn emit(&self) {
+        match get_event_format() {
+            EventFormat::Tilde => eprintln!("{}", self.to_tilde_string()),
+            EventFormat::Json => eprintln!("{}", self.to_json_string()),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use error::{Error, Result};
+use katatsuki;
+use katatsuki::Track;
+use sha1::Sha1;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Hashes the audio data of the track at the given path with SHA-1.
+/// Tags are left out of the hash, so copies of a track with
+/// different tags have the same content hash.
+pub fn content_hash(track_path: &Path) -> Result<String> {
+    let io_error = || Error::FileIOError(PathBuf::from(track_path));
+    let ranges = katatsuki::audio_ranges(track_path).map_err(|err| match err.kind() {
+        ErrorKind::InvalidData => Error::UnsupportedFile(PathBuf::from(track_path)),
+        _ => io_error(),
+    })?;
+    // Files without audio data would all share the same hash.
+    if ranges.iter().all(|&(_, len)| len == 0) {
+        return Err(Error::UnsupportedFile(PathBuf::from(track_path)));
+    }
+
+    let mut file = File::open(track_path).map_err(|_| io_error())?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    for (offset, len) in ranges {
+        file.seek(SeekFrom::Start(offset)).map_err(|_| io_error())?;
+        let mut range = (&mut file).take(len);
+        loop {
+            match range.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => hasher.update(&buffer[..read]),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return Err(io_error()),
+            }
+        }
+    }
+    Ok(hasher.digest().to_string())
+}
+
+/// Computes the content hash of the track, if it is not known yet.
+/// There is no content hash if the audio of the track could not be read.
+pub fn ensure_content_hash(track: &Track, content_hash: Option<String>) -> Option<String> {
+    content_hash.or_else(|| self::content_hash(&track.file_path).ok())
+}
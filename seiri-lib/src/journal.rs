@@ -0,0 +1,326 @@
+use chrono::prelude::*;
+use database;
+use error::{Error, Result};
+use fingerprint::ensure_content_hash;
+use katatsuki::Track;
+use paths::{get_auto_add_folder, get_rolled_back_path, get_rolled_back_source, move_file, new_track_checked,
+            remove_empty_parents};
+use rusqlite;
+use rusqlite::types::ToSql;
+use rusqlite::Connection;
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Why a file operation was made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperationReason {
+    /// A new track was moved into the library.
+    NewTrack,
+    /// A file that is not a track was moved out of the way.
+    NonTrack,
+    /// A track was moved after its tags changed.
+    Reconsider,
+    /// A track was moved after the naming template changed.
+    Rehome,
+    /// An earlier operation was rolled back.
+    Rollback,
+}
+
+impl OperationReason {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OperationReason::NewTrack => "newtrack",
+            OperationReason::NonTrack => "nontrack",
+            OperationReason::Reconsider => "reconsider",
+            OperationReason::Rehome => "rehome",
+            OperationReason::Rollback => "rollback",
+        }
+    }
+}
+
+impl FromStr for OperationReason {
+    type Err = ();
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, ()> {
+        match s {
+            "newtrack" => Ok(OperationReason::NewTrack),
+            "nontrack" => Ok(OperationReason::NonTrack),
+            "reconsider" => Ok(OperationReason::Reconsider),
+            "rehome" => Ok(OperationReason::Rehome),
+            "rollback" => Ok(OperationReason::Rollback),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperationKind {
+    /// A file was moved from the old path to the new path.
+    Move,
+    /// The empty directory at the old path was removed.
+    RemoveDirectory,
+}
+
+impl OperationKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OperationKind::Move => "move",
+            OperationKind::RemoveDirectory => "removedir",
+        }
+    }
+}
+
+impl FromStr for OperationKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, ()> {
+        match s {
+            "move" => Ok(OperationKind::Move),
+            "removedir" => Ok(OperationKind::RemoveDirectory),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A file operation recorded in the journal.
+#[derive(Debug)]
+pub struct Operation {
+    pub id: i64,
+    /// Operations made together share a batch, and can be rolled back together.
+    pub batch: i64,
+    pub kind: OperationKind,
+    pub old_path: PathBuf,
+    /// Where the file was moved to. Only moves have a new path.
+    pub new_path: Option<PathBuf>,
+    pub reason: OperationReason,
+    pub timestamp: String,
+    pub rolled_back: bool,
+}
+
+/// Records file operations to the journal, all in the same batch.
+/// The batch is only created once the first operation is recorded.
+pub struct Journal<'a> {
+    conn: &'a Connection,
+    reason: OperationReason,
+    batch: Cell<Option<i64>>,
+}
+
+impl<'a> Journal<'a> {
+    pub fn new(conn: &'a Connection, reason: OperationReason) -> Journal<'a> {
+        Journal {
+            conn,
+            reason,
+            batch: Cell::new(None),
+        }
+    }
+
+    /// The batch of the recorded operations, if any were recorded.
+    pub fn batch(&self) -> Option<i64> {
+        self.batch.get()
+    }
+
+    pub fn record_move(&self, old_path: &Path, new_path: &Path) {
+        self.record(OperationKind::Move, old_path, Some(new_path))
+    }
+
+    pub fn record_remove_directory(&self, path: &Path) {
+        self.record(OperationKind::RemoveDirectory, path, None)
+    }
+
+    fn record(&self, kind: OperationKind, old_path: &Path, new_path: Option<&Path>) {
+        let old_path = old_path.to_string_lossy().into_owned();
+        let new_path = new_path.map(|path| path.to_string_lossy().into_owned());
+        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let params: &[&dyn ToSql] = &[
+            &kind.as_str(),
+            &old_path,
+            &new_path,
+            &self.reason.as_str(),
+            &timestamp,
+        ];
+        match self.batch.get() {
+            Some(batch) => {
+                let mut params = params.to_vec();
+                params.push(&batch);
+                self.conn.execute(
+                    "INSERT INTO operations(Kind, OldPath, NewPath, Reason, Timestamp, Batch)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                    &params,
+                ).unwrap();
+            }
+            None => {
+                // Allocate the batch in the same statement, so that concurrent
+                // journals never share a batch.
+                self.conn.execute(
+                    "INSERT INTO operations(Kind, OldPath, NewPath, Reason, Timestamp, Batch)
+                     SELECT ?1, ?2, ?3, ?4, ?5, COALESCE(MAX(Batch), 0) + 1 FROM operations",
+                    params,
+                ).unwrap();
+                let batch = self.conn
+                    .query_row(
+                        "SELECT Batch FROM operations WHERE Id = ?1",
+                        &[&self.conn.last_insert_rowid()],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .unwrap();
+                self.batch.set(Some(batch));
+            }
+        }
+    }
+}
+
+fn query_operations(query: &str, params: &[&dyn ToSql], conn: &Connection) -> rusqlite::Result<Vec<Operation>> {
+    let mut statement = conn.prepare(query)?;
+    let mut rows = statement.query(params)?;
+    let mut operations = Vec::new();
+    while let Some(Ok(row)) = rows.next() {
+        operations.push(Operation {
+            id: row.get_checked(0)?,
+            batch: row.get_checked(1)?,
+            kind: row.get_checked::<_, String>(2)?
+                .parse()
+                .unwrap_or(OperationKind::Move),
+            old_path: PathBuf::from(row.get_checked::<_, String>(3)?),
+            new_path: row.get_checked::<_, String>(4).ok().map(PathBuf::from),
+            reason: row.get_checked::<_, String>(5)?
+                .parse()
+                .unwrap_or(OperationReason::NewTrack),
+            timestamp: row.get_checked(6)?,
+            rolled_back: row.get_checked(7)?,
+        })
+    }
+    Ok(operations)
+}
+
+const SELECT_OPERATIONS: &str =
+    "SELECT Id, Batch, Kind, OldPath, NewPath, Reason, Timestamp, RolledBack FROM operations";
+
+/// Gets the most recent operations in the journal, newest first.
+pub fn get_operations(conn: &Connection, limit: Option<i32>) -> rusqlite::Result<Vec<Operation>> {
+    query_operations(
+        &format!("{} ORDER BY Id DESC LIMIT {}", SELECT_OPERATIONS, limit.unwrap_or(-1)),
+        &[],
+        conn,
+    )
+}
+
+/// Gets the operations of a batch, newest first.
+pub fn get_batch(batch: i64, conn: &Connection) -> rusqlite::Result<Vec<Operation>> {
+    query_operations(
+        &format!("{} WHERE Batch = ?1 ORDER BY Id DESC", SELECT_OPERATIONS),
+        &[&batch],
+        conn,
+    )
+}
+
+fn rollback(
+    operation: &Operation,
+    library_path: &Path,
+    journal: &Journal,
+    conn: &Connection,
+) -> Result<()> {
+    if operation.rolled_back {
+        return Err(Error::OperationAlreadyRolledBack(operation.id));
+    }
+    match (operation.kind, &operation.new_path) {
+        (OperationKind::Move, &Some(ref new_path)) => {
+            let auto_add_path = get_auto_add_folder(library_path);
+            let old_path = &match operation.reason {
+                // Files put back into the Automatically Add to Library folder
+                // would be added again as soon as they were put back.
+                OperationReason::NewTrack | OperationReason::NonTrack => {
+                    get_rolled_back_path(&operation.old_path, &auto_add_path)
+                }
+                _ => operation.old_path.to_owned(),
+            };
+            // Rolling back the rollback of a new track puts it back in the
+            // library, but the rollback removed the track from the database.
+            let removed_track = if operation.reason == OperationReason::Rollback
+                && old_path.starts_with(library_path)
+                && !database::has_track(new_path, conn)?
+            {
+                let source = get_rolled_back_source(new_path, &auto_add_path);
+                Some(new_track_checked(new_path, Some(&source))?)
+            } else {
+                None
+            };
+            if old_path.exists() {
+                return Err(Error::RollbackPathExists(
+                    old_path.to_string_lossy().into_owned(),
+                ));
+            }
+            // The directory the file came from may have been cleaned up.
+            if let Some(old_dir) = old_path.parent() {
+                if let Err(_) = fs::create_dir_all(old_dir) {
+                    return Err(Error::UnableToCreateDirectory(
+                        old_dir.to_string_lossy().into_owned(),
+                    ));
+                }
+            }
+            move_file(new_path, old_path)?;
+            journal.record_move(new_path, old_path);
+            match (operation.reason, removed_track) {
+                // The track is no longer in the library.
+                (OperationReason::NewTrack, _) => database::remove_track_by_path(new_path, conn),
+                (OperationReason::NonTrack, _) => (),
+                (_, Some(track)) => {
+                    let track = Track {
+                        file_path: old_path.to_owned(),
+                        ..track
+                    };
+                    database::add_track(&track, ensure_content_hash(&track, None).as_ref(), conn);
+                }
+                (_, None) => database::update_track_path(new_path, old_path, conn),
+            }
+            remove_empty_parents(new_path, library_path, journal);
+        }
+        (OperationKind::RemoveDirectory, _) => {
+            if let Err(_) = fs::create_dir_all(&operation.old_path) {
+                return Err(Error::UnableToCreateDirectory(
+                    operation.old_path.to_string_lossy().into_owned(),
+                ));
+            }
+        }
+        (OperationKind::Move, &None) => return Err(Error::OperationNotFound(operation.id)),
+    }
+    conn.execute(
+        "UPDATE operations SET RolledBack = 1 WHERE Id = ?1",
+        &[&operation.id],
+    )?;
+    Ok(())
+}
+
+/// Rolls back a single operation, moving the file back to where it was,
+/// or recreating the directory that was removed. New tracks and other files
+/// that came from the Automatically Add to Library folder are put in its
+/// hidden `.rolledback` folder instead, so that they are not added again.
+/// Returns the batch that the rollback was recorded in.
+pub fn rollback_operation(id: i64, library_path: &Path, conn: &Connection) -> Result<Option<i64>> {
+    let operation = query_operations(
+        &format!("{} WHERE Id = ?1", SELECT_OPERATIONS),
+        &[&id],
+        conn,
+    )?.into_iter()
+        .next()
+        .ok_or(Error::OperationNotFound(id))?;
+    let journal = Journal::new(conn, OperationReason::Rollback);
+    rollback(&operation, library_path, &journal, conn)?;
+    Ok(journal.batch())
+}
+
+/// Rolls back every operation in a batch that has not been rolled back
+/// yet, newest first, stopping at the first operation that fails.
+/// Returns the batch that the rollback was recorded in.
+pub fn rollback_batch(batch: i64, library_path: &Path, conn: &Connection) -> Result<Option<i64>> {
+    let operations = get_batch(batch, conn)?;
+    if operations.is_empty() {
+        return Err(Error::BatchNotFound(batch));
+    }
+    let journal = Journal::new(conn, OperationReason::Rollback);
+    for operation in operations.iter().filter(|operation| !operation.rolled_back) {
+        rollback(operation, library_path, &journal, conn)?;
+    }
+    Ok(journal.batch())
+}
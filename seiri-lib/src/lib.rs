@@ -1,7 +1,10 @@
 #![feature(fs_read_write)]
 #![feature(toowned_clone_into)]
 #![feature(ascii_ctype)]
+#![recursion_limit = "256"]
 
+extern crate serde;
+extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
 
@@ -13,25 +16,43 @@ extern crate humantime;
 extern crate itertools;
 extern crate r2d2;
 extern crate r2d2_sqlite;
-extern crate rand;
 extern crate regex;
 extern crate rusqlite;
 extern crate app_dirs;
 extern crate toml;
 extern crate katatsuki;
+extern crate walkdir;
+extern crate sha1;
+extern crate unicode_normalization;
+
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
 
 mod bangs;
 mod error;
+mod migrations;
 
 
 pub use katatsuki::TrackFileType;
 pub use katatsuki::Track;
 pub use error::{Error, Result};
-pub use bangs::Bang;
+pub use bangs::{Bang, Comparison, NumericField, Span};
+pub use bangs::{complete_query, Completion};
 
+pub mod albums;
 pub mod config;
 pub mod database;
+pub mod events;
+pub mod fingerprint;
+pub mod journal;
+pub mod library;
+pub mod lint;
+pub mod matching;
+pub mod naming;
 pub mod paths;
+pub mod saved_queries;
+pub mod sort;
 
 pub mod ticks {
     pub use bangs::ms_to_ticks;
@@ -0,0 +1,277 @@
+use bangs::Bang;
+use database;
+use database::Connection;
+use error::{Error, Result as SeiriResult};
+use fingerprint::{self, ensure_content_hash};
+use journal::{Journal, OperationReason};
+use katatsuki::{ToPrimitive, Track};
+use naming::NamingTemplate;
+use paths::{is_track_in_place, move_track, new_track_checked, reconsider_track,
+            remove_empty_parents};
+use rusqlite::Result;
+use sort::SortSpec;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::{DirEntry, WalkDir};
+
+/// The outcome of reconciling the library folder with the database.
+#[derive(Debug, Default)]
+pub struct RescanSummary {
+    /// Tracks whose files no longer exist, and were dropped from the database.
+    pub removed: usize,
+    /// Files in the library that were not in the database, and were indexed in place.
+    pub added: usize,
+    /// Tracks whose tags changed enough to be moved to a new location.
+    pub moved: usize,
+    /// Tracks whose tags changed, but stayed in place.
+    pub updated: usize,
+    /// Tracks that matched the database.
+    pub unchanged: usize,
+    /// Unchanged tracks that were missing a content hash, and had one computed.
+    pub hashed: usize,
+    /// Files in the library that are not tracks, such as logs or cue sheets.
+    pub skipped: usize,
+    /// Tracks that could not be reconciled.
+    pub errors: Vec<Error>,
+    /// The journal batch of the moves made during the rescan, if any.
+    pub batch: Option<i64>,
+}
+
+impl fmt::Display for RescanSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} moved, {} updated, {} unchanged, {} hashed, {} skipped, {} errors",
+            self.added,
+            self.removed,
+            self.moved,
+            self.updated,
+            self.unchanged,
+            self.hashed,
+            self.skipped,
+            self.errors.len()
+        )
+    }
+}
+
+/// The outcome of moving the library to a new naming template.
+#[derive(Debug, Default)]
+pub struct RehomeSummary {
+    /// Tracks that were moved to where the template places them.
+    pub moved: usize,
+    /// Tracks that were already where the template places them.
+    pub unchanged: usize,
+    /// Tracks whose files no longer exist. A rescan will remove them.
+    pub missing: usize,
+    /// Tracks that could not be moved.
+    pub errors: Vec<Error>,
+    /// The journal batch of the moves, if any were made.
+    pub batch: Option<i64>,
+}
+
+impl fmt::Display for RehomeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} moved, {} unchanged, {} missing, {} errors",
+            self.moved,
+            self.unchanged,
+            self.missing,
+            self.errors.len()
+        )
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry
+        .file_name()
+        .to_str()
+        .map(|s| s.starts_with("."))
+        .unwrap_or(false)
+}
+
+/// Whether the tags of the track as read differ from the track as saved.
+fn track_differs(track_as_saved: &Track, track_as_read: &Track) -> bool {
+    !(track_as_saved.file_path == track_as_read.file_path
+        && track_as_saved.file_type.to_i32() == track_as_read.file_type.to_i32()
+        && track_as_saved.title == track_as_read.title
+        && track_as_saved.artist == track_as_read.artist
+        && track_as_saved.album_artists == track_as_read.album_artists
+        && track_as_saved.album == track_as_read.album
+        && track_as_saved.year == track_as_read.year
+        && track_as_saved.track_number == track_as_read.track_number
+        && track_as_saved.musicbrainz_track_id == track_as_read.musicbrainz_track_id
+        && track_as_saved.has_front_cover == track_as_read.has_front_cover
+        && track_as_saved.front_cover_width == track_as_read.front_cover_width
+        && track_as_saved.front_cover_height == track_as_read.front_cover_height
+        && track_as_saved.bitrate == track_as_read.bitrate
+        && track_as_saved.sample_rate == track_as_read.sample_rate
+        && track_as_saved.disc_number == track_as_read.disc_number
+        && track_as_saved.disc_count == track_as_read.disc_count
+        && track_as_saved.duration == track_as_read.duration)
+}
+
+/// Whether the audio of the track as read differs from the track as saved,
+/// so that the content hash of its audio has to be computed again.
+fn audio_differs(track_as_saved: &Track, track_as_read: &Track) -> bool {
+    !(track_as_saved.file_type.to_i32() == track_as_read.file_type.to_i32()
+        && track_as_saved.bitrate == track_as_read.bitrate
+        && track_as_saved.sample_rate == track_as_read.sample_rate
+        && track_as_saved.duration == track_as_read.duration)
+}
+
+/// Reconciles every track in the database with its file, then indexes
+/// any files in the library folder that are missing from the database.
+///
+/// Tracks that are unchanged are left alone, so that their updated date
+/// is kept.
+pub fn rescan_library(
+    library_path: &Path,
+    template: &NamingTemplate,
+    conn: &Connection,
+) -> Result<RescanSummary> {
+    let mut summary = RescanSummary::default();
+    let mut known_paths = HashSet::<PathBuf>::new();
+    let journal = Journal::new(conn, OperationReason::Reconsider);
+
+    for track in database::query_tracks(Bang::All, conn, template, &SortSpec::default(), None, None)? {
+        let content_hash = database::get_content_hash(&track.file_path, conn);
+        match reconsider_track(&track, library_path, template, &journal) {
+            Ok(Some(track_as_read)) => {
+                if track_as_read.file_path != track.file_path {
+                    summary.moved += 1;
+                } else if track_differs(&track, &track_as_read) {
+                    summary.updated += 1;
+                } else {
+                    summary.unchanged += 1;
+                    if content_hash.is_none() {
+                        if let Ok(content_hash) = fingerprint::content_hash(&track.file_path) {
+                            summary.hashed += 1;
+                            database::set_content_hash(&track, &content_hash, conn);
+                        }
+                    }
+                    known_paths.insert(track.file_path);
+                    continue;
+                }
+                // Retagging leaves the audio as is, so the content hash still
+                // holds unless the file was replaced with different audio.
+                let content_hash = if audio_differs(&track, &track_as_read) {
+                    ensure_content_hash(&track_as_read, None)
+                } else {
+                    ensure_content_hash(&track_as_read, content_hash)
+                };
+                database::remove_track(&track, conn);
+                database::add_track(&track_as_read, content_hash.as_ref(), conn);
+                known_paths.insert(track_as_read.file_path);
+            }
+            Ok(None) => {
+                summary.removed += 1;
+                database::remove_track(&track, conn);
+            }
+            Err(err) => {
+                // Keep the file from being indexed again as a new track.
+                known_paths.insert(track.file_path);
+                summary.errors.push(err)
+            }
+        }
+    }
+
+    let walker = WalkDir::new(library_path).into_iter();
+    for entry in walker.filter_entry(|e| !is_hidden(e)) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if !entry.file_type().is_file() || known_paths.contains(entry.path()) {
+            continue;
+        }
+        // Files are indexed where they are, since moving a file that is
+        // already in place would give it a new name.
+        match new_track_checked(entry.path(), None) {
+            Ok(track) => {
+                summary.added += 1;
+                database::add_track(&track, ensure_content_hash(&track, None).as_ref(), conn);
+            }
+            Err(Error::UnsupportedFile(_)) => summary.skipped += 1,
+            Err(err) => summary.errors.push(err),
+        }
+    }
+
+    // The search index is kept up to date by triggers, but is
+    // rebuilt in case it was ever out of step with the tracks.
+    database::rebuild_search_index(conn)?;
+
+    summary.batch = journal.batch();
+    Ok(summary)
+}
+
+/// Moves every track in the library to where the naming template places
+/// it, after the template has changed. The tags of each track are taken
+/// from the database, so tracks that were retagged since they were last
+/// seen should be reconciled with a rescan first.
+pub fn rehome_library(
+    library_path: &Path,
+    template: &NamingTemplate,
+    conn: &Connection,
+) -> Result<RehomeSummary> {
+    let mut summary = RehomeSummary::default();
+    let journal = Journal::new(conn, OperationReason::Rehome);
+
+    for track in database::query_tracks(Bang::All, conn, template, &SortSpec::default(), None, None)? {
+        if !track.file_path.exists() {
+            summary.missing += 1;
+            continue;
+        }
+        if is_track_in_place(&track, library_path, template) {
+            summary.unchanged += 1;
+            continue;
+        }
+        match move_track(&track, library_path, template, &track.source, &journal) {
+            Ok(moved_track) => {
+                summary.moved += 1;
+                // Only the path changed, so the rest of the row is kept.
+                database::update_track_path(&track.file_path, &moved_track.file_path, conn);
+                remove_empty_parents(&track.file_path, library_path, &journal);
+            }
+            Err(err) => summary.errors.push(err),
+        }
+    }
+
+    summary.batch = journal.batch();
+    Ok(summary)
+}
+
+/// Reconsiders the track at the given path in the library, moving it if
+/// its tags changed, and updates the database to match. Returns the track
+/// as it is now, or None if its file was gone and it was removed.
+pub fn refresh_track(
+    file_path: &Path,
+    library_path: &Path,
+    template: &NamingTemplate,
+    journal: &Journal,
+    conn: &Connection,
+) -> SeiriResult<Option<Track>> {
+    let file_path = file_path.to_string_lossy().into_owned();
+    let track = database::query_tracks(Bang::FilePath(file_path.clone()), conn, template, &SortSpec::default(), None, None)?
+        .into_iter()
+        .next()
+        .ok_or(Error::FileNotFound(file_path))?;
+    let content_hash = database::get_content_hash(&track.file_path, conn);
+    match reconsider_track(&track, library_path, template, journal)? {
+        Some(track_as_read) => {
+            let content_hash = if audio_differs(&track, &track_as_read) {
+                ensure_content_hash(&track_as_read, None)
+            } else {
+                ensure_content_hash(&track_as_read, content_hash)
+            };
+            database::remove_track(&track, conn);
+            database::add_track(&track_as_read, content_hash.as_ref(), conn);
+            Ok(Some(track_as_read))
+        }
+        None => {
+            database::remove_track(&track, conn);
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use bangs::Bang;
+use database;
+use error::{Error, Result};
+use katatsuki::Track;
+use naming::NamingTemplate;
+use rusqlite::Connection;
+use sort::SortSpec;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A kind of problem with the tags of tracks, which `!lint` can match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LintKind {
+    /// Tracks of one album that disagree on the year.
+    InconsistentYear,
+    /// Albums whose album artists and title are the same, apart from
+    /// case and spaces at their start or end, so that one album is
+    /// split into several.
+    SplitAlbum,
+    /// Folders that the naming template puts tracks in, which are the
+    /// same apart from case, so that they collide on filesystems that
+    /// ignore case.
+    CaseCollision,
+    /// A title, artist, album or album artist with spaces at its start
+    /// or end, runs of spaces, or whitespace other than spaces.
+    SuspiciousWhitespace,
+}
+
+impl LintKind {
+    /// The name of the kind, as `!lint` accepts it.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LintKind::InconsistentYear => "year",
+            LintKind::SplitAlbum => "split",
+            LintKind::CaseCollision => "case",
+            LintKind::SuspiciousWhitespace => "whitespace",
+        }
+    }
+}
+
+impl FromStr for LintKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "year" => Ok(LintKind::InconsistentYear),
+            "split" => Ok(LintKind::SplitAlbum),
+            "case" => Ok(LintKind::CaseCollision),
+            "whitespace" => Ok(LintKind::SuspiciousWhitespace),
+            _ => Err(Error::UnknownLintKind(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A problem with the tags of some tracks.
+#[derive(Debug)]
+pub struct LintIssue {
+    pub kind: LintKind,
+    /// What is wrong, such as the values that disagree.
+    pub description: String,
+    /// The folders that the naming template puts the tracks in.
+    pub folders: Vec<PathBuf>,
+    pub file_paths: Vec<PathBuf>,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind, self.description)?;
+        for folder in &self.folders {
+            write!(f, "\n    {}", folder.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// The album artists of the track, as they are stored in the database.
+fn album_artists(track: &Track) -> String {
+    track.album_artists.join(";")
+}
+
+/// Folds text the way `lower(trim(...))` does in SQLite, which only
+/// lowercases ASCII letters and only trims spaces.
+fn fold(text: &str) -> String {
+    text.trim_matches(' ').to_ascii_lowercase()
+}
+
+fn album_key(track: &Track) -> (String, String) {
+    (album_artists(track), track.album.to_owned())
+}
+
+fn folded_album_key(track: &Track) -> (String, String) {
+    (fold(&album_artists(track)), fold(&track.album))
+}
+
+const UNUSUAL_WHITESPACE: &[char] = &['\t', '\n', '\r', '\u{a0}'];
+
+fn has_suspicious_whitespace(text: &str) -> bool {
+    text.starts_with(' ')
+        || text.ends_with(' ')
+        || text.contains("  ")
+        || text.contains(UNUSUAL_WHITESPACE)
+}
+
+/// The fields of the track that have suspicious whitespace.
+/// Album artists are checked as they are stored, so that each
+/// album artist is checked on its own.
+fn whitespace_fields(track: &Track) -> Vec<&'static str> {
+    let album_artists = album_artists(track);
+    let mut fields = Vec::new();
+    if has_suspicious_whitespace(&track.title) {
+        fields.push("title");
+    }
+    if has_suspicious_whitespace(&track.artist) {
+        fields.push("artist");
+    }
+    if has_suspicious_whitespace(&track.album) {
+        fields.push("album");
+    }
+    if has_suspicious_whitespace(&album_artists)
+        || album_artists.contains("; ")
+        || album_artists.contains(" ;")
+    {
+        fields.push("album artists");
+    }
+    fields
+}
+
+/// The paths of the folders that the naming template puts the track in,
+/// from the outermost folder to the folder of the track itself.
+fn folder_paths(track: &Track, template: &NamingTemplate) -> Vec<String> {
+    let mut path = PathBuf::new();
+    template
+        .track_directory(track, Path::new(""))
+        .components()
+        .map(|folder| {
+            path.push(folder);
+            path.to_string_lossy().into_owned()
+        })
+        .collect()
+}
+
+/// The folders that the naming template puts a list of tracks in, to
+/// find the folders that collide apart from case without comparing
+/// every pair of tracks.
+pub struct FolderIndex {
+    /// The folders of each track, by file path.
+    folders: HashMap<PathBuf, Vec<String>>,
+    /// How many tracks are in each spelling of a folder, by the folder
+    /// in lower case.
+    spellings: HashMap<String, HashMap<String, usize>>,
+}
+
+impl FolderIndex {
+    pub fn new(tracks: &[Track], template: &NamingTemplate) -> FolderIndex {
+        let folders = tracks
+            .iter()
+            .map(|track| (track.file_path.clone(), folder_paths(track, template)))
+            .collect::<HashMap<PathBuf, Vec<String>>>();
+        let mut spellings = HashMap::<String, HashMap<String, usize>>::new();
+        for folder in folders.values().flat_map(|folders| folders.iter()) {
+            *spellings
+                .entry(folder.to_lowercase())
+                .or_insert_with(HashMap::new)
+                .entry(folder.clone())
+                .or_insert(0) += 1;
+        }
+        FolderIndex { folders, spellings }
+    }
+
+    /// Whether another of the tracks is in a folder that only differs
+    /// in case from one of the folders of the track, which does not
+    /// need to be one of the tracks.
+    pub fn has_case_collision(&self, track: &Track, template: &NamingTemplate) -> bool {
+        let own_folders = self.folders.get(&track.file_path);
+        folder_paths(track, template)
+            .iter()
+            .enumerate()
+            .any(|(depth, folder)| match self.spellings.get(&folder.to_lowercase()) {
+                Some(spellings) => spellings.iter().any(|(spelling, &count)| {
+                    // The track is not counted against itself if it is one of the tracks.
+                    let own_count = match own_folders.and_then(|own_folders| own_folders.get(depth)) {
+                        Some(own_folder) if own_folder == spelling => 1,
+                        _ => 0,
+                    };
+                    spelling != folder && count > own_count
+                }),
+                None => false,
+            })
+    }
+
+    /// The outermost folder of one of the tracks that has more than one
+    /// spelling, or the folder of the track itself if none does.
+    fn colliding_folder(&self, track: &Track) -> String {
+        let folders = &self.folders[&track.file_path];
+        folders
+            .iter()
+            .find(|folder| self.spellings[&folder.to_lowercase()].len() > 1)
+            .or_else(|| folders.last())
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Groups the tracks whose folders collide apart from case, with the
+/// spellings of the outermost folder that collides. Every folder on the
+/// way to a track is checked, since a collision merges everything in it.
+pub fn case_collisions<'a>(tracks: &'a [Track], template: &NamingTemplate) -> Vec<(Vec<&'a Track>, BTreeSet<String>)> {
+    let index = FolderIndex::new(tracks, template);
+    // Tracks whose folders do not collide are grouped by their own folder,
+    // which only has one spelling.
+    conflicting_groups(
+        tracks,
+        |track| index.colliding_folder(track).to_lowercase(),
+        |track| index.colliding_folder(track),
+    )
+}
+
+/// Whether the tracks are in conflict with each other in a way that
+/// the lint reports. This does not check whitespace, which is a
+/// problem of a track on its own.
+pub fn conflicts(kind: LintKind, track: &Track, other: &Track, template: &NamingTemplate) -> bool {
+    match kind {
+        LintKind::InconsistentYear => album_key(track) == album_key(other) && track.year != other.year,
+        LintKind::SplitAlbum => {
+            folded_album_key(track) == folded_album_key(other) && album_key(track) != album_key(other)
+        }
+        // The first folder that differs is the outermost one that could collide.
+        LintKind::CaseCollision => folder_paths(track, template)
+            .iter()
+            .zip(folder_paths(other, template).iter())
+            .find(|&(path, other_path)| path != other_path)
+            .map_or(false, |(path, other_path)| path.to_lowercase() == other_path.to_lowercase()),
+        LintKind::SuspiciousWhitespace => false,
+    }
+}
+
+/// Whether the track has suspicious whitespace in its tags.
+pub fn has_whitespace_issue(track: &Track) -> bool {
+    !whitespace_fields(track).is_empty()
+}
+
+fn quote_all<'a, I: Iterator<Item = &'a String>>(values: I) -> String {
+    values
+        .map(|value| format!("{:?}", value))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn describe_album(track: &Track) -> String {
+    format!("{} - {}", track.album_artists.join(", "), track.album)
+}
+
+/// Groups the tracks by a key, in the order that each key first appears,
+/// keeping only the groups that more than one value of the tracks is in.
+fn conflicting_groups<'a, K, V, FK, FV>(tracks: &'a [Track], key: FK, value: FV) -> Vec<(Vec<&'a Track>, BTreeSet<V>)>
+where
+    K: ::std::hash::Hash + Eq,
+    V: Ord,
+    FK: Fn(&Track) -> K,
+    FV: Fn(&Track) -> V,
+{
+    let mut indices = HashMap::<K, usize>::new();
+    let mut groups = Vec::<(Vec<&Track>, BTreeSet<V>)>::new();
+    for track in tracks {
+        let index = *indices.entry(key(track)).or_insert_with(|| {
+            groups.push((Vec::new(), BTreeSet::new()));
+            groups.len() - 1
+        });
+        groups[index].0.push(track);
+        groups[index].1.insert(value(track));
+    }
+    groups
+        .into_iter()
+        .filter(|&(_, ref values)| values.len() > 1)
+        .collect()
+}
+
+fn issue(
+    kind: LintKind,
+    description: String,
+    tracks: &[&Track],
+    template: &NamingTemplate,
+    library_path: &Path,
+) -> LintIssue {
+    let mut folders = Vec::<PathBuf>::new();
+    for track in tracks {
+        let folder = template.track_directory(track, library_path);
+        if !folders.contains(&folder) {
+            folders.push(folder);
+        }
+    }
+    LintIssue {
+        kind,
+        description,
+        folders,
+        file_paths: tracks.iter().map(|track| track.file_path.clone()).collect(),
+    }
+}
+
+/// Finds the problems with the tags of the tracks, with the folders
+/// that the naming template puts them in.
+pub fn lint_tracks(tracks: &[Track], template: &NamingTemplate, library_path: &Path) -> Vec<LintIssue> {
+    let mut issues = Vec::new();
+
+    for (group, years) in conflicting_groups(tracks, album_key, |track| track.year) {
+        let years = years.iter().map(|year| year.to_string()).collect::<Vec<String>>();
+        let description = format!(
+            "Tracks of {} disagree on the year: {}",
+            describe_album(group[0]),
+            years.join(", ")
+        );
+        issues.push(issue(LintKind::InconsistentYear, description, &group, template, library_path));
+    }
+
+    for (group, spellings) in conflicting_groups(tracks, folded_album_key, album_key) {
+        let spellings = spellings
+            .iter()
+            .map(|&(ref album_artists, ref album)| format!("{} - {}", album_artists.replace(';', ", "), album))
+            .collect::<Vec<String>>();
+        let description = format!(
+            "One album is split into {} albums: {}",
+            spellings.len(),
+            quote_all(spellings.iter())
+        );
+        issues.push(issue(LintKind::SplitAlbum, description, &group, template, library_path));
+    }
+
+    for (group, spellings) in case_collisions(tracks, template) {
+        let description = format!(
+            "Folders only differ in case: {}",
+            quote_all(spellings.iter())
+        );
+        issues.push(issue(LintKind::CaseCollision, description, &group, template, library_path));
+    }
+
+    for track in tracks {
+        let fields = whitespace_fields(track);
+        if !fields.is_empty() {
+            let description = format!(
+                "The {} of {:?} has extra or unusual whitespace",
+                fields.join(", "),
+                track.file_path.to_string_lossy()
+            );
+            issues.push(issue(LintKind::SuspiciousWhitespace, description, &[track], template, library_path));
+        }
+    }
+
+    issues
+}
+
+/// Finds the problems with the tags of every track in the library.
+pub fn lint_library(conn: &Connection, template: &NamingTemplate, library_path: &Path) -> Result<Vec<LintIssue>> {
+    let tracks = database::query_tracks(Bang::All, conn, template, &SortSpec::default(), None, None)?;
+    Ok(lint_tracks(&tracks, template, library_path))
+}
+
+/// Writes the issues as a plain text report.
+pub fn write_report<W: Write>(issues: &[LintIssue], writer: &mut W) -> io::Result<()> {
+    match issues.len() {
+        1 => writeln!(writer, "Found 1 issue")?,
+        count => writeln!(writer, "Found {} issues", count)?,
+    }
+    for issue in issues {
+        writeln!(writer, "{}", issue)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,482 @@
+use albums::{find_album_problems, TrackPosition};
+use bangs::{ms_to_ticks, Bang, Comparison, NumericField};
+use database;
+use fingerprint;
+use katatsuki::{ToPrimitive, Track, TrackFileType};
+use lint::{self, FolderIndex, LintKind};
+use naming::NamingTemplate;
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+/// What a bang needs to know about the rest of the library to match
+/// a track. A track is never counted as a duplicate of itself, so a
+/// track that is not in the library yet is a duplicate if it would be
+/// one once it was added.
+pub trait TrackLookup {
+    /// Whether another track has the same title and album artists.
+    fn has_duplicates(&self, track: &Track) -> bool;
+    /// Whether another track has the same content hash.
+    fn has_duplicate_audio(&self, track: &Track) -> bool;
+    /// Whether another track of the same album has a different file type.
+    fn album_has_other_format(&self, track: &Track) -> bool;
+    /// Whether another track of the same album has no cover art.
+    fn album_has_missing_cover_art(&self, track: &Track) -> bool;
+    /// Where the other tracks of the same album are in the album.
+    fn album_positions(&self, track: &Track) -> Vec<TrackPosition>;
+    /// Whether another track conflicts with the track, in a way that
+    /// the given kind of lint reports.
+    fn has_conflict(&self, track: &Track, kind: LintKind) -> bool;
+}
+
+fn same_album(track: &Track, other: &Track) -> bool {
+    other.file_path != track.file_path
+        && other.album == track.album
+        && other.album_artists == track.album_artists
+}
+
+/// A list of tracks to look up duplicates and albums in, such as the
+/// tracks that a client is showing.
+pub struct TrackList {
+    pub tracks: Vec<Track>,
+    /// The content hashes of the tracks that are known, by file path.
+    pub content_hashes: HashMap<PathBuf, String>,
+    /// The naming template that puts the tracks in folders, which case
+    /// collisions are found in.
+    pub template: NamingTemplate,
+    /// The folders of the tracks, which are found the first time a track
+    /// is checked for case collisions.
+    folders: RefCell<Option<FolderIndex>>,
+}
+
+impl TrackList {
+    pub fn new(
+        tracks: Vec<Track>,
+        content_hashes: HashMap<PathBuf, String>,
+        template: NamingTemplate,
+    ) -> TrackList {
+        TrackList {
+            tracks,
+            content_hashes,
+            template,
+            folders: RefCell::new(None),
+        }
+    }
+}
+
+impl TrackLookup for TrackList {
+    fn has_duplicates(&self, track: &Track) -> bool {
+        self.tracks.iter().any(|other| {
+            other.file_path != track.file_path
+                && other.title == track.title
+                && other.album_artists == track.album_artists
+        })
+    }
+
+    fn has_duplicate_audio(&self, track: &Track) -> bool {
+        // A track that is not in the list is hashed from its file.
+        let content_hash = fingerprint::ensure_content_hash(
+            track,
+            self.content_hashes.get(&track.file_path).cloned(),
+        );
+        match content_hash {
+            Some(ref content_hash) => self.tracks.iter().any(|other| {
+                other.file_path != track.file_path
+                    && self.content_hashes.get(&other.file_path) == Some(content_hash)
+            }),
+            None => false,
+        }
+    }
+
+    fn album_has_other_format(&self, track: &Track) -> bool {
+        let file_type = track.file_type.to_i32();
+        self.tracks
+            .iter()
+            .any(|other| same_album(track, other) && other.file_type.to_i32() != file_type)
+    }
+
+    fn album_has_missing_cover_art(&self, track: &Track) -> bool {
+        self.tracks
+            .iter()
+            .any(|other| same_album(track, other) && !other.has_front_cover)
+    }
+
+    fn album_positions(&self, track: &Track) -> Vec<TrackPosition> {
+        self.tracks
+            .iter()
+            .filter(|other| same_album(track, other))
+            .map(TrackPosition::of)
+            .collect()
+    }
+
+    fn has_conflict(&self, track: &Track, kind: LintKind) -> bool {
+        if kind == LintKind::CaseCollision {
+            let mut folders = self.folders.borrow_mut();
+            let folders = folders.get_or_insert_with(|| FolderIndex::new(&self.tracks, &self.template));
+            return folders.has_case_collision(track, &self.template);
+        }
+        self.tracks
+            .iter()
+            .any(|other| {
+                other.file_path != track.file_path && lint::conflicts(kind, track, other, &self.template)
+            })
+    }
+}
+
+thread_local! {
+    static CACHED_REGEXES: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// Whether the text matches a pattern the way `REGEXP` does.
+/// An invalid pattern matches nothing.
+fn regexp(pattern: &str, text: &str) -> bool {
+    CACHED_REGEXES.with(|cached_regexes| {
+        cached_regexes
+            .borrow_mut()
+            .entry(pattern.to_owned())
+            .or_insert_with(|| Regex::new(pattern).ok())
+            .as_ref()
+            .map(|regex| database::regexp_matches(regex, text))
+            .unwrap_or(false)
+    })
+}
+
+/// Whether the text matches a pattern the way `LIKE` does, where `%`
+/// matches any run of characters and `_` matches any one character.
+/// Only ASCII letters match regardless of case.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let text = text.chars().collect::<Vec<char>>();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume if the text after the last `%` stops matching.
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '%' {
+            p += 1;
+            backtrack = Some((p, t));
+        } else if p < pattern.len()
+            && (pattern[p] == '_' || pattern[p].eq_ignore_ascii_case(&text[t]))
+        {
+            p += 1;
+            t += 1;
+        } else if let Some((backtrack_p, backtrack_t)) = backtrack {
+            p = backtrack_p;
+            t = backtrack_t + 1;
+            backtrack = Some((backtrack_p, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+/// The combining diacritics that the search index removes from words,
+/// given as a mask of the code points from U+0300 to U+0331.
+const SEARCH_DIACRITICS: (u32, u32) = (0x0802_9FDF, 0x0003_61F8);
+
+/// Whether the search index removes the character, a combining
+/// diacritic, from words.
+fn is_search_diacritic(c: char) -> bool {
+    let (low, high) = SEARCH_DIACRITICS;
+    match c as u32 {
+        c @ 0x300..=0x31F => low & (1 << (c - 0x300)) != 0,
+        c @ 0x320..=0x331 => high & (1 << (c - 0x320)) != 0,
+        _ => false,
+    }
+}
+
+/// Whether the search index counts the character as part of a word.
+/// Letters, numbers and private use characters are, but combining
+/// marks are not, apart from the diacritics that are removed.
+fn is_search_token_char(c: char) -> bool {
+    match c as u32 {
+        0xE000..=0xF8FF | 0xF_0000..=0xF_FFFD | 0x10_0000..=0x10_FFFD => true,
+        _ => (c.is_alphanumeric() && !is_combining_mark(c)) || is_search_diacritic(c),
+    }
+}
+
+/// Folds the case of the character the way the search index does.
+/// Unlike lowercasing, case folding also merges the variant forms of
+/// some lowercase letters.
+fn fold_case(c: char) -> char {
+    match c {
+        'µ' => 'μ',
+        'ſ' => 's',
+        'ẛ' => 'ṡ',
+        'ς' => 'σ',
+        'ϐ' => 'β',
+        'ϑ' => 'θ',
+        'ϕ' => 'φ',
+        'ϖ' => 'π',
+        'ϰ' => 'κ',
+        'ϱ' => 'ρ',
+        'ϵ' => 'ε',
+        '\u{1FBE}' => 'ι',
+        c => c,
+    }
+}
+
+/// The letter without its diacritic, for the letters that the search
+/// index folds to an ASCII letter. Only Latin letters that decompose
+/// to an ASCII letter and a single diacritic are folded.
+fn remove_diacritic(c: char) -> char {
+    match c as u32 {
+        0xC0..=0x24F | 0x1E00..=0x1EFF => (),
+        _ => return c,
+    }
+    let mut parts = Vec::new();
+    decompose_canonical(c, |part| parts.push(part));
+    if parts.len() == 2 && parts[0].is_ascii_alphabetic() {
+        parts[0]
+    } else {
+        c
+    }
+}
+
+/// Splits text into words the way the search index does, as runs of
+/// letters and numbers folded to lowercase without diacritics. This
+/// agrees with the index on characters that were in Unicode 6.1, which
+/// the tables of the index are built from. The index counts characters
+/// added since as parts of words, while this follows their categories.
+pub fn search_tokens(text: &str) -> Vec<String> {
+    text.split(|c: char| !is_search_token_char(c))
+        .filter(|token| !token.is_empty())
+        .map(|token| {
+            token
+                .chars()
+                .flat_map(|c| c.to_lowercase())
+                .map(fold_case)
+                .map(remove_diacritic)
+                .filter(|&c| !is_search_diacritic(c))
+                .collect()
+        })
+        .filter(|token: &String| !token.is_empty())
+        .collect()
+}
+
+/// Whether the phrase appears in the words of a column,
+/// with its last word matching as a prefix.
+fn phrase_matches(phrase: &[String], column: &[String]) -> bool {
+    let (last, words) = match phrase.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    column.windows(phrase.len()).any(|window| {
+        window[..words.len()] == words[..] && window[words.len()].starts_with(last.as_str())
+    })
+}
+
+/// The columns of a track that a full text search looks in.
+fn search_columns(track: &Track) -> Vec<String> {
+    vec![
+        track.title.to_owned(),
+        track.album.to_owned(),
+        track.artist.to_owned(),
+        track.album_artists.join(";"),
+    ]
+}
+
+fn full_text_search(search: &str, track: &Track) -> bool {
+    let columns = search_columns(track);
+    match database::search_phrases(search) {
+        // Every phrase must be found in the index, each within one column.
+        Some(phrases) => {
+            let columns = columns
+                .iter()
+                .map(|column| search_tokens(column))
+                .collect::<Vec<Vec<String>>>();
+            phrases.iter().all(|phrase| {
+                let phrase = search_tokens(phrase);
+                columns.iter().any(|column| phrase_matches(&phrase, column))
+            })
+        }
+        None => {
+            let pattern = format!("%{}%", search);
+            columns[..3].iter().any(|column| like(&pattern, column))
+                || regexp(&database::album_artists_search_pattern(search), &columns[3])
+        }
+    }
+}
+
+fn full_text_search_exact(search: &str, track: &Track) -> bool {
+    // Whatever matches exactly is also found by the index, so it can be
+    // checked without it.
+    track.title == search
+        || track.album == search
+        || track.artist == search
+        || regexp(
+            &database::album_artists_exact_pattern(search),
+            &track.album_artists.join(";"),
+        )
+}
+
+fn format_matches(format: &TrackFileType, track: &Track) -> bool {
+    let file_type = track.file_type.to_i32().unwrap();
+    let between = |low: TrackFileType, high: TrackFileType| {
+        low.to_i32().unwrap() <= file_type && file_type <= high.to_i32().unwrap()
+    };
+    match *format {
+        TrackFileType::FLAC => between(TrackFileType::FLAC4, TrackFileType::FLAC),
+        TrackFileType::AIFF => between(TrackFileType::AIFF4, TrackFileType::AIFF),
+        TrackFileType::ALAC => between(TrackFileType::ALAC16, TrackFileType::ALAC),
+        TrackFileType::MonkeysAudio => {
+            between(TrackFileType::MonkeysAudio8, TrackFileType::MonkeysAudio)
+        }
+        TrackFileType::MP3 => {
+            file_type == TrackFileType::MP3CBR.to_i32().unwrap()
+                || file_type == TrackFileType::MP3VBR.to_i32().unwrap()
+        }
+        ref format => file_type == format.to_i32().unwrap(),
+    }
+}
+
+fn numeric_value(field: NumericField, track: &Track) -> i64 {
+    match field {
+        NumericField::Bitrate => track.bitrate as i64,
+        NumericField::CoverArtWidth => track.front_cover_width as i64,
+        NumericField::CoverArtHeight => track.front_cover_height as i64,
+        NumericField::Duration => ms_to_ticks(track.duration),
+        NumericField::Year => track.year as i64,
+        NumericField::SampleRate => track.sample_rate as i64,
+        NumericField::TrackNumber => track.track_number as i64,
+        NumericField::DiscNumber => track.disc_number as i64,
+    }
+}
+
+fn compare(comparison: &Comparison, value: i64) -> bool {
+    match *comparison {
+        Comparison::Equal(other) => value == other,
+        Comparison::LessThan(other) => value < other,
+        Comparison::LessThanOrEqual(other) => value <= other,
+        Comparison::GreaterThan(other) => value > other,
+        Comparison::GreaterThanOrEqual(other) => value >= other,
+        Comparison::Between(low, high) => low <= value && value <= high,
+    }
+}
+
+const LINT_KINDS: &[LintKind] = &[
+    LintKind::InconsistentYear,
+    LintKind::SplitAlbum,
+    LintKind::CaseCollision,
+    LintKind::SuspiciousWhitespace,
+];
+
+fn has_lint(kind: LintKind, track: &Track, lookup: &dyn TrackLookup) -> bool {
+    match kind {
+        LintKind::SuspiciousWhitespace => lint::has_whitespace_issue(track),
+        kind => lookup.has_conflict(track, kind),
+    }
+}
+
+impl Bang {
+    /// Whether the track matches the bang, the same way it would if
+    /// the bang was queried from the database. This works on tracks that
+    /// are not in the database, such as tracks that were just read.
+    pub fn matches(&self, track: &Track, lookup: &dyn TrackLookup) -> bool {
+        match *self {
+            Bang::All => true,
+            Bang::FilePath(ref path) => track.file_path.to_string_lossy() == path.as_str(),
+            Bang::TitleSearch(ref title) => like(&format!("%{}%", title), &track.title),
+            Bang::TitleSearchExact(ref title) => &track.title == title,
+            Bang::AlbumTitle(ref title) => like(&format!("%{}%", title), &track.album),
+            Bang::AlbumTitleExact(ref title) => &track.album == title,
+            Bang::Artist(ref artist) => like(&format!("%{}%", artist), &track.artist),
+            Bang::ArtistExact(ref artist) => &track.artist == artist,
+            Bang::AlbumArtists(ref artist) => regexp(
+                &database::album_artists_search_pattern(artist),
+                &track.album_artists.join(";"),
+            ),
+            Bang::AlbumArtistsExact(ref artist) => regexp(
+                &database::album_artists_exact_pattern(artist),
+                &track.album_artists.join(";"),
+            ),
+            Bang::Source(ref source) => track.source.eq_ignore_ascii_case(source),
+            Bang::Format(ref format) => format_matches(format, track),
+            Bang::Compare(field, ref comparison) => {
+                compare(comparison, numeric_value(field, track))
+            }
+            Bang::UpdatedBefore(ref date) => track.updated < database::resolve_date(date),
+            Bang::UpdatedAfter(ref date) => track.updated > database::resolve_date(date),
+            Bang::HasCoverArt(has) => track.has_front_cover == has,
+            Bang::HasMusicbrainzId(has) => track.musicbrainz_track_id.is_some() == has,
+            Bang::HasDuplicates(has) => lookup.has_duplicates(track) == has,
+            Bang::HasDuplicateAudio(has) => lookup.has_duplicate_audio(track) == has,
+            Bang::AlbumHasMixedFormats(mixed) => lookup.album_has_other_format(track) == mixed,
+            Bang::AlbumIsIncomplete(incomplete) => {
+                let mut positions = lookup.album_positions(track);
+                positions.push(TrackPosition::of(track));
+                !find_album_problems(&positions).is_empty() == incomplete
+            }
+            Bang::Lint(Some(kind)) => has_lint(kind, track, lookup),
+            Bang::Lint(None) => LINT_KINDS.iter().any(|&kind| has_lint(kind, track, lookup)),
+            Bang::AlbumHasCoverArt(has) => {
+                (track.has_front_cover && !lookup.album_has_missing_cover_art(track)) == has
+            }
+            Bang::FullTextSearch(ref search) => full_text_search(search, track),
+            Bang::FullTextSearchExact(ref search) => full_text_search_exact(search, track),
+            Bang::LogicalAnd(ref lhs, ref rhs) => {
+                lhs.matches(track, lookup) && rhs.matches(track, lookup)
+            }
+            Bang::LogicalOr(ref lhs, ref rhs) => {
+                lhs.matches(track, lookup) || rhs.matches(track, lookup)
+            }
+            Bang::LogicalNot(ref bang) => !bang.matches(track, lookup),
+            Bang::Grouping(ref bang) => bang.matches(track, lookup),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::search_tokens;
+    use rusqlite::Connection;
+
+    /// Text in scripts with and without diacritics and combining marks.
+    /// Every character in it was in Unicode 6.1, which the tables of the
+    /// search index are built from.
+    const FIXTURES: &[&str] = &[
+        "Hello, World! 50% off_the rock'n'roll",
+        "ÀÉÎÕÜ àéîõü ÿ Ç ñ Ø ø Æ æ ß Ð þ",
+        "Ǎ ǎ Ǖ ǖ ǘ Ḿ ḿ Ẽ ẽ Ỳ ỳ Tiếng Việt Ơ ư",
+        "Ł ł Đ đ Ħ ħ Ŋ ŋ Œ œ İstanbul ı",
+        "e\u{301}te\u{301} a\u{308}a\u{30C}a\u{332} o\u{31B}",
+        "µs ſ ẛ ς ϐ ϑ ϕ ϖ ϰ ϱ ϵ \u{1FBE}",
+        "Ἀθῆναι ΆΣΤΡΑ άστρα Ωμέγα",
+        "Ёлка ЁЛКА Йога й Ґ",
+        "日本語 ひらがな カタカナ 한국어 中文",
+        "हिन्दी বাংলা தமிழ் ไทย",
+        "ﾊﾝｶｸ ＦＵＬＬ ① ½ ² Ⅻ",
+        "\u{E000}x\u{F8FF} \u{F0000}",
+        "tab\there nb\u{a0}sp — “quoted” ♪♫ ★",
+    ];
+
+    /// The words that the search index finds in the text.
+    fn index_tokens(conn: &Connection, text: &str) -> Vec<String> {
+        conn.execute("DELETE FROM fixture", &[]).unwrap();
+        conn.execute("INSERT INTO fixture(Text) VALUES (?1)", &[&text])
+            .unwrap();
+        let mut statement = conn
+            .prepare("SELECT term FROM fixture_terms ORDER BY offset")
+            .unwrap();
+        let mut rows = statement.query(&[]).unwrap();
+        let mut tokens = Vec::new();
+        while let Some(Ok(row)) = rows.next() {
+            tokens.push(row.get(0));
+        }
+        tokens
+    }
+
+    #[test]
+    fn search_tokens_match_the_search_index() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE fixture USING fts5(Text);
+             CREATE VIRTUAL TABLE fixture_terms USING fts5vocab(fixture, 'instance');",
+        ).unwrap();
+        for text in FIXTURES {
+            assert_eq!(search_tokens(text), index_tokens(&conn, text), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use error::{Error, Result};
+use rusqlite;
+use rusqlite::Connection;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The schema migrations for the tracks database, in order.
+/// The migration at index `i` upgrades the database from
+/// `user_version` `i` to `i + 1`.
+///
+/// Migrations that have been released must never be changed;
+/// schema changes go in a new migration at the end.
+const MIGRATIONS: &[&str] = &[
+    // 1: The tracks table. Databases created before migrations existed
+    // are at version 0 but already have this table.
+    "CREATE TABLE IF NOT EXISTS tracks (
+        FilePath TEXT PRIMARY KEY,
+        Title TEXT,
+        Artist TEXT,
+        AlbumArtists TEXT,
+        Album TEXT,
+        Year INTEGER,
+        TrackNumber INTEGER,
+        MusicBrainzTrackId TEXT,
+        HasFrontCover INTEGER,
+        FrontCoverWidth INTEGER,
+        FrontCoverHeight INTEGER,
+        Bitrate INTEGER,
+        SampleRate INTEGER,
+        Source TEXT,
+        DiscNumber INTEGER,
+        Duration INTEGER,
+        FileType INTEGER,
+        Updated DATE
+    );",
+    // 2: A hash of the audio data of each track, for finding duplicates.
+    "ALTER TABLE tracks ADD COLUMN ContentHash TEXT;
+     CREATE INDEX tracks_content_hash ON tracks(ContentHash);",
+    // 3: The journal of file operations, so that moves can be rolled back.
+    "CREATE TABLE operations (
+        Id INTEGER PRIMARY KEY AUTOINCREMENT,
+        Batch INTEGER NOT NULL,
+        Kind TEXT NOT NULL,
+        OldPath TEXT NOT NULL,
+        NewPath TEXT,
+        Reason TEXT NOT NULL,
+        Timestamp DATETIME NOT NULL,
+        RolledBack INTEGER NOT NULL DEFAULT 0
+     );
+     CREATE INDEX operations_batch ON operations(Batch);",
+    // 4: The number of discs in the release, for naming templates.
+    "ALTER TABLE tracks ADD COLUMN DiscCount INTEGER;",
+    // 5: A full text search index of the tracks table, for !q and !Q.
+    // INSERT OR REPLACE does not fire delete triggers, so the old entry of a
+    // replaced track is removed before it is inserted instead.
+    "CREATE VIRTUAL TABLE tracks_fts USING fts5(
+        Title,
+        Album,
+        Artist,
+        AlbumArtists,
+        content = 'tracks'
+     );
+     INSERT INTO tracks_fts(tracks_fts) VALUES('rebuild');
+     CREATE TRIGGER tracks_fts_before_insert BEFORE INSERT ON tracks BEGIN
+        INSERT INTO tracks_fts(tracks_fts, rowid, Title, Album, Artist, AlbumArtists)
+            SELECT 'delete', rowid, Title, Album, Artist, AlbumArtists
+            FROM tracks WHERE FilePath = new.FilePath;
+     END;
+     CREATE TRIGGER tracks_fts_after_insert AFTER INSERT ON tracks BEGIN
+        INSERT INTO tracks_fts(rowid, Title, Album, Artist, AlbumArtists)
+            VALUES (new.rowid, new.Title, new.Album, new.Artist, new.AlbumArtists);
+     END;
+     CREATE TRIGGER tracks_fts_after_delete AFTER DELETE ON tracks BEGIN
+        INSERT INTO tracks_fts(tracks_fts, rowid, Title, Album, Artist, AlbumArtists)
+            VALUES ('delete', old.rowid, old.Title, old.Album, old.Artist, old.AlbumArtists);
+     END;
+     CREATE TRIGGER tracks_fts_after_update AFTER UPDATE OF Title, Album, Artist, AlbumArtists ON tracks BEGIN
+        INSERT INTO tracks_fts(tracks_fts, rowid, Title, Album, Artist, AlbumArtists)
+            VALUES ('delete', old.rowid, old.Title, old.Album, old.Artist, old.AlbumArtists);
+        INSERT INTO tracks_fts(rowid, Title, Album, Artist, AlbumArtists)
+            VALUES (new.rowid, new.Title, new.Album, new.Artist, new.AlbumArtists);
+     END;",
+    // 6: Named queries, which can be evaluated as smart playlists.
+    "CREATE TABLE saved_queries (
+        Name TEXT PRIMARY KEY,
+        Query TEXT NOT NULL,
+        Sort TEXT NOT NULL DEFAULT '',
+        TrackLimit INTEGER
+     );",
+];
+
+/// The schema version this build of seiri expects.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.len() as i32
+}
+
+fn get_user_version(conn: &Connection) -> rusqlite::Result<i32> {
+    conn.query_row("PRAGMA user_version", &[], |row| row.get_checked(0))
+        .and_then(|version| version)
+}
+
+fn has_tables(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", &[], |row| {
+        row.get_checked::<_, i32>(0)
+    }).and_then(|count| count)
+        .map(|count| count > 0)
+}
+
+/// Copies the database to a backup next to it, named after the
+/// schema version it is being upgraded from.
+fn backup_database(conn: &Connection, database_path: &Path, version: i32) -> Result<()> {
+    let mut backup_path = database_path.as_os_str().to_owned();
+    backup_path.push(format!(".v{}.bak", version));
+    let backup_path = PathBuf::from(backup_path);
+    let error = || Error::UnableToBackupDatabase(backup_path.to_string_lossy().into_owned());
+
+    // Move everything in the write-ahead log into the database file
+    // so the copy is complete.
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", &[], |_| ())
+        .map_err(|_| error())?;
+    fs::copy(database_path, &backup_path).map_err(|_| error())?;
+    Ok(())
+}
+
+/// Brings the database up to the latest schema version, backing up
+/// the database before applying any migrations to an existing schema.
+///
+/// Databases with a newer schema than this build of seiri are refused.
+pub fn migrate(conn: &Connection, database_path: &Path) -> Result<()> {
+    let latest = latest_version();
+    let migration_error = |err: rusqlite::Error| Error::DatabaseMigrationError(latest, err.to_string());
+
+    let version = get_user_version(conn).map_err(migration_error)?;
+    if version > latest {
+        return Err(Error::DatabaseTooNew(version, latest));
+    }
+    if version == latest {
+        return Ok(());
+    }
+
+    if has_tables(conn).map_err(migration_error)? {
+        backup_database(conn, database_path, version)?;
+    }
+
+    // Take the write lock before checking the version again, in case
+    // another connection migrated the database in the meantime.
+    conn.execute_batch("BEGIN IMMEDIATE").map_err(migration_error)?;
+    let version = match get_user_version(conn) {
+        Ok(version) => version,
+        Err(err) => {
+            conn.execute_batch("ROLLBACK").unwrap_or(());
+            return Err(migration_error(err));
+        }
+    };
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let target = index as i32 + 1;
+        let result = conn.execute_batch(migration)
+            .and_then(|_| conn.execute_batch(&format!("PRAGMA user_version = {}", target)));
+        if let Err(err) = result {
+            conn.execute_batch("ROLLBACK").unwrap_or(());
+            return Err(Error::DatabaseMigrationError(target, err.to_string()));
+        }
+    }
+    conn.execute_batch("COMMIT").map_err(migration_error)
+}
@@ -0,0 +1,312 @@
+use error::{Error, Result};
+use katatsuki::Track;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::default::Default;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::{Chars, FromStr};
+
+/// The naming template for the layout of the library before
+/// templates were configurable.
+pub const DEFAULT_TEMPLATE: &str = "{albumartist}/{album}/{disc}-{track:02} {title}";
+
+trait InvalidChar {
+    fn is_invalid_for_path(&self) -> bool;
+}
+
+impl InvalidChar for char {
+    fn is_invalid_for_path(&self) -> bool {
+        match *self {
+            '\"' | '<' | '>' | '|' | '\0' | ':' | '*' | '?' | '\\' | '/' => true,
+            _ => false,
+        }
+    }
+}
+
+pub fn sanitize_file_name(path: &str) -> String {
+    path.replace(|c: char| c.is_invalid_for_path(), "_")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    AlbumArtist,
+    Artist,
+    Album,
+    Title,
+    Year,
+    Track,
+    Disc,
+    Discs,
+    Source,
+}
+
+impl Field {
+    fn is_number(&self) -> bool {
+        match *self {
+            Field::Year | Field::Track | Field::Disc | Field::Discs => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = ();
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, ()> {
+        match s {
+            "albumartist" => Ok(Field::AlbumArtist),
+            "artist" => Ok(Field::Artist),
+            "album" => Ok(Field::Album),
+            "title" => Ok(Field::Title),
+            "year" => Ok(Field::Year),
+            "track" => Ok(Field::Track),
+            "disc" => Ok(Field::Disc),
+            "discs" => Ok(Field::Discs),
+            "source" => Ok(Field::Source),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    /// A field, with numbers zero-padded to the given width.
+    Field(Field, usize),
+    /// A section that is left out if any field in it is empty.
+    Optional(Vec<Segment>),
+}
+
+/// A template for the location of tracks in the library, such as
+/// `{albumartist}/{year} - {album}/[{disc}-]{track:02} {title}`.
+///
+/// Fields in braces are replaced with the tags of the track, and `/`
+/// separates folders. Numbers can be zero-padded with a width, as in
+/// `{track:02}`. Sections in square brackets are left out when any
+/// field in them is empty: text that is blank, a number that is 0,
+/// the source of a track that has none, or the disc of a release that
+/// has only one disc. Releases without a disc count are taken to have
+/// one disc when the track is on the first disc.
+///
+/// The available fields are `albumartist`, `artist`, `album`, `title`,
+/// `year`, `track`, `disc`, `discs` and `source`.
+#[derive(Debug, Clone)]
+pub struct NamingTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+fn invalid_template(template: &str, reason: &str) -> Error {
+    Error::InvalidNamingTemplate(template.to_owned(), reason.to_owned())
+}
+
+fn parse_field(chars: &mut Chars, template: &str) -> Result<Segment> {
+    let mut field = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(c) => field.push(c),
+            None => return Err(invalid_template(template, "a field is missing its closing '}'")),
+        }
+    }
+    let mut parts = field.splitn(2, ':');
+    let name = parts.next().unwrap_or("").trim();
+    let field = name.parse::<Field>()
+        .map_err(|_| invalid_template(template, &format!("unknown field {{{}}}", name)))?;
+    let width = match parts.next() {
+        Some(width) => {
+            if !field.is_number() {
+                return Err(invalid_template(
+                    template,
+                    &format!("{{{}}} is not a number and can not be padded", name),
+                ));
+            }
+            width.trim().parse::<usize>().map_err(|_| {
+                invalid_template(template, &format!("invalid width for {{{}}}", name))
+            })?
+        }
+        None => 0,
+    };
+    Ok(Segment::Field(field, width))
+}
+
+fn parse_segments(chars: &mut Chars, template: &str, optional: bool) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    loop {
+        match chars.next() {
+            Some('{') => segments.push(parse_field(chars, template)?),
+            Some('}') => return Err(invalid_template(template, "unexpected '}'")),
+            Some('[') => segments.push(Segment::Optional(parse_segments(chars, template, true)?)),
+            Some(']') if optional => return Ok(segments),
+            Some(']') => return Err(invalid_template(template, "unexpected ']'")),
+            Some(c) => {
+                if let Some(&mut Segment::Text(ref mut text)) = segments.last_mut() {
+                    text.push(c);
+                    continue;
+                }
+                segments.push(Segment::Text(c.to_string()))
+            }
+            None if optional => {
+                return Err(invalid_template(template, "a section is missing its closing ']'"))
+            }
+            None => return Ok(segments),
+        }
+    }
+}
+
+fn has_separator(segments: &[Segment]) -> bool {
+    segments.iter().any(|segment| match *segment {
+        Segment::Text(ref text) => text.contains('/'),
+        Segment::Optional(ref segments) => has_separator(segments),
+        Segment::Field(_, _) => false,
+    })
+}
+
+/// Whether the file name always has the title, so that every track
+/// gets a file name that is not blank.
+fn file_name_has_title(segments: &[Segment]) -> bool {
+    for segment in segments.iter().rev() {
+        match *segment {
+            Segment::Field(Field::Title, _) => return true,
+            Segment::Text(ref text) if text.contains('/') => return false,
+            Segment::Optional(ref segments) if has_separator(segments) => return false,
+            _ => (),
+        }
+    }
+    false
+}
+
+fn is_single_disc(track: &Track) -> bool {
+    track.disc_count == 1 || (track.disc_count == 0 && track.disc_number <= 1)
+}
+
+/// Renders a field of the track, and whether the field is empty.
+fn render_field(field: Field, width: usize, track: &Track) -> (String, bool) {
+    let text = match field {
+        Field::AlbumArtist => track.album_artists.join(", "),
+        Field::Artist => track.artist.to_owned(),
+        Field::Album => track.album.to_owned(),
+        Field::Title => track.title.to_owned(),
+        Field::Source if track.source == "None" => String::new(),
+        Field::Source => track.source.to_owned(),
+        Field::Year => return (format!("{:01$}", track.year, width), track.year == 0),
+        Field::Track => {
+            return (
+                format!("{:01$}", track.track_number, width),
+                track.track_number == 0,
+            )
+        }
+        Field::Disc => {
+            return (
+                format!("{:01$}", track.disc_number, width),
+                track.disc_number == 0 || is_single_disc(track),
+            )
+        }
+        Field::Discs => {
+            return (
+                format!("{:01$}", track.disc_count, width),
+                track.disc_count == 0,
+            )
+        }
+    };
+    let empty = text.trim().is_empty();
+    (sanitize_file_name(&text), empty)
+}
+
+/// Renders the segments into the path, returning false if any field was empty.
+fn render_segments(segments: &[Segment], track: &Track, path: &mut String) -> bool {
+    let mut complete = true;
+    for segment in segments {
+        match *segment {
+            Segment::Text(ref text) => path.push_str(text),
+            Segment::Field(field, width) => {
+                let (value, empty) = render_field(field, width, track);
+                complete &= !empty;
+                path.push_str(&value);
+            }
+            Segment::Optional(ref segments) => {
+                let mut section = String::new();
+                if render_segments(segments, track, &mut section) {
+                    path.push_str(&section);
+                }
+            }
+        }
+    }
+    complete
+}
+
+impl NamingTemplate {
+    /// Renders the folders and the file name of the track.
+    fn render(&self, track: &Track) -> (Vec<String>, String) {
+        let mut path = String::new();
+        render_segments(&self.segments, track, &mut path);
+        let mut parts = path.split('/')
+            .map(|part| sanitize_file_name(part))
+            .collect::<Vec<String>>();
+        let file_name = parts.pop().unwrap_or_default();
+        let folders = parts
+            .iter()
+            .map(|folder| folder.trim().to_owned())
+            .filter(|folder| !folder.is_empty())
+            .collect();
+        (folders, file_name)
+    }
+
+    /// Gets the folder of the track in the library.
+    pub fn track_directory(&self, track: &Track, library_path: &Path) -> PathBuf {
+        let mut track_path = PathBuf::from(library_path);
+        for folder in self.render(track).0 {
+            track_path.push(folder);
+        }
+        track_path
+    }
+
+    /// Gets the file name of the track, without the extension.
+    pub fn track_file_name(&self, track: &Track) -> String {
+        self.render(track).1
+    }
+}
+
+impl FromStr for NamingTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let segments = parse_segments(&mut s.chars(), s, false)?;
+        if !file_name_has_title(&segments) {
+            return Err(invalid_template(
+                s,
+                "the file name must contain {title}, outside of any optional section",
+            ));
+        }
+        Ok(NamingTemplate {
+            template: s.to_owned(),
+            segments,
+        })
+    }
+}
+
+impl Default for NamingTemplate {
+    fn default() -> NamingTemplate {
+        DEFAULT_TEMPLATE.parse().unwrap()
+    }
+}
+
+impl fmt::Display for NamingTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+impl Serialize for NamingTemplate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.template)
+    }
+}
+
+impl<'de> Deserialize<'de> for NamingTemplate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let template = String::deserialize(deserializer)?;
+        template.parse().map_err(de::Error::custom)
+    }
+}
@@ -1,27 +1,19 @@
 use app_dirs::*;
 use chrono::prelude::*;
 use error::{Error, Result};
+use events::Event;
+use journal::Journal;
 use katatsuki::Track;
+use naming::{sanitize_file_name, NamingTemplate};
+use sha1::Sha1;
 // use tree_magic;
 use std::ascii::AsciiExt;
 use std::fs;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
 
-trait InvalidChar {
-    fn is_invalid_for_path(&self) -> bool;
-}
-
-impl InvalidChar for char {
-    fn is_invalid_for_path(&self) -> bool {
-        match *self {
-            '\"' | '<' | '>' | '|' | '\0' | ':' | '*' | '?' | '\\' | '/' => true,
-            _ => false,
-        }
-    }
-}
-
 pub fn new_track_checked(track_path: &Path, source: Option<&str>) -> Result<Track> {
 
     // let mimetype = tree_magic::from_filepath(track_path);
@@ -86,42 +78,34 @@ pub fn ensure_music_folder(folder_path: &str) -> io::Result<(PathBuf, PathBuf)>
     // Todo: handle these unwraps properly.
     let music_folder = Path::new(folder_path);
     let music_folder = PathBuf::from(music_folder);
-    let mut auto_add_folder = PathBuf::new();
-    music_folder.clone_into(&mut auto_add_folder);
-    auto_add_folder.pop();
-    auto_add_folder.push("Automatically Add to Library");
+    let auto_add_folder = get_auto_add_folder(&music_folder);
     fs::create_dir_all(music_folder.as_path())?;
     fs::create_dir_all(auto_add_folder.as_path())?;
     Ok((music_folder, auto_add_folder))
 }
 
-fn sanitize_file_name(path: &str) -> String {
-    path.replace(|c: char| c.is_invalid_for_path(), "_")
+/// The Automatically Add to Library folder, which is next to the music folder.
+pub fn get_auto_add_folder(music_folder: &Path) -> PathBuf {
+    let mut auto_add_folder = PathBuf::from(music_folder);
+    auto_add_folder.pop();
+    auto_add_folder.push("Automatically Add to Library");
+    auto_add_folder
 }
 
-pub fn get_track_directory(track: &Track, library_path: &Path) -> PathBuf {
-    let mut track_path = PathBuf::from(library_path);
-
-    let artist_folder = if track.album_artists.len() > 0 {
-        track.album_artists.join(", ")
-    } else {
-        (&track.album_artists[0]).to_owned()
-    };
-
-    let artist_folder = artist_folder.trim();
-    let album_folder = &track.album.to_owned();
-    let album_folder = album_folder.trim();
-    track_path.push(sanitize_file_name(&artist_folder));
-    track_path.push(sanitize_file_name(&album_folder));
-    track_path
+/// Where a file that was in the Automatically Add to Library folder is put
+/// back when it is rolled back. The file keeps its place under a hidden
+/// folder, so that the watcher does not add it again. Files that were not
+/// in the Automatically Add to Library folder are put back where they were.
+pub fn get_rolled_back_path(file_path: &Path, auto_add_path: &Path) -> PathBuf {
+    match file_path.strip_prefix(auto_add_path) {
+        Ok(relative_path) => auto_add_path.join(".rolledback").join(relative_path),
+        Err(_) => PathBuf::from(file_path),
+    }
 }
 
-fn get_track_filename(track: &Track) -> String {
-    let file_name = &format!(
-        "{}-{:02} {}",
-        &track.disc_number, &track.track_number, &track.title
-    );
-    sanitize_file_name(file_name)
+/// The source of a file that was rolled back out of the library.
+pub fn get_rolled_back_source(file_path: &Path, auto_add_path: &Path) -> String {
+    get_source(file_path, &auto_add_path.join(".rolledback"))
 }
 
 fn get_iterative_filename(filename: &str, extension: &str, destination: &Path) -> PathBuf {
@@ -138,6 +122,163 @@ fn get_iterative_filename(filename: &str, extension: &str, destination: &Path) -
     new_path
 }
 
+/// The size of the chunks that files are copied in.
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Copies of files at least this large report their progress.
+const LARGE_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// The error for renaming a file to another drive, EXDEV.
+#[cfg(not(windows))]
+const CROSS_DEVICE_ERROR: i32 = 18;
+
+/// The error for renaming a file to another drive, ERROR_NOT_SAME_DEVICE.
+#[cfg(windows)]
+const CROSS_DEVICE_ERROR: i32 = 17;
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(hasher.digest().to_string())
+}
+
+/// Copies the source into the destination, then checks that the copy
+/// has the same size and hash as the source once it is on disk.
+fn copy_verified<F: FnMut(u64, u64)>(
+    source: &Path,
+    destination: &mut File,
+    destination_path: &Path,
+    progress: &mut F,
+) -> io::Result<()> {
+    let mut source_file = File::open(source)?;
+    let total = source_file.metadata()?.len();
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = match source_file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buffer[..read]);
+        destination.write_all(&buffer[..read])?;
+        copied += read as u64;
+        progress(copied, total);
+    }
+    destination.sync_all()?;
+
+    if copied != total || fs::metadata(destination_path)?.len() != total {
+        return Err(io::Error::new(
+            ErrorKind::Other,
+            "the copy is not the same size as the original",
+        ));
+    }
+    if hash_file(destination_path)? != hasher.digest().to_string() {
+        return Err(io::Error::new(
+            ErrorKind::Other,
+            "the copy does not match the original",
+        ));
+    }
+
+    // Make sure the new directory entry is on disk before the source is deleted.
+    #[cfg(unix)]
+    {
+        if let Some(directory) = destination_path.parent() {
+            File::open(directory)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves a file, copying it when the destination is on another drive.
+/// A copy is checked against the source and synced to disk before the
+/// source is deleted. Progress is reported with the number of bytes
+/// copied so far and the size of the file.
+pub fn move_file_with_progress<F: FnMut(u64, u64)>(
+    source: &Path,
+    destination: &Path,
+    mut progress: F,
+) -> Result<()> {
+    let destination_name = || destination.to_string_lossy().into_owned();
+    match fs::rename(source, destination) {
+        Ok(_) => Ok(()),
+        Err(ref err) if err.raw_os_error() == Some(CROSS_DEVICE_ERROR) => {
+            // Never overwrite a file that is already at the destination.
+            let mut destination_file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(destination)
+                .map_err(|err| Error::UnableToCopy(destination_name(), err.to_string()))?;
+            let copied = copy_verified(source, &mut destination_file, destination, &mut progress);
+            drop(destination_file);
+            if let Err(err) = copied {
+                fs::remove_file(destination).unwrap_or(());
+                return Err(Error::UnableToCopy(destination_name(), err.to_string()));
+            }
+            // Keep the file in one place only if the source can't be removed.
+            if let Err(_) = fs::remove_file(source) {
+                fs::remove_file(destination).unwrap_or(());
+                return Err(Error::UnableToMove(destination_name()));
+            }
+            Ok(())
+        }
+        Err(_) => Err(Error::UnableToMove(destination_name())),
+    }
+}
+
+/// Moves a file, copying it when the destination is on another drive.
+/// The progress of copying large files is emitted as events.
+pub fn move_file(source: &Path, destination: &Path) -> Result<()> {
+    let mut reported = 0;
+    move_file_with_progress(source, destination, |copied, total| {
+        if total < LARGE_FILE_SIZE {
+            return;
+        }
+        let percent = copied * 100 / total;
+        if percent >= reported + 10 || (copied == total && percent != reported) {
+            reported = percent;
+            Event::CopyProgress {
+                path: destination.to_string_lossy().into_owned(),
+                percent,
+            }.emit();
+        }
+    })
+}
+
+/// Whether the track is already where the naming template would place it,
+/// allowing for the number added to the file name to avoid overwriting
+/// another file.
+pub fn is_track_in_place(track: &Track, library_path: &Path, template: &NamingTemplate) -> bool {
+    let track_file_path = Path::new(&track.file_path);
+    if track_file_path.parent() != Some(template.track_directory(track, library_path).as_path()) {
+        return false;
+    }
+    let file_stem = match track_file_path.file_stem().and_then(|s| s.to_str()) {
+        Some(file_stem) => file_stem,
+        None => return false,
+    };
+    let file_name = template.track_file_name(track);
+    if file_stem == file_name {
+        return true;
+    }
+    // Iterative file names look like "name (1)".
+    file_stem.starts_with(&format!("{} (", file_name)) && file_stem.ends_with(")")
+        && file_stem[file_name.len() + 2..file_stem.len() - 1]
+            .chars()
+            .all(|c| c.is_digit(10))
+}
+
 pub fn is_in_hidden_path(file_path: &Path, relative_to: &Path) -> bool {
     get_source(file_path, relative_to).starts_with(".")
 }
@@ -165,36 +306,60 @@ fn ensure_not_added(auto_add_path: &Path) -> io::Result<PathBuf> {
     }
 }
 
-pub fn move_non_track(path: &Path, auto_add_path: &Path) -> Result<()> {
+pub fn move_non_track(path: &Path, auto_add_path: &Path, journal: &Journal) -> Result<()> {
     if let Ok(notadded) = ensure_not_added(auto_add_path) {
         let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("");
         let filename = path.file_stem()
             .and_then(|s| s.to_str())
             .unwrap_or("unnamed file");
         let new_file_name = get_iterative_filename(filename, ext, &notadded);
-        if let Err(_) = fs::rename(path, &new_file_name) {
-            return Err(Error::UnableToMove(
-                new_file_name.to_string_lossy().into_owned(),
-            ));
-        } else {
-            return Ok(());
-        }
+        move_file(path, &new_file_name)?;
+        journal.record_move(path, &new_file_name);
+        return Ok(());
     }
     Err(Error::UnableToMove("not added folder".to_owned()))
 }
 
-fn track_warrants_move(track_as_saved: &Track, track_as_read: &Track) -> bool {
-    !(track_as_saved.title == track_as_read.title && track_as_saved.album == track_as_read.album
-        && track_as_saved.artist == track_as_read.artist
-        && track_as_saved.track_number == track_as_read.track_number
-        && track_as_saved.album_artists == track_as_read.album_artists)
+/// Whether the changes to the tags of the track give it a new location.
+fn track_warrants_move(
+    track_as_saved: &Track,
+    track_as_read: &Track,
+    library_path: &Path,
+    template: &NamingTemplate,
+) -> bool {
+    template.track_directory(track_as_saved, library_path)
+        != template.track_directory(track_as_read, library_path)
+        || template.track_file_name(track_as_saved) != template.track_file_name(track_as_read)
+}
+
+/// Removes the directory of a file that was moved away if it is empty,
+/// then each directory above it that was left empty, up to the library
+/// folder. Files outside the library leave their directories alone.
+pub fn remove_empty_parents(file_path: &Path, library_path: &Path, journal: &Journal) {
+    let mut old_dir = file_path.parent();
+    while let Some(dir) = old_dir {
+        if dir == library_path || !dir.starts_with(library_path) {
+            break;
+        }
+        // Stop at the first directory that still has files.
+        if let Err(_) = fs::remove_dir(dir) {
+            break;
+        }
+        journal.record_remove_directory(dir);
+        old_dir = dir.parent();
+    }
 }
 
 /// Reconsider the location of a track.
 /// If the file is gone or deleted, returns Ok(None).
 /// Otherwise, returns a new Track that has a new
 /// or same location, depending if its properties have changed.
-pub fn reconsider_track(track: &Track, library_path: &Path) -> Result<Option<Track>> {
+pub fn reconsider_track(
+    track: &Track,
+    library_path: &Path,
+    template: &NamingTemplate,
+    journal: &Journal,
+) -> Result<Option<Track>> {
     let track_file_path = Path::new(&track.file_path);
     if !track_file_path.exists() {
         return Ok(None);
@@ -202,7 +367,7 @@ pub fn reconsider_track(track: &Track, library_path: &Path) -> Result<Option<Tra
 
     match new_track_checked(track_file_path, Some(&track.source)) {
         Ok(track_as_read) => {
-            if !track_warrants_move(track, &track_as_read) {
+            if !track_warrants_move(track, &track_as_read, library_path, template) {
                 return Ok(Some(track_as_read));
             }
             let track_as_read = Track {
@@ -210,18 +375,17 @@ pub fn reconsider_track(track: &Track, library_path: &Path) -> Result<Option<Tra
                 ..track_as_read
             };
             println!("{:?}", track_as_read);
-            match move_track(&track_as_read, library_path, &track_as_read.source) {
-                Ok(track) => {
+            match move_track(
+                &track_as_read,
+                library_path,
+                template,
+                &track_as_read.source,
+                journal,
+            ) {
+                Ok(moved_track) => {
                     //  Cleanup
-                    if let Some(old_dir) = &track_file_path.parent() {
-                        // If the directory is empty, simply remove it.
-                        fs::remove_dir(old_dir).unwrap_or(());
-                        if let Some(old_dir) = old_dir.parent() {
-                            // Cleanup after the artist as well.
-                            fs::remove_dir(old_dir).unwrap_or(());
-                        }
-                    }
-                    Ok(Some(track))
+                    remove_empty_parents(track_file_path, library_path, journal);
+                    Ok(Some(moved_track))
                 }
                 Err(err) => Err(err),
             }
@@ -232,7 +396,13 @@ pub fn reconsider_track(track: &Track, library_path: &Path) -> Result<Option<Tra
 
 /// Moves the given track to its proper destination in the library, relative
 /// to the Automatically Add to Library path.
-pub fn move_new_track(track: &Track, library_path: &Path, auto_add_path: &Path) -> Result<Track> {
+pub fn move_new_track(
+    track: &Track,
+    library_path: &Path,
+    auto_add_path: &Path,
+    template: &NamingTemplate,
+    journal: &Journal,
+) -> Result<Track> {
     // The original path where the track was found.
     let original_path = Path::new(&track.file_path);
 
@@ -240,11 +410,17 @@ pub fn move_new_track(track: &Track, library_path: &Path, auto_add_path: &Path)
     // and marks it as the source.
     let source = get_source(original_path, auto_add_path);
 
-    move_track(track, library_path, &source)
+    move_track(track, library_path, template, &source, journal)
 }
 
 /// Moves a track to its proper position in the library, with the given source.
-pub fn move_track(track: &Track, library_path: &Path, source: &str) -> Result<Track> {
+pub fn move_track(
+    track: &Track,
+    library_path: &Path,
+    template: &NamingTemplate,
+    source: &str,
+    journal: &Journal,
+) -> Result<Track> {
     let track_file_path = Path::new(&track.file_path);
 
     // get the track file extension
@@ -272,10 +448,10 @@ pub fn move_track(track: &Track, library_path: &Path, source: &str) -> Result<Tr
     };
 
     // The new filename of the track, from the track metadata.
-    let track_file_name = get_track_filename(&track);
+    let track_file_name = template.track_file_name(&track);
 
     // The new directory of the track in the library, from track metadata
-    let track_folder = get_track_directory(&track, &library_path);
+    let track_folder = template.track_directory(&track, &library_path);
 
     // Ensure the new directory
     if let Err(_) = fs::create_dir_all(&track_folder) {
@@ -288,13 +464,12 @@ pub fn move_track(track: &Track, library_path: &Path, source: &str) -> Result<Tr
     let new_file_name = get_iterative_filename(&track_file_name, &track_ext, &track_folder);
 
     // Do the move.
-    if let Err(err) = fs::rename(track_file_path, &new_file_name) {
+    if let Err(err) = move_file(track_file_path, &new_file_name) {
         println!("{}", err);
         println!("{:?}", track_file_path);
-        Err(Error::UnableToMove(
-            new_file_name.to_string_lossy().into_owned(),
-        ))
+        Err(err)
     } else {
+        journal.record_move(track_file_path, &new_file_name);
         new_track_checked(&new_file_name, Some(&source))
     }
 }
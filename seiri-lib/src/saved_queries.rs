@@ -0,0 +1,163 @@
+use bangs::Bang;
+use database;
+use error::{Error, Result};
+use katatsuki::Track;
+use naming::NamingTemplate;
+use rusqlite;
+use rusqlite::types::ToSql;
+use rusqlite::Connection;
+use sort::SortSpec;
+use std::collections::BTreeMap;
+
+/// A bang query saved under a name, which can be evaluated
+/// by name like a smart playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedQuery {
+    pub name: String,
+    /// The bang to query with.
+    pub query: String,
+    /// The order of the tracks.
+    pub sort: SortSpec,
+    /// The most tracks to return, if limited.
+    pub limit: Option<i32>,
+}
+
+impl SavedQuery {
+    pub fn new(name: &str, query: &str, sort: SortSpec, limit: Option<i32>) -> SavedQuery {
+        SavedQuery {
+            name: name.to_owned(),
+            query: query.to_owned(),
+            sort,
+            limit,
+        }
+    }
+
+    /// Queries the tracks that the saved query matches,
+    /// with the macros that it may use.
+    pub fn evaluate(
+        &self,
+        macros: &BTreeMap<String, String>,
+        template: &NamingTemplate,
+        conn: &Connection,
+    ) -> Result<Vec<Track>> {
+        let bang = Bang::with_macros(&self.query, macros)?;
+        Ok(database::query_tracks(
+            bang,
+            conn,
+            template,
+            &self.sort,
+            self.limit,
+            None,
+        )?)
+    }
+}
+
+fn query_saved_queries(
+    query: &str,
+    params: &[&dyn ToSql],
+    conn: &Connection,
+) -> rusqlite::Result<Vec<SavedQuery>> {
+    let mut statement = conn.prepare(query)?;
+    let mut rows = statement.query(params)?;
+    let mut saved_queries = Vec::new();
+    while let Some(Ok(row)) = rows.next() {
+        saved_queries.push(SavedQuery {
+            name: row.get_checked(0)?,
+            query: row.get_checked(1)?,
+            // The sort was valid when it was saved, so this only falls
+            // back to the default order if a sort column was removed.
+            sort: row.get_checked::<_, String>(2)?
+                .parse()
+                .unwrap_or_default(),
+            limit: row.get_checked(3)?,
+        })
+    }
+    Ok(saved_queries)
+}
+
+const SELECT_SAVED_QUERIES: &str = "SELECT Name, Query, Sort, TrackLimit FROM saved_queries";
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        Err(Error::InvalidSavedQueryName(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Gets every saved query, in order of name.
+pub fn get_saved_queries(conn: &Connection) -> Result<Vec<SavedQuery>> {
+    Ok(query_saved_queries(
+        &format!("{} ORDER BY Name COLLATE NOCASE", SELECT_SAVED_QUERIES),
+        &[],
+        conn,
+    )?)
+}
+
+/// Gets the saved query with the given name.
+pub fn get_saved_query(name: &str, conn: &Connection) -> Result<SavedQuery> {
+    query_saved_queries(
+        &format!("{} WHERE Name = ?1", SELECT_SAVED_QUERIES),
+        &[&name],
+        conn,
+    )?.into_iter()
+        .next()
+        .ok_or(Error::SavedQueryNotFound(name.to_owned()))
+}
+
+/// Saves a query under a name that is not yet taken.
+/// The query is parsed first with the macros that it may use,
+/// so that only valid queries are saved.
+pub fn create_saved_query(
+    saved_query: &SavedQuery,
+    macros: &BTreeMap<String, String>,
+    conn: &Connection,
+) -> Result<()> {
+    validate_name(&saved_query.name)?;
+    Bang::with_macros(&saved_query.query, macros)?;
+    if get_saved_query(&saved_query.name, conn).is_ok() {
+        return Err(Error::SavedQueryExists(saved_query.name.to_owned()));
+    }
+    conn.execute(
+        "INSERT INTO saved_queries(Name, Query, Sort, TrackLimit) VALUES (?1, ?2, ?3, ?4)",
+        &[
+            &saved_query.name,
+            &saved_query.query,
+            &saved_query.sort.to_string(),
+            &saved_query.limit,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Gives a saved query a new name that is not yet taken.
+pub fn rename_saved_query(name: &str, new_name: &str, conn: &Connection) -> Result<()> {
+    validate_name(new_name)?;
+    if name != new_name && get_saved_query(new_name, conn).is_ok() {
+        return Err(Error::SavedQueryExists(new_name.to_owned()));
+    }
+    match conn.execute(
+        "UPDATE saved_queries SET Name = ?1 WHERE Name = ?2",
+        &[&new_name, &name],
+    )? {
+        0 => Err(Error::SavedQueryNotFound(name.to_owned())),
+        _ => Ok(()),
+    }
+}
+
+pub fn delete_saved_query(name: &str, conn: &Connection) -> Result<()> {
+    match conn.execute("DELETE FROM saved_queries WHERE Name = ?1", &[&name])? {
+        0 => Err(Error::SavedQueryNotFound(name.to_owned())),
+        _ => Ok(()),
+    }
+}
+
+/// Queries the tracks that the saved query with the given name matches.
+pub fn evaluate_saved_query(
+    name: &str,
+    macros: &BTreeMap<String, String>,
+    template: &NamingTemplate,
+    conn: &Connection,
+) -> Result<Vec<Track>> {
+    get_saved_query(name, conn)?.evaluate(macros, template, conn)
+}
@@ -0,0 +1,207 @@
+use error::{Error, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// The order tracks are returned in when no sort is given.
+/// Compilations by Various Artists are put after everything else.
+const DEFAULT_ORDER: &str =
+    "CASE WHEN AlbumArtists = 'Various Artists' THEN 1 END, AlbumArtists, Album, TrackNumber";
+
+/// A column of the tracks table that tracks can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortColumn {
+    FilePath,
+    Title,
+    Artist,
+    AlbumArtists,
+    Album,
+    Year,
+    TrackNumber,
+    MusicBrainzTrackId,
+    HasFrontCover,
+    FrontCoverWidth,
+    FrontCoverHeight,
+    Bitrate,
+    SampleRate,
+    Source,
+    DiscNumber,
+    DiscCount,
+    Duration,
+    FileType,
+    Updated,
+}
+
+impl SortColumn {
+    pub fn column_name(&self) -> &'static str {
+        match *self {
+            SortColumn::FilePath => "FilePath",
+            SortColumn::Title => "Title",
+            SortColumn::Artist => "Artist",
+            SortColumn::AlbumArtists => "AlbumArtists",
+            SortColumn::Album => "Album",
+            SortColumn::Year => "Year",
+            SortColumn::TrackNumber => "TrackNumber",
+            SortColumn::MusicBrainzTrackId => "MusicBrainzTrackId",
+            SortColumn::HasFrontCover => "HasFrontCover",
+            SortColumn::FrontCoverWidth => "FrontCoverWidth",
+            SortColumn::FrontCoverHeight => "FrontCoverHeight",
+            SortColumn::Bitrate => "Bitrate",
+            SortColumn::SampleRate => "SampleRate",
+            SortColumn::Source => "Source",
+            SortColumn::DiscNumber => "DiscNumber",
+            SortColumn::DiscCount => "DiscCount",
+            SortColumn::Duration => "Duration",
+            SortColumn::FileType => "FileType",
+            SortColumn::Updated => "Updated",
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        match *self {
+            SortColumn::FilePath
+            | SortColumn::Title
+            | SortColumn::Artist
+            | SortColumn::AlbumArtists
+            | SortColumn::Album
+            | SortColumn::MusicBrainzTrackId
+            | SortColumn::Source => true,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for SortColumn {
+    type Err = Error;
+
+    /// Column names are case insensitive, so the camelCase
+    /// keys of a track in seiri-neon can be used directly.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "filepath" => Ok(SortColumn::FilePath),
+            "title" => Ok(SortColumn::Title),
+            "artist" => Ok(SortColumn::Artist),
+            "albumartists" => Ok(SortColumn::AlbumArtists),
+            "album" => Ok(SortColumn::Album),
+            "year" => Ok(SortColumn::Year),
+            "tracknumber" => Ok(SortColumn::TrackNumber),
+            "musicbrainztrackid" => Ok(SortColumn::MusicBrainzTrackId),
+            "hasfrontcover" => Ok(SortColumn::HasFrontCover),
+            "frontcoverwidth" => Ok(SortColumn::FrontCoverWidth),
+            "frontcoverheight" => Ok(SortColumn::FrontCoverHeight),
+            "bitrate" => Ok(SortColumn::Bitrate),
+            "samplerate" => Ok(SortColumn::SampleRate),
+            "source" => Ok(SortColumn::Source),
+            "discnumber" => Ok(SortColumn::DiscNumber),
+            "disccount" => Ok(SortColumn::DiscCount),
+            "duration" => Ok(SortColumn::Duration),
+            "filetype" => Ok(SortColumn::FileType),
+            "updated" => Ok(SortColumn::Updated),
+            _ => Err(Error::InvalidSortKey(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub column: SortColumn,
+    pub direction: SortDirection,
+}
+
+impl SortKey {
+    pub fn new(column: SortColumn, direction: SortDirection) -> SortKey {
+        SortKey { column, direction }
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    /// Parses a column name, optionally followed by `asc` or `desc`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.split_whitespace();
+        let column = words
+            .next()
+            .ok_or(Error::InvalidSortKey(s.to_owned()))?
+            .parse()?;
+        let direction = match words.next().map(|word| word.to_lowercase()) {
+            None => SortDirection::Ascending,
+            Some(ref word) if word == "asc" => SortDirection::Ascending,
+            Some(ref word) if word == "desc" => SortDirection::Descending,
+            Some(_) => return Err(Error::InvalidSortKey(s.to_owned())),
+        };
+        if words.next().is_some() {
+            return Err(Error::InvalidSortKey(s.to_owned()));
+        }
+        Ok(SortKey::new(column, direction))
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.direction {
+            SortDirection::Ascending => write!(f, "{} asc", self.column.column_name()),
+            SortDirection::Descending => write!(f, "{} desc", self.column.column_name()),
+        }
+    }
+}
+
+/// The order to return tracks in, by each key in turn.
+/// Ties are broken by the default order, so that the tracks of an
+/// album stay together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortSpec {
+    pub keys: Vec<SortKey>,
+}
+
+impl SortSpec {
+    pub fn new(keys: Vec<SortKey>) -> SortSpec {
+        SortSpec { keys }
+    }
+
+    /// The ORDER BY clause for this sort, without the leading ORDER BY.
+    pub fn to_order_by_clause(&self) -> String {
+        let mut clause = String::new();
+        for key in &self.keys {
+            clause.push_str(key.column.column_name());
+            if key.column.is_text() {
+                clause.push_str(" COLLATE NOCASE");
+            }
+            match key.direction {
+                SortDirection::Ascending => clause.push_str(" ASC, "),
+                SortDirection::Descending => clause.push_str(" DESC, "),
+            }
+        }
+        clause.push_str(DEFAULT_ORDER);
+        clause
+    }
+}
+
+impl FromStr for SortSpec {
+    type Err = Error;
+
+    /// Parses comma separated sort keys, such as `year desc, album`.
+    /// An empty string is the default order.
+    fn from_str(s: &str) -> Result<Self> {
+        let keys = s.split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| key.parse())
+            .collect::<Result<Vec<SortKey>>>()?;
+        Ok(SortSpec::new(keys))
+    }
+}
+
+impl fmt::Display for SortSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys = self.keys
+            .iter()
+            .map(|key| key.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{}", keys.join(", "))
+    }
+}
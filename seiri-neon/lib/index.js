@@ -2,5 +2,15 @@ const addon = require('../native');
 
 module.exports = {
     queryTracks: addon.queryTracks,
-    refreshTracks: addon.refreshTracks
+    queryAlbums: addon.queryAlbums,
+    checkQuery: addon.checkQuery,
+    completeQuery: addon.completeQuery,
+    explainQuery: addon.explainQuery,
+    lintLibrary: addon.lintLibrary,
+    refreshTracks: addon.refreshTracks,
+    rescanLibrary: addon.rescanLibrary,
+    rehomeLibrary: addon.rehomeLibrary,
+    getOperations: addon.getOperations,
+    rollbackOperation: addon.rollbackOperation,
+    rollbackBatch: addon.rollbackBatch
 };
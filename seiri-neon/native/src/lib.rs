@@ -3,13 +3,23 @@ extern crate neon;
 extern crate seiri;
 extern crate num_traits;
 
-use neon::js::{JsArray, JsBoolean, JsInteger, JsNull, JsObject, JsString, JsUndefined, Object};
+use neon::js::{JsArray, JsBoolean, JsInteger, JsNull, JsNumber, JsObject, JsString, JsUndefined, JsValue,
+               Object, Value};
+use neon::js::error::{JsError, Kind};
+use neon::scope::Scope;
 use neon::vm::Throw;
 use neon::vm::{Call, JsResult};
 use num_traits::cast::ToPrimitive;
+use seiri::albums;
 use seiri::config::get_config;
 use seiri::database;
+use seiri::fingerprint;
+use seiri::journal;
+use seiri::journal::{Journal, OperationReason};
+use seiri::library;
+use seiri::lint;
 use seiri::paths;
+use seiri::sort::SortSpec;
 use seiri::Bang;
 use seiri::Track;
 use seiri::TrackFileType;
@@ -21,6 +31,7 @@ fn refresh_tracks(call: Call) -> JsResult<JsUndefined> {
     let config = get_config();
     let conn = database::get_database_connection();
     let library_path = Path::new(&config.music_folder);
+    let journal = Journal::new(&conn, OperationReason::Reconsider);
     let args = &call.arguments.require(scope, 0)?.check::<JsArray>()?;
     let mut track_filenames: Vec<String> = Vec::new();
     for i in 0..args.len() {
@@ -28,14 +39,18 @@ fn refresh_tracks(call: Call) -> JsResult<JsUndefined> {
         track_filenames.push(result.to_owned());
     }
     for file in track_filenames {
-        let tracks = database::query_tracks(Bang::FilePath(file.clone()), &conn, None, None);
+        let tracks = database::query_tracks(Bang::FilePath(file.clone()), &conn, &config.naming_template, &SortSpec::default(), None, None);
         if let Ok(tracks) = tracks {
             if let Some(track) = tracks.into_iter().next() {
-                match paths::reconsider_track(&track, &library_path) {
+                match paths::reconsider_track(&track, &library_path, &config.naming_template, &journal) {
                     Ok(Some(new_track)) => {
                         println!("RECONSIDERED OK {:?}", new_track);
+                        let content_hash = fingerprint::ensure_content_hash(
+                            &new_track,
+                            database::get_content_hash(&track.file_path, &conn),
+                        );
                         database::remove_track(&track, &conn);
-                        database::add_track(&new_track, &conn);
+                        database::add_track(&new_track, content_hash.as_ref(), &conn);
                     }
                     Ok(None) => {
                         println!("RECONSIDERED NOT FOUND {:?}", track);
@@ -49,6 +64,169 @@ fn refresh_tracks(call: Call) -> JsResult<JsUndefined> {
     Ok(JsUndefined::new())
 }
 
+#[allow(non_snake_case)]
+fn rescan_library(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let config = get_config();
+    let conn = database::get_database_connection();
+    let library_path = Path::new(&config.music_folder);
+    let summary = library::rescan_library(&library_path, &config.naming_template, &conn).unwrap();
+    let ret = JsObject::new(scope);
+    ret.set("added", JsInteger::new(scope, summary.added as i32))?;
+    ret.set("removed", JsInteger::new(scope, summary.removed as i32))?;
+    ret.set("moved", JsInteger::new(scope, summary.moved as i32))?;
+    ret.set("updated", JsInteger::new(scope, summary.updated as i32))?;
+    ret.set("unchanged", JsInteger::new(scope, summary.unchanged as i32))?;
+    ret.set("hashed", JsInteger::new(scope, summary.hashed as i32))?;
+    ret.set("skipped", JsInteger::new(scope, summary.skipped as i32))?;
+    let jsErrors = JsArray::new(scope, summary.errors.len() as u32);
+    for (i, err) in summary.errors.into_iter().enumerate() {
+        jsErrors.set(i as u32, JsString::new(scope, &err.to_string()).unwrap())?;
+    }
+    ret.set("errors", jsErrors)?;
+    match summary.batch {
+        Some(batch) => ret.set("batch", JsInteger::new(scope, batch as i32)),
+        None => ret.set("batch", JsNull::new()),
+    }?;
+    Ok(ret)
+}
+
+#[allow(non_snake_case)]
+fn rehome_library(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let config = get_config();
+    let conn = database::get_database_connection();
+    let library_path = Path::new(&config.music_folder);
+    let summary = library::rehome_library(&library_path, &config.naming_template, &conn).unwrap();
+    let ret = JsObject::new(scope);
+    ret.set("moved", JsInteger::new(scope, summary.moved as i32))?;
+    ret.set("unchanged", JsInteger::new(scope, summary.unchanged as i32))?;
+    ret.set("missing", JsInteger::new(scope, summary.missing as i32))?;
+    let jsErrors = JsArray::new(scope, summary.errors.len() as u32);
+    for (i, err) in summary.errors.into_iter().enumerate() {
+        jsErrors.set(i as u32, JsString::new(scope, &err.to_string()).unwrap())?;
+    }
+    ret.set("errors", jsErrors)?;
+    match summary.batch {
+        Some(batch) => ret.set("batch", JsInteger::new(scope, batch as i32)),
+        None => ret.set("batch", JsNull::new()),
+    }?;
+    Ok(ret)
+}
+
+#[allow(non_snake_case)]
+fn get_operations(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let limit = call.arguments
+        .require(scope, 0)?
+        .check::<JsInteger>()?
+        .value();
+    let conn = database::get_database_connection();
+    let operations = journal::get_operations(&conn, Some(limit as i32)).unwrap();
+    let ret = JsObject::new(scope);
+    let jsOperations = JsArray::new(scope, operations.len() as u32);
+    for (i, operation) in operations.into_iter().enumerate() {
+        let jsOperation = JsObject::new(scope);
+        jsOperation.set("id", JsInteger::new(scope, operation.id as i32))?;
+        jsOperation.set("batch", JsInteger::new(scope, operation.batch as i32))?;
+        jsOperation.set("kind", JsString::new(scope, operation.kind.as_str()).unwrap())?;
+        jsOperation.set("oldPath", JsString::new(scope, &operation.old_path.to_string_lossy()).unwrap())?;
+        match operation.new_path {
+            Some(ref new_path) => jsOperation.set(
+                "newPath",
+                JsString::new(scope, &new_path.to_string_lossy()).unwrap(),
+            ),
+            None => jsOperation.set("newPath", JsNull::new()),
+        }?;
+        jsOperation.set("reason", JsString::new(scope, operation.reason.as_str()).unwrap())?;
+        jsOperation.set("timestamp", JsString::new(scope, &operation.timestamp).unwrap())?;
+        jsOperation.set("rolledBack", JsBoolean::new(scope, operation.rolled_back))?;
+        jsOperations.set(i as u32, jsOperation)?;
+    }
+    ret.set("operations", jsOperations)?;
+    Ok(ret)
+}
+
+#[allow(non_snake_case)]
+fn rollback_operation(call: Call) -> JsResult<JsUndefined> {
+    let scope = call.scope;
+    let id = call.arguments
+        .require(scope, 0)?
+        .check::<JsInteger>()?
+        .value();
+    let config = get_config();
+    let conn = database::get_database_connection();
+    let library_path = Path::new(&config.music_folder);
+    match journal::rollback_operation(id, &library_path, &conn) {
+        Ok(_) => Ok(JsUndefined::new()),
+        Err(err) => JsError::throw(Kind::Error, &err.to_string()),
+    }
+}
+
+#[allow(non_snake_case)]
+fn rollback_batch(call: Call) -> JsResult<JsUndefined> {
+    let scope = call.scope;
+    let batch = call.arguments
+        .require(scope, 0)?
+        .check::<JsInteger>()?
+        .value();
+    let config = get_config();
+    let conn = database::get_database_connection();
+    let library_path = Path::new(&config.music_folder);
+    match journal::rollback_batch(batch, &library_path, &conn) {
+        Ok(_) => Ok(JsUndefined::new()),
+        Err(err) => JsError::throw(Kind::Error, &err.to_string()),
+    }
+}
+
+#[allow(non_snake_case)]
+fn track_to_js<'a, T: Scope<'a>>(scope: &mut T, track: Track) -> JsResult<'a, JsObject> {
+    let jsTrack = JsObject::new(scope);
+    jsTrack.set("filePath", JsString::new(scope, &track.file_path.into_os_string().into_string().unwrap()).unwrap())?;
+    jsTrack.set("title", JsString::new(scope, &track.title).unwrap())?;
+    jsTrack.set("artist", JsString::new(scope, &track.artist).unwrap())?;
+
+    let jsAlbumArtists = JsArray::new(scope, track.album_artists.len() as u32);
+
+    for (i, artist) in track.album_artists.into_iter().enumerate() {
+        let jsArtistString = JsString::new(scope, &artist).unwrap();
+        jsAlbumArtists.set(i as u32, jsArtistString)?;
+    }
+    jsTrack.set("albumArtists", jsAlbumArtists)?;
+    jsTrack.set("album", JsString::new(scope, &track.album).unwrap())?;
+    jsTrack.set("trackNumber", JsInteger::new(scope, track.track_number))?;
+
+    match &track.musicbrainz_track_id {
+        Some(track_id) => jsTrack.set(
+            "musicbrainzTrackId",
+            JsString::new(scope, track_id).unwrap(),
+        ),
+        None => jsTrack.set("musicbrainzTrackId", JsNull::new()),
+    }?;
+
+    jsTrack.set(
+        "hasFrontCover",
+        JsBoolean::new(scope, track.has_front_cover),
+    )?;
+    jsTrack.set(
+        "frontCoverHeight",
+        JsInteger::new(scope, track.front_cover_height),
+    )?;
+    jsTrack.set(
+        "frontCoverWidth",
+        JsInteger::new(scope, track.front_cover_width),
+    )?;
+    jsTrack.set("bitrate", JsInteger::new(scope, track.bitrate))?;
+    jsTrack.set("sampleRate", JsInteger::new(scope, track.sample_rate))?;
+    jsTrack.set("source", JsString::new(scope, &track.source).unwrap())?;
+    jsTrack.set("discNumber", JsInteger::new(scope, track.disc_number))?;
+    jsTrack.set("discCount", JsInteger::new(scope, track.disc_count))?;
+    jsTrack.set("duration", JsInteger::new(scope, track.duration))?;
+    jsTrack.set("fileType", JsInteger::new(scope, track.file_type.to_i32().unwrap()))?;
+    jsTrack.set("updated", JsString::new(scope, &track.updated).unwrap())?;
+    Ok(jsTrack)
+}
+
 #[allow(non_snake_case)]
 fn query_tracks(call: Call) -> JsResult<JsObject> {
     let scope = call.scope;
@@ -57,60 +235,247 @@ fn query_tracks(call: Call) -> JsResult<JsObject> {
         .require(scope, 0)?
         .check::<JsString>()?
         .value();
-    let bang = Bang::new(query).unwrap();
+    let config = get_config();
+    let bang = match Bang::with_macros(query, &config.macros) {
+        Ok(bang) => bang,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    // The sort is optional, and tracks are returned in the default order without it.
+    let sort = match call.arguments.get(scope, 1).and_then(|arg| arg.downcast::<JsString>()) {
+        Some(sort) => match sort.value().parse::<SortSpec>() {
+            Ok(sort) => sort,
+            Err(err) => return JsError::throw(Kind::TypeError, &err.to_string()),
+        },
+        None => SortSpec::default(),
+    };
     let conn = database::get_database_connection();
-    let results: Vec<Track> = database::query_tracks(bang, &conn, None, None).unwrap();
+    let results: Vec<Track> = database::query_tracks(bang, &conn, &config.naming_template, &sort, None, None).unwrap();
     let jsTracks = JsArray::new(scope, results.len() as u32);
     for (i, track) in results.into_iter().enumerate() {
-        let mut jsTrack = JsObject::new(scope);
-        jsTrack.set("filePath", JsString::new(scope, &track.file_path.into_os_string().into_string().unwrap()).unwrap())?;
-        jsTrack.set("title", JsString::new(scope, &track.title).unwrap())?;
-        jsTrack.set("artist", JsString::new(scope, &track.artist).unwrap())?;
+        let jsTrack = track_to_js(scope, track)?;
+        jsTracks.set(i as u32, jsTrack)?;
+    }
+    ret.set("tracks", jsTracks)?;
+    Ok(ret)
+}
 
-        let jsAlbumArtists = JsArray::new(scope, track.album_artists.len() as u32);
+#[allow(non_snake_case)]
+fn query_albums(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let ret = JsObject::new(scope);
+    let query = &call.arguments
+        .require(scope, 0)?
+        .check::<JsString>()?
+        .value();
+    let config = get_config();
+    let bang = match Bang::with_macros(query, &config.macros) {
+        Ok(bang) => bang,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    let sort = match call.arguments.get(scope, 1).and_then(|arg| arg.downcast::<JsString>()) {
+        Some(sort) => match sort.value().parse::<SortSpec>() {
+            Ok(sort) => sort,
+            Err(err) => return JsError::throw(Kind::TypeError, &err.to_string()),
+        },
+        None => SortSpec::default(),
+    };
+    let conn = database::get_database_connection();
+    let results = match albums::query_albums(bang, &conn, &config.naming_template, &sort, None, None) {
+        Ok(results) => results,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    let jsAlbums = JsArray::new(scope, results.len() as u32);
+    for (i, album) in results.into_iter().enumerate() {
+        let jsAlbum = JsObject::new(scope);
+        let jsAlbumArtists = JsArray::new(scope, album.album_artists.len() as u32);
+        for (i, artist) in album.album_artists.iter().enumerate() {
+            jsAlbumArtists.set(i as u32, JsString::new(scope, artist).unwrap())?;
+        }
+        jsAlbum.set("albumArtists", jsAlbumArtists)?;
+        jsAlbum.set("album", JsString::new(scope, &album.album).unwrap())?;
+        jsAlbum.set("year", JsInteger::new(scope, album.year))?;
+        jsAlbum.set("trackCount", JsInteger::new(scope, album.track_count))?;
+        // The duration of an album can be longer than an i32 of milliseconds.
+        jsAlbum.set("duration", JsNumber::new(scope, album.duration as f64))?;
+        let jsFileTypes = JsArray::new(scope, album.file_types.len() as u32);
+        for (i, file_type) in album.file_types.iter().enumerate() {
+            jsFileTypes.set(i as u32, JsInteger::new(scope, file_type.to_i32().unwrap()))?;
+        }
+        jsAlbum.set("fileTypes", jsFileTypes)?;
+        jsAlbum.set("hasFrontCover", JsBoolean::new(scope, album.has_front_cover))?;
+        let problems = album.problems();
+        let jsProblems = JsArray::new(scope, problems.len() as u32);
+        for (i, problem) in problems.iter().enumerate() {
+            jsProblems.set(i as u32, JsString::new(scope, &problem.to_string()).unwrap())?;
+        }
+        jsAlbum.set("problems", jsProblems)?;
+        let jsTracks = JsArray::new(scope, album.tracks.len() as u32);
+        for (i, track) in album.tracks.into_iter().enumerate() {
+            let jsTrack = track_to_js(scope, track)?;
+            jsTracks.set(i as u32, jsTrack)?;
+        }
+        jsAlbum.set("tracks", jsTracks)?;
+        jsAlbums.set(i as u32, jsAlbum)?;
+    }
+    ret.set("albums", jsAlbums)?;
+    Ok(ret)
+}
 
-        for (i, artist) in track.album_artists.into_iter().enumerate() {
-            let jsArtistString = JsString::new(scope, &artist).unwrap();
-            jsAlbumArtists.set(i as u32, jsArtistString)?;
+#[allow(non_snake_case)]
+fn lint_library(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let config = get_config();
+    let conn = database::get_database_connection();
+    let issues = match lint::lint_library(&conn, &config.naming_template, Path::new(&config.music_folder)) {
+        Ok(issues) => issues,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    let ret = JsObject::new(scope);
+    let jsIssues = JsArray::new(scope, issues.len() as u32);
+    for (i, issue) in issues.into_iter().enumerate() {
+        let jsIssue = JsObject::new(scope);
+        jsIssue.set("kind", JsString::new(scope, issue.kind.name()).unwrap())?;
+        jsIssue.set("description", JsString::new(scope, &issue.description).unwrap())?;
+        let jsFolders = JsArray::new(scope, issue.folders.len() as u32);
+        for (i, folder) in issue.folders.iter().enumerate() {
+            jsFolders.set(i as u32, JsString::new(scope, &folder.to_string_lossy()).unwrap())?;
         }
-        jsTrack.set("albumArtists", jsAlbumArtists)?;
-        jsTrack.set("album", JsString::new(scope, &track.album).unwrap())?;
-        jsTrack.set("trackNumber", JsInteger::new(scope, track.track_number))?;
-
-        match &track.musicbrainz_track_id {
-            Some(track_id) => jsTrack.set(
-                "musicbrainzTrackId",
-                JsString::new(scope, track_id).unwrap(),
-            ),
-            None => jsTrack.set("musicbrainzTrackId", JsNull::new()),
-        }?;
+        jsIssue.set("folders", jsFolders)?;
+        let jsFilePaths = JsArray::new(scope, issue.file_paths.len() as u32);
+        for (i, file_path) in issue.file_paths.iter().enumerate() {
+            jsFilePaths.set(i as u32, JsString::new(scope, &file_path.to_string_lossy()).unwrap())?;
+        }
+        jsIssue.set("filePaths", jsFilePaths)?;
+        jsIssues.set(i as u32, jsIssue)?;
+    }
+    ret.set("issues", jsIssues)?;
+    Ok(ret)
+}
 
-        jsTrack.set(
-            "hasFrontCover",
-            JsBoolean::new(scope, track.has_front_cover),
-        )?;
-        jsTrack.set(
-            "frontCoverHeight",
-            JsInteger::new(scope, track.front_cover_height),
-        )?;
-        jsTrack.set(
-            "frontCoverWidth",
-            JsInteger::new(scope, track.front_cover_width),
-        )?;
-        jsTrack.set("bitrate", JsInteger::new(scope, track.bitrate))?;
-        jsTrack.set("sampleRate", JsInteger::new(scope, track.sample_rate))?;
-        jsTrack.set("source", JsString::new(scope, &track.source).unwrap())?;
-        jsTrack.set("discNumber", JsInteger::new(scope, track.disc_number))?;
-        jsTrack.set("duration", JsInteger::new(scope, track.duration))?;
-        jsTrack.set("fileType", JsInteger::new(scope, track.file_type.to_i32().unwrap()))?;
-        jsTrack.set("updated", JsString::new(scope, &track.updated).unwrap())?;
-        jsTracks.set(i as u32, jsTrack)?;
+/// The offset into the query in UTF-16 code units, which is how
+/// JavaScript indexes strings.
+fn to_utf16_offset(query: &str, offset: usize) -> i32 {
+    query[..offset].encode_utf16().count() as i32
+}
+
+/// The byte offset into the query of an offset in UTF-16 code units.
+fn from_utf16_offset(query: &str, offset: i32) -> usize {
+    let mut units = 0;
+    for (index, c) in query.char_indices() {
+        if units >= offset as usize {
+            return index;
+        }
+        units += c.len_utf16();
     }
-    ret.set("tracks", jsTracks)?;
+    query.len()
+}
+
+#[allow(non_snake_case)]
+fn complete_query(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let query = &call.arguments
+        .require(scope, 0)?
+        .check::<JsString>()?
+        .value();
+    let cursor = call.arguments
+        .require(scope, 1)?
+        .check::<JsInteger>()?
+        .value();
+    let conn = database::get_database_connection();
+    let completions = match seiri::complete_query(query, from_utf16_offset(query, cursor as i32), &conn) {
+        Ok(completions) => completions,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    let ret = JsObject::new(scope);
+    let jsCompletions = JsArray::new(scope, completions.len() as u32);
+    for (i, completion) in completions.into_iter().enumerate() {
+        let jsCompletion = JsObject::new(scope);
+        jsCompletion.set("text", JsString::new(scope, &completion.text).unwrap())?;
+        jsCompletion.set("description", JsString::new(scope, &completion.description).unwrap())?;
+        jsCompletion.set("start", JsInteger::new(scope, to_utf16_offset(query, completion.span.start)))?;
+        jsCompletion.set("end", JsInteger::new(scope, to_utf16_offset(query, completion.span.end)))?;
+        jsCompletions.set(i as u32, jsCompletion)?;
+    }
+    ret.set("completions", jsCompletions)?;
     Ok(ret)
 }
 
+#[allow(non_snake_case)]
+fn explain_query(call: Call) -> JsResult<JsObject> {
+    let scope = call.scope;
+    let query = &call.arguments
+        .require(scope, 0)?
+        .check::<JsString>()?
+        .value();
+    let bang = match Bang::with_macros(query, &get_config().macros) {
+        Ok(bang) => bang,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    let sort = match call.arguments.get(scope, 1).and_then(|arg| arg.downcast::<JsString>()) {
+        Some(sort) => match sort.value().parse::<SortSpec>() {
+            Ok(sort) => sort,
+            Err(err) => return JsError::throw(Kind::TypeError, &err.to_string()),
+        },
+        None => SortSpec::default(),
+    };
+    let conn = database::get_database_connection();
+    let explanation = match database::explain(bang, &conn, &sort, None, None) {
+        Ok(explanation) => explanation,
+        Err(err) => return JsError::throw(Kind::Error, &err.to_string()),
+    };
+    let ret = JsObject::new(scope);
+    ret.set("query", JsString::new(scope, &explanation.query).unwrap())?;
+    let jsParams = JsArray::new(scope, explanation.params.len() as u32);
+    for (i, (name, value)) in explanation.params.into_iter().enumerate() {
+        let jsParam = JsObject::new(scope);
+        jsParam.set("name", JsString::new(scope, &name).unwrap())?;
+        jsParam.set("value", JsString::new(scope, &value).unwrap())?;
+        jsParams.set(i as u32, jsParam)?;
+    }
+    ret.set("params", jsParams)?;
+    let jsPlan = JsArray::new(scope, explanation.plan.len() as u32);
+    for (i, detail) in explanation.plan.into_iter().enumerate() {
+        jsPlan.set(i as u32, JsString::new(scope, &detail).unwrap())?;
+    }
+    ret.set("plan", jsPlan)?;
+    Ok(ret)
+}
+
+#[allow(non_snake_case)]
+fn check_query(call: Call) -> JsResult<JsValue> {
+    let scope = call.scope;
+    let query = &call.arguments
+        .require(scope, 0)?
+        .check::<JsString>()?
+        .value();
+    match Bang::with_macros(query, &get_config().macros) {
+        Ok(_) => Ok(JsNull::new().upcast()),
+        Err(err) => {
+            let span = err.span().unwrap_or_default();
+            let ret = JsObject::new(scope);
+            ret.set("message", JsString::new(scope, &err.to_string()).unwrap())?;
+            ret.set("start", JsInteger::new(scope, to_utf16_offset(query, span.start)))?;
+            ret.set("end", JsInteger::new(scope, to_utf16_offset(query, span.end)))?;
+            match err.expected() {
+                Some(expected) => ret.set("expected", JsString::new(scope, expected).unwrap()),
+                None => ret.set("expected", JsNull::new()),
+            }?;
+            Ok(ret.upcast())
+        }
+    }
+}
+
 register_module!(m, {
     m.export("queryTracks", query_tracks);
-    m.export("refreshTracks", refresh_tracks)
+    m.export("queryAlbums", query_albums);
+    m.export("checkQuery", check_query);
+    m.export("completeQuery", complete_query);
+    m.export("explainQuery", explain_query);
+    m.export("lintLibrary", lint_library);
+    m.export("refreshTracks", refresh_tracks);
+    m.export("rescanLibrary", rescan_library);
+    m.export("rehomeLibrary", rehome_library);
+    m.export("getOperations", get_operations);
+    m.export("rollbackOperation", rollback_operation);
+    m.export("rollbackBatch", rollback_batch)
 });
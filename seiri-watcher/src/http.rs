@@ -0,0 +1,496 @@
+use num_traits::cast::ToPrimitive;
+use percent_encoding::percent_decode;
+use seiri::albums::{self, Album};
+use seiri::config::get_config;
+use seiri::database;
+use seiri::database::{Connection, ConnectionPool};
+use seiri::journal::{Journal, OperationReason};
+use seiri::library;
+use seiri::lint;
+use seiri::naming::NamingTemplate;
+use seiri::saved_queries;
+use seiri::sort::SortSpec;
+use seiri::{Bang, Error, Track};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+
+/// The port that the API is served on.
+pub const PORT: u16 = 9235;
+
+/// The largest request body that will be read.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The longest request line or header line that will be read.
+const MAX_LINE_SIZE: usize = 8 * 1024;
+
+/// The largest size of the headers of a request together.
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+
+/// The hosts that requests may be sent to. Any other host means the
+/// request came through a name that was made to point at this machine,
+/// such as by DNS rebinding. `localhost` may be bound to either loopback
+/// address.
+const ALLOWED_HOSTS: &[&str] = &["localhost:9235", "127.0.0.1:9235", "[::1]:9235"];
+
+struct Request {
+    method: String,
+    path: String,
+    /// The headers of the request, by their lowercase names.
+    headers: HashMap<String, String>,
+    query: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+
+    /// A query that could not be parsed, with the span of the query
+    /// the error is about, and what was expected there.
+    fn query_error(err: &Error) -> Response {
+        Response {
+            status: 400,
+            body: json!({
+                "error": err.to_string(),
+                "span": err.span(),
+                "expected": err.expected(),
+            }),
+        }
+    }
+}
+
+fn decode(component: &str) -> String {
+    percent_decode(component.replace('+', " ").as_bytes())
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            let key = decode(pair.next().unwrap_or(""));
+            let value = decode(pair.next().unwrap_or(""));
+            (key, value)
+        })
+        .collect()
+}
+
+/// Reads a line of the request line or headers, or None if it is longer
+/// than `MAX_LINE_SIZE`, without reading the rest of it into memory.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_SIZE as u64).read_line(&mut line)?;
+    if line.len() == MAX_LINE_SIZE && !line.ends_with('\n') {
+        Ok(None)
+    } else {
+        Ok(Some(line))
+    }
+}
+
+/// Reads a request, or the error response for a request that is too large.
+fn read_request(stream: &TcpStream) -> io::Result<Result<Request, Response>> {
+    let mut reader = BufReader::new(stream);
+    let request_line = match read_line(&mut reader)? {
+        Some(request_line) => request_line,
+        None => return Ok(Err(Response::error(400, "Request line too long"))),
+    };
+    let mut request_line = request_line.split_whitespace();
+    let method = request_line.next().unwrap_or("").to_owned();
+    let target = request_line.next().unwrap_or("").to_owned();
+
+    let mut headers = HashMap::new();
+    let mut headers_size = 0;
+    loop {
+        let header = match read_line(&mut reader)? {
+            Some(header) => header,
+            None => return Ok(Err(Response::error(431, "Request header too large"))),
+        };
+        if header.trim().is_empty() {
+            break;
+        }
+        headers_size += header.len();
+        if headers_size > MAX_HEADERS_SIZE {
+            return Ok(Err(Response::error(431, "Request headers too large")));
+        }
+        let mut header = header.splitn(2, ':');
+        let name = header.next().unwrap_or("").trim().to_lowercase();
+        let value = header.next().unwrap_or("").trim().to_owned();
+        headers.insert(name, value);
+    }
+    let content_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        return Ok(Err(Response::error(413, "Request body too large")));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let mut target = target.splitn(2, '?');
+    let path = target.next().unwrap_or("").to_owned();
+    let query = parse_query(target.next().unwrap_or(""));
+    Ok(Ok(Request {
+        method,
+        path,
+        headers,
+        query,
+        body,
+    }))
+}
+
+fn track_to_json(track: &Track) -> Value {
+    json!({
+        "filePath": track.file_path.to_string_lossy(),
+        "title": track.title,
+        "artist": track.artist,
+        "albumArtists": track.album_artists,
+        "album": track.album,
+        "year": track.year,
+        "trackNumber": track.track_number,
+        "musicbrainzTrackId": track.musicbrainz_track_id,
+        "hasFrontCover": track.has_front_cover,
+        "frontCoverHeight": track.front_cover_height,
+        "frontCoverWidth": track.front_cover_width,
+        "bitrate": track.bitrate,
+        "sampleRate": track.sample_rate,
+        "source": track.source,
+        "discNumber": track.disc_number,
+        "discCount": track.disc_count,
+        "duration": track.duration,
+        "fileType": track.file_type.to_i32(),
+        "updated": track.updated,
+    })
+}
+
+fn album_to_json(album: &Album) -> Value {
+    json!({
+        "albumArtists": album.album_artists,
+        "album": album.album,
+        "year": album.year,
+        "trackCount": album.track_count,
+        "duration": album.duration,
+        "fileTypes": album.file_types.iter().map(|file_type| file_type.to_i32()).collect::<Vec<_>>(),
+        "hasFrontCover": album.has_front_cover,
+        "problems": album.problems().iter().map(|problem| problem.to_string()).collect::<Vec<String>>(),
+        "tracks": album.tracks.iter().map(track_to_json).collect::<Vec<Value>>(),
+    })
+}
+
+fn parse_number(request: &Request, name: &str) -> Result<Option<i32>, Response> {
+    match request.query.get(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Response::error(400, &format!("{} must be a number", name))),
+        None => Ok(None),
+    }
+}
+
+/// The bang, sort, limit and offset of a query, with the naming
+/// template that case collisions are found in.
+struct QueryParams {
+    bang: Bang,
+    template: NamingTemplate,
+    sort: SortSpec,
+    limit: Option<i32>,
+    offset: Option<i32>,
+}
+
+/// Reads the `q`, `sort`, `limit` and `offset` parameters of a request.
+fn parse_query_params(request: &Request) -> Result<QueryParams, Response> {
+    let limit = parse_number(request, "limit")?;
+    let offset = parse_number(request, "offset")?;
+    let query = request.query.get("q").map(|q| q.as_str()).unwrap_or("");
+    let config = get_config();
+    let bang = Bang::with_macros(query, &config.macros)
+        .map_err(|err| Response::query_error(&err))?;
+    let sort = request
+        .query
+        .get("sort")
+        .map(|sort| sort.parse())
+        .unwrap_or(Ok(SortSpec::default()))
+        .map_err(|err: Error| Response::error(400, &err.to_string()))?;
+    Ok(QueryParams {
+        bang,
+        template: config.naming_template,
+        sort,
+        limit,
+        offset,
+    })
+}
+
+/// GET /query?q=<bang>&sort=<sort>&limit=<limit>&offset=<offset>
+fn query(request: &Request, conn: &Connection) -> Response {
+    let params = match parse_query_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+    match database::query_tracks(params.bang, conn, &params.template, &params.sort, params.limit, params.offset) {
+        Ok(tracks) => Response::ok(json!({
+            "tracks": tracks.iter().map(track_to_json).collect::<Vec<Value>>()
+        })),
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+/// GET /albums?q=<bang>&sort=<sort>&limit=<limit>&offset=<offset>
+fn query_albums(request: &Request, conn: &Connection) -> Response {
+    let params = match parse_query_params(request) {
+        Ok(params) => params,
+        Err(response) => return response,
+    };
+    match albums::query_albums(params.bang, conn, &params.template, &params.sort, params.limit, params.offset) {
+        Ok(albums) => Response::ok(json!({
+            "albums": albums.iter().map(album_to_json).collect::<Vec<Value>>()
+        })),
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+/// GET /lint
+fn lint(conn: &Connection) -> Response {
+    let config = get_config();
+    match lint::lint_library(conn, &config.naming_template, Path::new(&config.music_folder)) {
+        Ok(issues) => Response::ok(json!({
+            "issues": issues
+                .iter()
+                .map(|issue| json!({
+                    "kind": issue.kind.name(),
+                    "description": issue.description,
+                    "folders": issue.folders.iter().map(|folder| folder.to_string_lossy()).collect::<Vec<_>>(),
+                    "filePaths": issue.file_paths.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<Value>>()
+        })),
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+/// POST /refresh with a body of `{"paths": [...]}`
+fn refresh(request: &Request, conn: &Connection) -> Response {
+    let paths = match ::serde_json::from_slice::<Value>(&request.body) {
+        Ok(body) => match body["paths"].as_array() {
+            Some(paths) => paths
+                .iter()
+                .filter_map(|path| path.as_str().map(|path| path.to_owned()))
+                .collect::<Vec<String>>(),
+            None => return Response::error(400, "Expected an array of paths"),
+        },
+        Err(err) => return Response::error(400, &err.to_string()),
+    };
+
+    let config = get_config();
+    let library_path = Path::new(&config.music_folder);
+    let journal = Journal::new(conn, OperationReason::Reconsider);
+    let mut tracks = Vec::new();
+    let mut removed = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        match library::refresh_track(
+            Path::new(&path),
+            library_path,
+            &config.naming_template,
+            &journal,
+            conn,
+        ) {
+            Ok(Some(track)) => tracks.push(track_to_json(&track)),
+            Ok(None) => removed.push(path),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+    Response::ok(json!({
+        "tracks": tracks,
+        "removed": removed,
+        "errors": errors,
+        "batch": journal.batch(),
+    }))
+}
+
+/// GET /saved-queries
+fn list_saved_queries(conn: &Connection) -> Response {
+    match saved_queries::get_saved_queries(conn) {
+        Ok(saved_queries) => Response::ok(json!({
+            "savedQueries": saved_queries
+                .iter()
+                .map(|saved_query| {
+                    json!({
+                        "name": saved_query.name,
+                        "query": saved_query.query,
+                        "sort": saved_query.sort.to_string(),
+                        "limit": saved_query.limit,
+                    })
+                })
+                .collect::<Vec<Value>>()
+        })),
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+/// GET /saved-queries/tracks?name=<name>
+fn saved_query_tracks(request: &Request, conn: &Connection) -> Response {
+    let name = match request.query.get("name") {
+        Some(name) => name,
+        None => return Response::error(400, "Expected the name of a saved query"),
+    };
+    let config = get_config();
+    match saved_queries::evaluate_saved_query(name, &config.macros, &config.naming_template, conn) {
+        Ok(tracks) => Response::ok(json!({
+            "tracks": tracks.iter().map(track_to_json).collect::<Vec<Value>>()
+        })),
+        Err(err @ Error::SavedQueryNotFound(_)) => Response::error(404, &err.to_string()),
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+/// GET /stats
+fn stats(conn: &Connection) -> Response {
+    match database::get_library_stats(conn) {
+        Ok(stats) => {
+            let file_types = stats
+                .file_types
+                .iter()
+                .map(|&(ref file_type, count)| {
+                    json!({ "fileType": file_type.to_i32(), "count": count })
+                })
+                .collect::<Vec<Value>>();
+            Response::ok(json!({
+                "tracks": stats.tracks,
+                "albums": stats.albums,
+                "albumArtists": stats.album_artists,
+                "duration": stats.duration,
+                "fileTypes": file_types,
+            }))
+        }
+        Err(err) => Response::error(500, &err.to_string()),
+    }
+}
+
+/// Checks that the request came from a local client rather than a web page.
+/// Browsers always send an origin with requests made by pages to other
+/// sites, and only send a JSON content type after asking the API whether
+/// they may, which it never answers.
+fn check_request(request: &Request) -> Option<Response> {
+    if request.header("origin").is_some() {
+        return Some(Response::error(403, "Requests from web pages are not allowed"));
+    }
+    match request.header("host") {
+        Some(host) if ALLOWED_HOSTS.contains(&host.to_lowercase().as_str()) => (),
+        _ => return Some(Response::error(403, "Requests must be sent to localhost")),
+    }
+    let is_json = request
+        .header("content-type")
+        .map(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case("application/json")
+        })
+        .unwrap_or(false);
+    if request.method == "POST" && !is_json {
+        return Some(Response::error(415, "Content-Type must be application/json"));
+    }
+    None
+}
+
+fn handle(request: &Request, pool: &ConnectionPool) -> Response {
+    if let Some(response) = check_request(request) {
+        return response;
+    }
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(err) => return Response::error(500, &err.to_string()),
+    };
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/query") => query(request, &conn),
+        ("GET", "/albums") => query_albums(request, &conn),
+        ("POST", "/refresh") => refresh(request, &conn),
+        ("GET", "/stats") => stats(&conn),
+        ("GET", "/lint") => lint(&conn),
+        ("GET", "/saved-queries") => list_saved_queries(&conn),
+        ("GET", "/saved-queries/tracks") => saved_query_tracks(request, &conn),
+        (_, "/query")
+        | (_, "/albums")
+        | (_, "/refresh")
+        | (_, "/stats")
+        | (_, "/lint")
+        | (_, "/saved-queries")
+        | (_, "/saved-queries/tracks") => Response::error(405, "Method not allowed"),
+        _ => Response::error(404, "Not found"),
+    }
+}
+
+fn write_response(mut stream: &TcpStream, response: Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    let body = response.body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Serves the query API on the listener that holds the single-instance lock.
+/// Requests are handled one at a time.
+pub fn serve(listener: TcpListener, pool: ConnectionPool) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        // Don't let a client that stops sending hold up every other request.
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap_or(());
+        let response = match read_request(&stream) {
+            Ok(Ok(request)) => handle(&request, &pool),
+            Ok(Err(response)) => response,
+            Err(_) => continue,
+        };
+        if let Err(err) = write_response(&stream, response) {
+            println!("{}", err);
+        }
+    }
+}
@@ -4,7 +4,11 @@
 #![feature(ascii_ctype)]
 
 extern crate notify;
+extern crate num_traits;
+extern crate percent_encoding;
 extern crate seiri;
+#[macro_use]
+extern crate serde_json;
 extern crate threadpool;
 extern crate walkdir;
 
@@ -15,6 +19,7 @@ use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver};
 use std::thread;
 use std::time::Duration;
+mod http;
 mod utils;
 mod watcher;
 
@@ -23,6 +28,9 @@ use seiri::config::Config;
 use seiri::database;
 use seiri::database::Connection;
 use seiri::database::ConnectionPool;
+use seiri::events::{set_event_format, Event, EventFormat};
+use seiri::fingerprint;
+use seiri::journal::{Journal, OperationReason};
 use seiri::paths;
 use seiri::Error;
 use watcher::WatchStatus;
@@ -32,27 +40,49 @@ fn process(path: &Path, config: &Config, conn: &Connection, retry: bool) {
     match track {
         Ok(track) => match paths::ensure_music_folder(&config.music_folder) {
             Ok(library_path) => {
-                let track = paths::move_new_track(&track, &library_path.0, &library_path.1);
-                if let Ok(track) = track {
-                    database::add_track(&track, conn);
-                    eprintln!("TRACKADDED~{} – {}", track.artist, track.title);
+                let journal = Journal::new(conn, OperationReason::NewTrack);
+                let track = paths::move_new_track(
+                    &track,
+                    &library_path.0,
+                    &library_path.1,
+                    &config.naming_template,
+                    &journal,
+                );
+                match track {
+                    Ok(track) => {
+                        let content_hash = fingerprint::ensure_content_hash(&track, None);
+                        database::add_track(&track, content_hash.as_ref(), conn);
+                        Event::TrackAdded {
+                            artist: track.artist,
+                            title: track.title,
+                        }.emit();
+                    }
+                    Err(err @ Error::UnableToCopy(_, _)) => Event::TrackCopyError {
+                        message: err.to_string(),
+                    }.emit(),
+                    Err(err) => Event::TrackMoveError {
+                        message: err.to_string(),
+                    }.emit(),
                 }
             }
-            Err(_) => eprintln!("LIBRARYNOTFOUND~{}.", path.display()),
+            Err(_) => Event::LibraryNotFound {
+                path: path.display().to_string(),
+            }.emit(),
         },
         Err(err) => match err {
             Error::UnsupportedFile(file_name) => {
                 if !retry {
                     match paths::ensure_music_folder(&config.music_folder) {
                         Ok(library_path) => {
-                            paths::move_non_track(&file_name, &library_path.1).unwrap();
-                            eprintln!(
-                                "NONTRACK~{}",
-                                file_name
+                            let journal = Journal::new(conn, OperationReason::NonTrack);
+                            paths::move_non_track(&file_name, &library_path.1, &journal).unwrap();
+                            Event::NonTrack {
+                                name: file_name
                                     .file_name()
                                     .and_then(|s| Some(s.to_string_lossy()))
                                     .unwrap_or(Cow::Borrowed(""))
-                            )
+                                    .into_owned(),
+                            }.emit()
                         }
                         Err(_) => {
                             if retry {
@@ -60,7 +90,9 @@ fn process(path: &Path, config: &Config, conn: &Connection, retry: bool) {
                                 println!("Retrying...");
                                 process(path, config, conn, false)
                             } else {
-                                eprintln!("TRACKMOVEERROR~{}", file_name.display())
+                                Event::TrackMoveError {
+                                    message: file_name.display().to_string(),
+                                }.emit()
                             }
                         }
                     }
@@ -70,21 +102,21 @@ fn process(path: &Path, config: &Config, conn: &Connection, retry: bool) {
                     process(path, config, conn, false)
                 }
             }
-            Error::MissingRequiredTag(file_name, tag) => eprintln!(
-                "MISSINGTAG~Track {} is missing tag {}.",
-                Path::new(&file_name)
+            Error::MissingRequiredTag(file_name, tag) => Event::MissingTag {
+                name: Path::new(&file_name)
                     .file_name()
                     .and_then(|s| Some(s.to_string_lossy()))
-                    .unwrap_or(Cow::Borrowed("")),
-                tag
-            ),
+                    .unwrap_or(Cow::Borrowed(""))
+                    .into_owned(),
+                tag: tag.to_owned(),
+            }.emit(),
             _ => {
                 if retry {
                     thread::sleep(Duration::from_secs(2));
                     println!("Retrying...");
                     process(path, config, conn, false)
                 } else {
-                    eprintln!("TRACKERROR~")
+                    Event::TrackError.emit()
                 }
             }
         },
@@ -132,7 +164,7 @@ fn start_watcher_watchdog(wait_time: Duration) {
         loop {
             thread::park_timeout(wait_time);
             if let Err(_) = tx.send(WatchStatus::KeepAlive) {
-                eprintln!("WATCHERKEEPALIVEFAIL~Keep-alive failed. Watcher thread probably panicked. Restarting Watcher Thread...");
+                Event::WatcherKeepAliveFail.emit();
                 let (new_tx, rx) = channel();
                 tx = new_tx.clone();
                 _watch_thread = get_watcher_thread(rx).unwrap();
@@ -140,13 +172,13 @@ fn start_watcher_watchdog(wait_time: Duration) {
 
             let music_folder = paths::ensure_music_folder(&config.music_folder);
             if let Err(_) = music_folder {
-                eprintln!("WATCHERFOLDERACCESSLOST~{}", &config.music_folder);
+                Event::WatcherFolderAccessLost {
+                    folder: config.music_folder.to_owned(),
+                }.emit();
                 wait_for_watch_root_available(&config.music_folder);
                 let (new_tx, rx) = channel();
                 tx.send(WatchStatus::Exit).unwrap();
-                eprintln!(
-                    "WATCHERRESTART~Requested watcher thread exit. Restarting Watcher Thread..."
-                );
+                Event::WatcherRestart.emit();
                 tx = new_tx.clone();
                 _watch_thread = get_watcher_thread(rx).unwrap();
             }
@@ -162,7 +194,13 @@ fn ensure_port(port: u16) -> Result<TcpListener, io::Error> {
 }
 
 fn main() {
-    let _lock = ensure_port(9235).expect("Unable to acquire lock");
+    // Events are written in the tilde format unless JSON is asked for.
+    if std::env::args().any(|arg| arg == "--events=json") {
+        set_event_format(EventFormat::Json);
+    }
+
+    let lock = ensure_port(http::PORT).expect("Unable to acquire lock");
+    thread::spawn(move || http::serve(lock, database::get_connection_pool()));
 
     let wait_time = Duration::from_secs(5);
     start_watcher_watchdog(wait_time);
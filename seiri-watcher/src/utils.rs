@@ -1,16 +1,23 @@
 use std::io;
 use std::path::Path;
 use seiri::Bang;
-use seiri::database::query_tracks;
+use seiri::albums::query_albums;
+use seiri::lint::{lint_library, write_report};
+use std::fs::File;
+use seiri::database::{explain, query_tracks};
 use seiri::database::Connection;
+use seiri::sort::SortSpec;
 use seiri::paths::reconsider_track;
+use seiri::library::{rehome_library, rescan_library};
+use seiri::journal::{get_operations, rollback_batch, Journal, OperationReason};
 use seiri::config::get_config;
+use seiri::events::Event;
 
 pub fn wait_for_exit(conn: &Connection) {
     let stdin = io::stdin();
     println!("Type 'exit' to exit");
-    let folder = get_config().music_folder;
-    let library_path = Path::new(&folder);
+    let config = get_config();
+    let library_path = Path::new(&config.music_folder);
     let mut input = String::new();
     while let Ok(_) = stdin.read_line(&mut input) {
         if input.trim().eq_ignore_ascii_case("exit") {
@@ -21,32 +28,134 @@ pub fn wait_for_exit(conn: &Connection) {
                 Some(query_str) => query_str,
                 None => "",
             };
-            let track = query_tracks(Bang::FilePath(file_name.to_owned()), conn, None, None).unwrap();
+            let track = query_tracks(Bang::FilePath(file_name.to_owned()), conn, &config.naming_template, &SortSpec::default(), None, None).unwrap();
             match track.into_iter().next() {
                 Some(track) => {
-                    reconsider_track(&track, &library_path).unwrap();
+                    let journal = Journal::new(conn, OperationReason::Reconsider);
+                    reconsider_track(&track, &library_path, &config.naming_template, &journal).unwrap();
                 }
                 None => {
                     println!("Some Error")
                 }
             };
         }
+        if input.trim().eq_ignore_ascii_case("journal") {
+            match get_operations(conn, Some(20)) {
+                Ok(operations) => for operation in operations {
+                    println!("{:?}", operation)
+                },
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim().starts_with("rollback") {
+            let batch = input
+                .trim()
+                .splitn(2, " ")
+                .nth(1)
+                .and_then(|batch| batch.parse::<i64>().ok());
+            match batch {
+                Some(batch) => match rollback_batch(batch, &library_path, conn) {
+                    Ok(_) => println!("Rolled back batch {}", batch),
+                    Err(err) => println!("{}", err),
+                },
+                None => println!("Usage: rollback <batch>"),
+            }
+        }
+        if input.trim().eq_ignore_ascii_case("rescan") {
+            match rescan_library(&library_path, &config.naming_template, conn) {
+                Ok(summary) => {
+                    for err in &summary.errors {
+                        Event::RescanError {
+                            message: err.to_string(),
+                        }.emit();
+                    }
+                    Event::RescanComplete {
+                        summary: summary.to_string(),
+                    }.emit();
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        if input.trim().eq_ignore_ascii_case("rehome") {
+            match rehome_library(&library_path, &config.naming_template, conn) {
+                Ok(summary) => {
+                    for err in &summary.errors {
+                        Event::RehomeError {
+                            message: err.to_string(),
+                        }.emit();
+                    }
+                    Event::RehomeComplete {
+                        summary: summary.to_string(),
+                    }.emit();
+                }
+                Err(err) => println!("{:?}", err),
+            }
+        }
         if input.trim().starts_with("query") {
             let query_str: &str = match input.trim().splitn(2, " ").nth(1) {
                 Some(query_str) => query_str,
                 None => "",
             };
 
-            match Bang::new(query_str) {
+            match Bang::with_macros(query_str, &config.macros) {
                 Ok(bang) => {
                     println!("{:?}", bang);
                   //  println!("Compiles to... -------------");
-                    let tracks = query_tracks(bang, conn, None, None);
+                    let tracks = query_tracks(bang, conn, &config.naming_template, &SortSpec::default(), None, None);
                     println!("{:?}", tracks)
                 },
                 Err(err) => println!("{:?}", err),
             }
         }
+        if input.trim().starts_with("explain") {
+            let query_str: &str = match input.trim().splitn(2, " ").nth(1) {
+                Some(query_str) => query_str,
+                None => "",
+            };
+
+            match Bang::with_macros(query_str, &config.macros).and_then(|bang| {
+                Ok(explain(bang, conn, &SortSpec::default(), None, None)?)
+            }) {
+                Ok(explanation) => {
+                    println!("{}", explanation.query);
+                    for (name, value) in explanation.params {
+                        println!("{} = {:?}", name, value);
+                    }
+                    for detail in explanation.plan {
+                        println!("{}", detail);
+                    }
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
+        if input.trim().eq_ignore_ascii_case("incomplete") {
+            match query_albums(Bang::AlbumIsIncomplete(true), conn, &config.naming_template, &SortSpec::default(), None, None) {
+                Ok(albums) => for album in albums {
+                    println!("{} - {}", album.album_artists.join(";"), album.album);
+                    for problem in album.problems() {
+                        println!("    {}", problem);
+                    }
+                },
+                Err(err) => println!("{}", err),
+            }
+        }
+        if input.trim().starts_with("lint") {
+            // The report is written to a file if one is given.
+            let report_path = input.trim().splitn(2, " ").nth(1).map(|path| path.trim());
+            match lint_library(conn, &config.naming_template, &library_path) {
+                Ok(issues) => {
+                    let written = match report_path {
+                        Some(report_path) => File::create(report_path)
+                            .and_then(|mut file| write_report(&issues, &mut file)),
+                        None => write_report(&issues, &mut io::stdout()),
+                    };
+                    if let Err(err) = written {
+                        println!("{}", err);
+                    }
+                }
+                Err(err) => println!("{}", err),
+            }
+        }
         input.clear();
         continue;
     }
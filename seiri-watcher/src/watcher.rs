@@ -3,6 +3,7 @@ use notify::DebouncedEvent;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use seiri::config::Config;
 use seiri::database::{Connection, ConnectionPool};
+use seiri::events::Event;
 use seiri::paths::is_in_hidden_path;
 use std::fs::OpenOptions;
 use std::path::{Path, PathBuf};
@@ -124,7 +125,9 @@ where
                         }
                     }
                 }
-                Err(e) => eprintln!("WATCHERROR~{:?}", e),
+                Err(e) => Event::WatchError {
+                    message: format!("{:?}", e),
+                }.emit(),
             },
             keepalive = quit_rx.recv() => match keepalive {
                 Ok(WatchStatus::KeepAlive) => (),